@@ -0,0 +1,113 @@
+use std::fmt;
+
+use ansi_term::Color;
+
+/// How serious a diagnostic is
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    fn name(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+
+    fn color(&self) -> Color {
+        match self {
+            Severity::Error => Color::Red,
+            Severity::Warning => Color::Yellow,
+        }
+    }
+}
+
+/// A range of characters on a single line of a source, lines and columns starting at 1
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+    pub length: usize,
+}
+
+impl Span {
+    pub fn new(line: usize, column: usize, length: usize) -> Self {
+        Self { line, column, length }
+    }
+}
+
+/// A problem found while assembling a source, pointing at where it was found
+#[derive(Clone, PartialEq, Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub file: String,
+    pub span: Span,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    /// Creates an error diagnostic for the span of the file
+    pub fn error<T: ToString>(message: T, file: &str, span: Span) -> Self {
+        Self {
+            severity: Severity::Error,
+            message: message.to_string(),
+            file: file.to_string(),
+            span,
+            notes: Vec::new(),
+        }
+    }
+
+    /// Adds a note shown after the source snippet
+    pub fn with_note<T: ToString>(mut self, note: T) -> Self {
+        self.notes.push(note.to_string());
+        self
+    }
+
+    /// Renders the diagnostic with the line of the source it points at, underlining the span
+    /// with `^` characters. Without the source only the location is shown.
+    pub fn render(&self, source: Option<&str>, colored: bool) -> String {
+        let paint = |color: Color, text: &str| {
+            if colored { color.bold().paint(text).to_string() } else { text.to_string() }
+        };
+        let mut rendered = format!(
+            "{} {}\n",
+            paint(self.severity.color(), &format!("{}:", self.severity.name())),
+            self.message,
+        );
+        let line_number = self.span.line.to_string();
+        let gutter = " ".repeat(line_number.len());
+        rendered.push_str(&format!("{gutter}{} {}:{}:{}\n", paint(Color::Blue, "-->"), self.file, self.span.line, self.span.column));
+        let line = source.and_then(|source| source.lines().nth(self.span.line.saturating_sub(1)));
+        if let Some(line) = line {
+            let bar = paint(Color::Blue, "|");
+            // tabs are kept so the underline lines up with the source
+            let padding: String = line
+                .chars()
+                .take(self.span.column.saturating_sub(1))
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            let underline = "^".repeat(self.span.length.max(1));
+            rendered.push_str(&format!("{gutter} {bar}\n"));
+            rendered.push_str(&format!("{} {bar} {line}\n", paint(Color::Blue, &line_number)));
+            rendered.push_str(&format!("{gutter} {bar} {padding}{}\n", paint(self.severity.color(), &underline)));
+        }
+        for note in &self.notes {
+            rendered.push_str(&format!("{gutter} {} {note}\n", paint(Color::Blue, "= note:")));
+        }
+        rendered
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}: {}: {}", self.file, self.span.line, self.span.column, self.severity.name(), self.message)?;
+        for note in &self.notes {
+            write!(f, "\n  note: {note}")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,154 @@
+use std::fmt::Write;
+use std::time::UNIX_EPOCH;
+
+use crate::debug_info::DebugInfo;
+use crate::instructions::Instruction;
+use crate::meta::Metadata;
+use crate::value::MiValue;
+
+/// Formats the metadata of a binary as a header of `--` comments followed by its
+/// instructions, so the output can be assembled again
+pub fn disassemble_metadata(metadata: &Metadata) -> Result<String, String> {
+    let mut output = String::new();
+    let _ = writeln!(output, "-- package: {}", metadata.package);
+    if let Some(version) = &metadata.version {
+        let _ = writeln!(output, "-- version: {}", version);
+    }
+    if let Some(author) = &metadata.author {
+        let _ = writeln!(output, "-- author: {}", author);
+    }
+    if let Some(license) = &metadata.license {
+        let _ = writeln!(output, "-- license: {}", license);
+    }
+    if !metadata.description.is_empty() {
+        for line in metadata.description.lines() {
+            let _ = writeln!(output, "-- description: {}", line);
+        }
+    }
+    let _ = writeln!(output, "-- compiled version: {}", metadata.compiled_version);
+    if let Ok(timestamp) = metadata.timestamp.duration_since(UNIX_EPOCH) {
+        let _ = writeln!(output, "-- timestamp: {}", timestamp.as_secs());
+    }
+    let _ = writeln!(output, "-- instructions: {}", metadata.total_instructions);
+    let _ = writeln!(output, "-- debug info: {}", if metadata.debug_info.is_some() { "yes" } else { "no" });
+    output.push('\n');
+    output.push_str(&disassemble_with_debug_info(&metadata.instructions, metadata.debug_info.as_ref())?);
+    Ok(output)
+}
+
+/// Formats the instructions in the syntax accepted by the assembler, one per line,
+/// indenting the bodies of functions and classes
+pub fn disassemble(instructions: &[Instruction]) -> Result<String, String> {
+    disassemble_with_debug_info(instructions, None)
+}
+
+/// Formats the instructions like `disassemble`, naming the registers with the aliases of the
+/// debug info. An `.alias` directive is written before the instruction each alias starts at.
+pub fn disassemble_with_debug_info(instructions: &[Instruction], debug_info: Option<&DebugInfo>) -> Result<String, String> {
+    let mut output = String::new();
+    let mut depth = 0usize;
+    for (index, instruction) in instructions.iter().enumerate() {
+        if matches!(instruction, Instruction::EndFunction | Instruction::EndClass) {
+            depth = depth.saturating_sub(1);
+        }
+        let indent = "    ".repeat(depth);
+        let aliases = debug_info.map(|debug_info| debug_info.aliases.as_slice()).unwrap_or_default();
+        for alias in aliases.iter().filter(|alias| alias.start == index) {
+            let _ = writeln!(output, "{indent}.alias {} r{}", alias.name, alias.register);
+        }
+        let register = |reg: usize| match debug_info.and_then(|debug_info| debug_info.alias(index, reg)) {
+            Some(alias) => alias.to_string(),
+            None => format!("r{reg}"),
+        };
+        let line = format_named_instruction(instruction, &register).map_err(|err| format!("instruction {index}: {err}"))?;
+        let _ = writeln!(output, "{indent}{line}");
+        if matches!(instruction, Instruction::DefineFnLabel(..) | Instruction::DefineClass(_)) {
+            depth += 1;
+        }
+    }
+    Ok(output)
+}
+
+/// Formats a single instruction in the syntax accepted by the assembler
+pub fn format_instruction(instruction: &Instruction) -> Result<String, String> {
+    format_named_instruction(instruction, &|reg| format!("r{reg}"))
+}
+
+/// Formats a single instruction, writing its registers with the names given by the function
+fn format_named_instruction(instruction: &Instruction, register: &dyn Fn(usize) -> String) -> Result<String, String> {
+    let opcode = instruction.opcode();
+    let line = match instruction {
+        Instruction::Move(dst, value) => format!("{opcode} {} {}", register(*dst), format_value(value)?),
+        Instruction::MoveArgument(name, dst) => format!("{opcode} {} {}", format_string(name), register(*dst)),
+        Instruction::SetVariable(src, name) => format!("{opcode} {} {name}", register(*src)),
+        Instruction::MovFromVariable(name, dst) => format!("{opcode} {name} {}", register(*dst)),
+        Instruction::DefineLabel(label)
+        | Instruction::JumpUnconditional(label)
+        | Instruction::Call(label)
+        | Instruction::DefineClass(label) => format!("{opcode} {label}"),
+        Instruction::JumpConditional(reg, label) => format!("{opcode} {} {label}", register(*reg)),
+        Instruction::DefineFnLabel(name, args, returns) => {
+            let mut line = format!("{opcode} {name} {}", args.len());
+            for arg in args {
+                line.push(' ');
+                line.push_str(arg);
+            }
+            format!("{line} {}", returns.name())
+        }
+        Instruction::Try(label, name_reg, message_reg) => format!("{opcode} {label} {} {}", register(*name_reg), register(*message_reg)),
+        Instruction::ClassField(name, variant) => format!("{opcode} {name} {}", variant.name()),
+        Instruction::New(name, dst) => format!("{opcode} {name} {}", register(*dst)),
+        Instruction::GetField(obj, field, reg) | Instruction::SetField(obj, field, reg) => {
+            format!("{opcode} {} {field} {}", register(*obj), register(*reg))
+        }
+        Instruction::CallMethod(obj, method) => format!("{opcode} {} {method}", register(*obj)),
+        Instruction::Cast(src, variant, dst) => format!("{opcode} {} {} {}", register(*src), variant.name(), register(*dst)),
+        // the remaining instructions only take registers
+        _ => {
+            let mut line = opcode.to_string();
+            for reg in instruction.registers() {
+                let _ = write!(line, " {}", register(reg));
+            }
+            line
+        }
+    };
+    Ok(line)
+}
+
+/// Formats a value the way `move` expects it
+fn format_value(value: &MiValue) -> Result<String, String> {
+    match value {
+        MiValue::Int(int) => Ok(format!("int {int}")),
+        MiValue::Float(float) => Ok(format!("float {float:?}")),
+        MiValue::Str(string) => Ok(format!("string {}", format_string(string))),
+        MiValue::Bool(boolean) => Ok(format!("bool {boolean}")),
+        MiValue::None => Ok("None".to_string()),
+        MiValue::Array(array) => {
+            let elements = array.iter().map(format_value).collect::<Result<Vec<String>, String>>()?;
+            Ok(format!("array [{}]", elements.join(", ")))
+        }
+        MiValue::Map(_) | MiValue::Class(_) | MiValue::Function(_) => Err(format!(
+            "a value of type `{}` cannot be written as a literal",
+            value.variant().name()
+        )),
+    }
+}
+
+/// Formats a string literal, escaping the characters the tokenizer treats specially
+fn format_string(string: &str) -> String {
+    let mut literal = String::with_capacity(string.len() + 2);
+    literal.push('"');
+    for character in string.chars() {
+        match character {
+            '"' => literal.push_str("\\\""),
+            '\\' => literal.push_str("\\\\"),
+            '\n' => literal.push_str("\\n"),
+            '\r' => literal.push_str("\\r"),
+            '\t' => literal.push_str("\\t"),
+            '\0' => literal.push_str("\\0"),
+            _ => literal.push(character),
+        }
+    }
+    literal.push('"');
+    literal
+}
@@ -0,0 +1,296 @@
+use std::rc::Rc;
+
+use fxhash::FxHashMap;
+
+use super::diagnostic::{Diagnostic, Span};
+use super::tokens::{Token, TokenType};
+
+/// How many times macros can be expanded within other macros, to stop recursive macros
+const MAX_EXPANSION_DEPTH: usize = 64;
+
+/// A macro call that tokens were expanded from
+#[derive(Clone, Debug, PartialEq)]
+pub struct Expansion {
+    /// Name of the expanded macro
+    pub name: String,
+    /// Where the macro was called
+    pub call: Span,
+    /// The expansion the call itself comes from, for macros called by other macros
+    pub parent: Option<Rc<Expansion>>,
+}
+
+/// A `.macro name args ... .endmacro` definition
+struct Macro {
+    /// Where the name of the macro is in its definition
+    span: Span,
+    parameters: Vec<String>,
+    body: Vec<Token>,
+    /// The labels defined by the body, renamed on every expansion
+    labels: Vec<String>,
+}
+
+/// Removes the `.macro` definitions from the tokens and replaces every call to them with their body.
+///
+/// A macro is called by writing its name at the start of a line, followed on the same line by
+/// one token for each parameter. The parameters are replaced by these tokens in the body, and
+/// the labels defined by the body are renamed for each call so that they don't collide.
+pub fn expand(tokens: Vec<Token>, filename: &str) -> Result<Vec<Token>, Vec<Diagnostic>> {
+    let mut expander = Expander {
+        filename,
+        macros: FxHashMap::default(),
+        expansions: 0,
+        diagnostics: Vec::new(),
+    };
+    let tokens = expander.collect_definitions(tokens);
+    let mut expanded = Vec::with_capacity(tokens.len());
+    expander.expand_lines(lines(tokens), 0, &mut expanded);
+    if expander.diagnostics.is_empty() {
+        Ok(expanded)
+    } else {
+        Err(expander.diagnostics)
+    }
+}
+
+/// Adds to the diagnostic a note for every macro call the token was expanded from, innermost first.
+/// The same call repeated by a recursive macro is noted once.
+pub fn note_expansions(mut diagnostic: Diagnostic, token: &Token, filename: &str) -> Diagnostic {
+    let mut expansion = token.expansion.as_deref();
+    while let Some(current) = expansion {
+        let mut repeated = 1;
+        expansion = current.parent.as_deref();
+        while let Some(parent) = expansion.filter(|parent| parent.name == current.name && parent.call == current.call) {
+            repeated += 1;
+            expansion = parent.parent.as_deref();
+        }
+        let expansions = if repeated == 1 { "the expansion".to_string() } else { format!("{} nested expansions", repeated) };
+        diagnostic = diagnostic.with_note(format!(
+            "in {} of macro `{}` called at {}:{}:{}",
+            expansions, current.name, filename, current.call.line, current.call.column
+        ));
+    }
+    diagnostic
+}
+
+/// Groups tokens by the line they are on, keeping their order
+fn lines(tokens: Vec<Token>) -> Vec<Vec<Token>> {
+    let mut lines: Vec<Vec<Token>> = Vec::new();
+    for token in tokens {
+        match lines.last_mut() {
+            Some(line) if line[0].line == token.line => line.push(token),
+            _ => lines.push(vec![token]),
+        }
+    }
+    lines
+}
+
+fn span(token: &Token) -> Span {
+    Span::new(token.line, token.column, token.length)
+}
+
+struct Expander<'a> {
+    filename: &'a str,
+    macros: FxHashMap<String, Macro>,
+    /// How many macro calls were expanded, to give unique names to their labels
+    expansions: usize,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Expander<'_> {
+    fn error<T: ToString>(&self, token: &Token, message: T) -> Diagnostic {
+        note_expansions(Diagnostic::error(message, self.filename, span(token)), token, self.filename)
+    }
+
+    /// Stores the macro definitions, returning the tokens outside of them
+    fn collect_definitions(&mut self, tokens: Vec<Token>) -> Vec<Token> {
+        let mut remaining = Vec::with_capacity(tokens.len());
+        let mut tokens = tokens.into_iter().peekable();
+        while let Some(token) = tokens.next() {
+            match &token.token_type {
+                TokenType::Directive(directive) if directive == "macro" => {
+                    let Some(name) = tokens.next_if(|name| name.line == token.line) else {
+                        let diagnostic = self.error(&token, "Expected the name of the macro after `.macro`");
+                        self.diagnostics.push(diagnostic);
+                        continue;
+                    };
+                    let mut parameters = Vec::new();
+                    while let Some(parameter) = tokens.next_if(|parameter| parameter.line == token.line) {
+                        match &parameter.token_type {
+                            TokenType::Identifier(parameter_name) if parameters.contains(parameter_name) => {
+                                let diagnostic = self.error(&parameter, format!("The parameter `{}` is declared twice", parameter_name));
+                                self.diagnostics.push(diagnostic);
+                            }
+                            TokenType::Identifier(parameter_name) => parameters.push(parameter_name.clone()),
+                            _ => {
+                                let diagnostic = self.error(&parameter, "Expected the name of a parameter")
+                                    .with_note("parameters are identifiers, which cannot be keywords, types or registers");
+                                self.diagnostics.push(diagnostic);
+                            }
+                        }
+                    }
+                    let mut body = Vec::new();
+                    let mut closed = false;
+                    for body_token in tokens.by_ref() {
+                        match &body_token.token_type {
+                            TokenType::Directive(directive) if directive == "endmacro" => {
+                                closed = true;
+                                break;
+                            }
+                            TokenType::Directive(directive) if directive == "macro" => {
+                                let diagnostic = self.error(&body_token, "Macros cannot be defined inside other macros")
+                                    .with_note(format!("the enclosing macro starts at {}:{}:{}", self.filename, token.line, token.column));
+                                self.diagnostics.push(diagnostic);
+                            }
+                            _ => body.push(body_token),
+                        }
+                    }
+                    if !closed {
+                        let diagnostic = self.error(&token, "Unclosed macro definition")
+                            .with_note("add a `.endmacro` after the body of the macro");
+                        self.diagnostics.push(diagnostic);
+                    }
+                    let TokenType::Identifier(macro_name) = &name.token_type else {
+                        let diagnostic = self.error(&name, "Expected the name of the macro after `.macro`")
+                            .with_note("macro names are identifiers, which cannot be keywords, types or registers");
+                        self.diagnostics.push(diagnostic);
+                        continue;
+                    };
+                    if let Some(previous) = self.macros.get(macro_name) {
+                        let diagnostic = self.error(&name, format!("The macro `{}` is defined twice", macro_name))
+                            .with_note(format!("it was first defined at {}:{}:{}", self.filename, previous.span.line, previous.span.column));
+                        self.diagnostics.push(diagnostic);
+                        continue;
+                    }
+                    let labels = body
+                        .windows(2)
+                        .filter_map(|pair| match (&pair[0].token_type, &pair[1].token_type) {
+                            (TokenType::Keyword(keyword), TokenType::Identifier(label)) if keyword == "definelabel" => Some(label.clone()),
+                            _ => None,
+                        })
+                        .collect();
+                    self.macros.insert(macro_name.clone(), Macro { span: span(&name), parameters, body, labels });
+                }
+                TokenType::Directive(directive) if directive == "endmacro" => {
+                    let diagnostic = self.error(&token, "Found `.endmacro` outside of a macro definition");
+                    self.diagnostics.push(diagnostic);
+                }
+                _ => remaining.push(token),
+            }
+        }
+        remaining
+    }
+
+    /// Appends the tokens of the lines to the output, expanding the macro calls among them
+    fn expand_lines(&mut self, lines: Vec<Vec<Token>>, depth: usize, output: &mut Vec<Token>) {
+        for mut line in lines {
+            let name = match &line[0].token_type {
+                TokenType::Identifier(name) if self.macros.contains_key(name) => name.clone(),
+                _ => {
+                    output.append(&mut line);
+                    continue;
+                }
+            };
+            let call = line.remove(0);
+            let arguments = line;
+            self.expansions += 1;
+            let number = self.expansions;
+            let definition = &self.macros[&name];
+            let defined_at = format!("the macro is defined at {}:{}:{}", self.filename, definition.span.line, definition.span.column);
+            if depth >= MAX_EXPANSION_DEPTH {
+                let diagnostic = self.error(&call, format!("The macro `{}` is expanded more than {} times within itself", name, MAX_EXPANSION_DEPTH))
+                    .with_note(defined_at)
+                    .with_note("a macro cannot call itself, directly or through other macros");
+                self.diagnostics.push(diagnostic);
+                continue;
+            }
+            if arguments.len() != definition.parameters.len() {
+                let plural = |count: usize, noun: &str| if count == 1 { format!("1 {}", noun) } else { format!("{} {}s", count, noun) };
+                let diagnostic = self.error(&call, format!(
+                    "The macro `{}` takes {}, but {} given",
+                    name,
+                    plural(definition.parameters.len(), "argument"),
+                    if arguments.len() == 1 { "1 was".to_string() } else { format!("{} were", arguments.len()) },
+                ))
+                    .with_note(defined_at)
+                    .with_note("every argument is a single token, written on the same line as the macro name");
+                self.diagnostics.push(diagnostic);
+                continue;
+            }
+
+            let expansion = Rc::new(Expansion {
+                name: name.clone(),
+                call: span(&call),
+                // calls within macros are tagged with the expansion they come from
+                parent: call.expansion.clone(),
+            });
+            let body = definition
+                .body
+                .iter()
+                .map(|token| {
+                    if let TokenType::Identifier(identifier) = &token.token_type {
+                        if let Some(index) = definition.parameters.iter().position(|parameter| parameter == identifier) {
+                            return arguments[index].clone();
+                        }
+                        if definition.labels.contains(identifier) {
+                            return Token {
+                                token_type: TokenType::Identifier(format!("{}.{}.{}", name, number, identifier)),
+                                expansion: Some(expansion.clone()),
+                                ..token.clone()
+                            };
+                        }
+                    }
+                    Token { expansion: Some(expansion.clone()), ..token.clone() }
+                })
+                .collect::<Vec<Token>>();
+            // the body is split on its own lines, as the arguments come from the line of the call
+            let mut body_lines: Vec<Vec<Token>> = Vec::new();
+            let mut previous_line = None;
+            for (token, original) in body.into_iter().zip(&definition.body) {
+                match body_lines.last_mut() {
+                    Some(line) if previous_line == Some(original.line) => line.push(token),
+                    _ => body_lines.push(vec![token]),
+                }
+                previous_line = Some(original.line);
+            }
+            self.expand_lines(body_lines, depth + 1, output);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembly::parser::Parser;
+    use crate::assembly::tokens::tokenize;
+    use crate::instructions::Instruction;
+
+    fn expand_source(source: &str) -> Result<Vec<Token>, Vec<Diagnostic>> {
+        expand(tokenize(source, "test.masm")?, "test.masm")
+    }
+
+    #[test]
+    fn labels_are_renamed_for_every_expansion() {
+        let source = ".macro wait reg\ndefinelabel loop\njumpc reg loop\n.endmacro\nwait r0\nwait r1\n";
+        let tokens = expand_source(source).unwrap();
+        let instructions = Parser::new(tokens, "test.masm").parse().unwrap();
+        assert_eq!(instructions, vec![
+            Instruction::DefineLabel("wait.1.loop".to_string()),
+            Instruction::JumpConditional(0, "wait.1.loop".to_string()),
+            Instruction::DefineLabel("wait.2.loop".to_string()),
+            Instruction::JumpConditional(1, "wait.2.loop".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn recursive_expansions_are_capped() {
+        let diagnostics = expand_source(".macro again\nagain\n.endmacro\nagain\n").unwrap_err();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, format!("The macro `again` is expanded more than {MAX_EXPANSION_DEPTH} times within itself"));
+        // the repeated calls are noted once instead of once per expansion
+        assert_eq!(diagnostics[0].notes, vec![
+            format!("in {} nested expansions of macro `again` called at test.masm:2:1", MAX_EXPANSION_DEPTH - 1),
+            "in the expansion of macro `again` called at test.masm:4:1".to_string(),
+            "the macro is defined at test.masm:1:8".to_string(),
+            "a macro cannot call itself, directly or through other macros".to_string(),
+        ]);
+    }
+}
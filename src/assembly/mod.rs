@@ -1,2 +1,394 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use fxhash::{FxHashMap, FxHashSet};
+
+use crate::debug_info::{DebugInfo, RegisterAlias, SourceFile, SourceLocation};
+use crate::instructions::Instruction;
+use diagnostic::{Diagnostic, Span};
+
+pub mod diagnostic;
 pub mod tokens;
-pub mod parser;
\ No newline at end of file
+pub mod macros;
+pub mod parser;
+pub mod disasm;
+
+/// Tokenizes and parses a `.masm` source into its instructions, along with the files it imports
+pub fn assemble(source: &str, filename: &str) -> Result<Vec<Instruction>, Vec<Diagnostic>> {
+    Assembler::new().assemble(source, filename).map(|(instructions, _)| instructions)
+}
+
+/// Assembles a program made of a main file and the files it imports with `import "path.masm"`.
+///
+/// Imported files are read relative to the file importing them and inserted where they are
+/// first imported. The functions, labels and classes they define are prefixed with the name
+/// of the file they are defined in, so `sqrt` defined in `math.masm` is called as `math.sqrt`.
+/// The names of imported files must be identifiers. Two imported files cannot have the same name,
+/// and a file cannot have the name of a class, as `name.function` would then be ambiguous.
+pub struct Assembler {
+    /// Every file read, with its source, the main one being the first
+    files: Vec<SourceFile>,
+    /// Canonical paths of the files imported so far
+    imported: FxHashSet<PathBuf>,
+    /// Canonical paths and names of the files being imported, to detect import cycles
+    importing: Vec<(PathBuf, String)>,
+    /// The imported files, by module name
+    modules: FxHashMap<String, Module>,
+    instructions: Vec<Instruction>,
+    locations: Vec<SourceLocation>,
+    aliases: Vec<RegisterAlias>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Assembler {
+    pub fn new() -> Self {
+        Self {
+            files: Vec::new(),
+            imported: FxHashSet::default(),
+            importing: Vec::new(),
+            modules: FxHashMap::default(),
+            instructions: Vec::new(),
+            locations: Vec::new(),
+            aliases: Vec::new(),
+            diagnostics: Vec::new(),
+        }
+    }
+
+    /// Assembles the main source of a program, whose imports are read from the disk.
+    /// The returned debug info holds the sources of every file.
+    pub fn assemble(&mut self, source: &str, filename: &str) -> Result<(Vec<Instruction>, DebugInfo), Vec<Diagnostic>> {
+        let path = Path::new(filename);
+        let canonical = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        self.imported.insert(canonical.clone());
+        self.importing.push((canonical, filename.to_string()));
+        self.assemble_file(source.to_string(), filename.to_string(), None);
+        self.importing.pop();
+        self.check_class_names();
+
+        if !self.diagnostics.is_empty() {
+            return Err(std::mem::take(&mut self.diagnostics));
+        }
+        let debug_info = DebugInfo {
+            files: self.files.clone(),
+            locations: std::mem::take(&mut self.locations),
+            aliases: std::mem::take(&mut self.aliases),
+        };
+        Ok((std::mem::take(&mut self.instructions), debug_info))
+    }
+
+    /// Returns the source of a file read while assembling
+    pub fn source(&self, filename: &str) -> Option<&str> {
+        self.files.iter().find(|file| file.name == filename).and_then(|file| file.source.as_deref())
+    }
+
+    /// Renders the diagnostics one after the other with the sources of their files,
+    /// as `Diagnostic::render` does
+    pub fn render_diagnostics(&self, diagnostics: &[Diagnostic], colored: bool) -> String {
+        diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.render(self.source(&diagnostic.file), colored))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    /// Tokenizes and parses a file, inserting the files it imports before the instructions
+    /// following each `import`. The names defined by modules are prefixed with the module name.
+    fn assemble_file(&mut self, source: String, filename: String, module: Option<String>) {
+        let file = self.files.len();
+        self.files.push(SourceFile { name: filename.clone(), source: Some(source) });
+        let source = self.files[file].source.as_deref().unwrap_or_default();
+
+        let tokens = match tokens::tokenize(source, &filename) {
+            Ok(tokens) => tokens,
+            Err(diagnostics) => {
+                self.diagnostics.extend(diagnostics);
+                return;
+            }
+        };
+        let tokens = match macros::expand(tokens, &filename) {
+            Ok(tokens) => tokens,
+            Err(diagnostics) => {
+                self.diagnostics.extend(diagnostics);
+                return;
+            }
+        };
+        let mut parser = parser::Parser::new(tokens, &filename);
+        let mut instructions = match parser.parse() {
+            Ok(instructions) => instructions,
+            Err(diagnostics) => {
+                self.diagnostics.extend(diagnostics);
+                return;
+            }
+        };
+        if let Some(module) = &module {
+            namespace(&mut instructions, module);
+        }
+
+        let mut imports = parser.imports().iter().peekable();
+        // the index every instruction of the file ends up at, imported files being inserted between them
+        let mut indices = Vec::with_capacity(instructions.len());
+        for (index, instruction) in instructions.into_iter().enumerate() {
+            while let Some(import) = imports.next_if(|import| import.position == index) {
+                self.import(&filename, &import.path, import.span);
+            }
+            let (line, column) = parser.locations()[index];
+            indices.push(self.instructions.len());
+            self.instructions.push(instruction);
+            self.locations.push(SourceLocation { file, line, column });
+        }
+        for import in imports {
+            self.import(&filename, &import.path, import.span);
+        }
+
+        // aliases don't apply to the imported instructions, so their ranges are split around them
+        for alias in parser.aliases() {
+            let mut range: Option<(usize, usize)> = None;
+            for &index in &indices[alias.start..alias.end] {
+                range = match range {
+                    Some((start, end)) if end == index => Some((start, index + 1)),
+                    Some((start, end)) => {
+                        self.aliases.push(RegisterAlias { start, end, ..alias.clone() });
+                        Some((index, index + 1))
+                    }
+                    None => Some((index, index + 1)),
+                };
+            }
+            if let Some((start, end)) = range {
+                self.aliases.push(RegisterAlias { start, end, ..alias.clone() });
+            }
+        }
+        self.aliases.sort_by_key(|alias| alias.start);
+    }
+
+    /// Assembles the file imported by another one, unless it was already imported
+    fn import(&mut self, importer: &str, path: &str, span: Span) {
+        let filename = match Path::new(importer).parent() {
+            Some(directory) => directory.join(path).to_string_lossy().into_owned(),
+            None => path.to_string(),
+        };
+        let canonical = match fs::canonicalize(&filename) {
+            Ok(canonical) => canonical,
+            Err(err) => {
+                self.diagnostics.push(Diagnostic::error(format!("Cannot find the imported file `{filename}`: {err}"), importer, span));
+                return;
+            }
+        };
+        if let Some(start) = self.importing.iter().position(|(importing, _)| importing == &canonical) {
+            let cycle = self.importing[start..]
+                .iter()
+                .map(|(_, name)| name.as_str())
+                .chain([filename.as_str()])
+                .collect::<Vec<&str>>()
+                .join(" -> ");
+            self.diagnostics.push(
+                Diagnostic::error(format!("The import of `{filename}` creates an import cycle"), importer, span)
+                    .with_note(format!("the cycle is {cycle}"))
+            );
+            return;
+        }
+        if !self.imported.insert(canonical.clone()) {
+            return;
+        }
+        let source = match fs::read_to_string(&canonical) {
+            Ok(source) => source,
+            Err(err) => {
+                self.diagnostics.push(Diagnostic::error(format!("Cannot read the imported file `{filename}`: {err}"), importer, span));
+                return;
+            }
+        };
+        let module = Path::new(path)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| path.to_string());
+        if !is_module_name(&module) {
+            self.diagnostics.push(
+                Diagnostic::error(format!("The module `{module}` of `{filename}` is not a valid identifier"), importer, span)
+                    .with_note(format!("modules are named after their file, so `{module}.name` could not be written: rename the file"))
+                    .with_note("module names start with a letter or `_`, followed by letters, digits or `_`")
+            );
+            return;
+        }
+        if let Some(previous) = self.modules.get(&module) {
+            self.diagnostics.push(
+                Diagnostic::error(format!("The module `{module}` of `{filename}` has the same name as the module of `{}`", previous.filename), importer, span)
+                    .with_note(format!("`{}` is imported at {}:{}:{}", previous.filename, previous.importer, previous.span.line, previous.span.column))
+                    .with_note(format!("modules are named after their file, so `{module}.name` would refer to both: rename one of the files"))
+            );
+            return;
+        }
+        self.modules.insert(module.clone(), Module { filename: filename.clone(), importer: importer.to_string(), span });
+        self.importing.push((canonical, filename.clone()));
+        self.assemble_file(source, filename, Some(module));
+        self.importing.pop();
+    }
+
+    /// Reports the classes defined outside of modules that have the name of a module, as
+    /// their methods would be called like the functions of the module
+    fn check_class_names(&mut self) {
+        for (instruction, location) in self.instructions.iter().zip(&self.locations) {
+            let Instruction::DefineClass(name) = instruction else { continue };
+            let Some(module) = self.modules.get(name) else { continue };
+            self.diagnostics.push(
+                Diagnostic::error(format!("The module `{name}` of `{}` has the same name as the class `{name}`", module.filename), &module.importer, module.span)
+                    .with_note(format!("the class is defined at {}:{}:{}", self.files[location.file].name, location.line, location.column))
+                    .with_note(format!("`{name}.name` would refer both to a function of the module and to a method of the class"))
+            );
+        }
+    }
+}
+
+/// Returns whether the name can prefix the names of a module, that is, whether it is an
+/// identifier the tokenizer reads whole. Dots are excluded as they separate the module name.
+fn is_module_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some('a'..='z' | 'A'..='Z' | '_')) && chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// A file imported as a module
+struct Module {
+    /// Name of the file, relative to the main one
+    filename: String,
+    /// The file that first imported it, and where
+    importer: String,
+    span: Span,
+}
+
+impl Default for Assembler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Prefixes the functions, labels and classes defined by the instructions of a module with
+/// the module name, along with the references to them. Methods keep their names, as they
+/// are looked up through their class.
+fn namespace(instructions: &mut [Instruction], module: &str) {
+    let mut defined = FxHashSet::default();
+    let mut in_class = false;
+    for instruction in instructions.iter() {
+        match instruction {
+            Instruction::DefineClass(name) => {
+                in_class = true;
+                defined.insert(name.clone());
+            }
+            Instruction::EndClass => in_class = false,
+            Instruction::DefineFnLabel(name, ..) if !in_class => {
+                defined.insert(name.clone());
+            }
+            Instruction::DefineLabel(name) => {
+                defined.insert(name.clone());
+            }
+            _ => {}
+        }
+    }
+
+    let qualify = |name: &mut String| {
+        if defined.contains(name) {
+            *name = format!("{module}.{name}");
+        }
+    };
+    let mut in_class = false;
+    for instruction in instructions.iter_mut() {
+        match instruction {
+            Instruction::DefineClass(name) => {
+                in_class = true;
+                qualify(name);
+            }
+            Instruction::EndClass => in_class = false,
+            Instruction::DefineFnLabel(name, ..) if !in_class => qualify(name),
+            Instruction::DefineLabel(name)
+            | Instruction::JumpUnconditional(name)
+            | Instruction::JumpConditional(_, name)
+            | Instruction::Call(name)
+            | Instruction::Try(name, ..)
+            | Instruction::New(name, _) => qualify(name),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::value::MiType;
+
+    /// Writes the files to a new directory and assembles the first one
+    fn assemble_files(name: &str, files: &[(&str, &str)]) -> Result<Vec<Instruction>, Vec<Diagnostic>> {
+        let directory = std::env::temp_dir().join(format!("mirage-{}-{name}", std::process::id()));
+        for (path, source) in files {
+            let path = directory.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, source).unwrap();
+        }
+        let main = directory.join(files[0].0);
+        let result = assemble(files[0].1, &main.to_string_lossy());
+        fs::remove_dir_all(&directory).unwrap();
+        result
+    }
+
+    const MATH: &str = "definefnlabel one 0 int\nmove r15 int 1\nreturn\nendfunction\n";
+
+    #[test]
+    fn imported_names_are_prefixed_with_the_module() {
+        let instructions = assemble_files("prefix", &[
+            ("main.masm", "import \"lib/math.masm\"\ncall math.one\n"),
+            ("lib/math.masm", MATH),
+        ]).unwrap();
+        assert_eq!(instructions[0], Instruction::DefineFnLabel("math.one".to_string(), Vec::new(), MiType::Int));
+        assert_eq!(instructions[4], Instruction::Call("math.one".to_string()));
+    }
+
+    #[test]
+    fn import_cycles_are_reported() {
+        let diagnostics = assemble_files("cycle", &[
+            ("main.masm", "import \"a.masm\"\n"),
+            ("a.masm", "import \"b.masm\"\n"),
+            ("b.masm", "import \"a.masm\"\n"),
+        ]).unwrap_err();
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].message.starts_with("The import of `"), "{:?}", diagnostics[0]);
+        assert!(diagnostics[0].message.ends_with("a.masm` creates an import cycle"), "{:?}", diagnostics[0]);
+        assert!(diagnostics[0].file.ends_with("b.masm"));
+        let cycle = diagnostics[0].notes[0].split(" -> ").collect::<Vec<&str>>();
+        assert!(cycle.len() == 3 && cycle[0].ends_with("a.masm") && cycle[1].ends_with("b.masm") && cycle[2].ends_with("a.masm"), "{cycle:?}");
+    }
+
+    #[test]
+    fn module_names_must_be_identifiers() {
+        for (name, module) in [("my-lib.masm", "my-lib"), ("2d.masm", "2d"), ("v1.2.masm", "v1.2")] {
+            let diagnostics = assemble_files("invalid-name", &[
+                ("main.masm", &format!("move r0 int 1\nimport \"{name}\"\n")),
+                (name, MATH),
+            ]).unwrap_err();
+            assert_eq!(diagnostics.len(), 1, "{diagnostics:?}");
+            assert!(diagnostics[0].message.starts_with(&format!("The module `{module}` of `")), "{:?}", diagnostics[0]);
+            assert!(diagnostics[0].message.ends_with("is not a valid identifier"), "{:?}", diagnostics[0]);
+            assert!(diagnostics[0].file.ends_with("main.masm"));
+            assert_eq!((diagnostics[0].span.line, diagnostics[0].span.column), (2, 1));
+        }
+        assert!(assemble_files("valid-name", &[("main.masm", "import \"_vec2.masm\"\n"), ("_vec2.masm", MATH)]).is_ok());
+    }
+
+    #[test]
+    fn files_with_the_same_name_are_rejected() {
+        let diagnostics = assemble_files("same-name", &[
+            ("main.masm", "import \"a/util.masm\"\nimport \"b/util.masm\"\n"),
+            ("a/util.masm", MATH),
+            ("b/util.masm", MATH),
+        ]).unwrap_err();
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].message.starts_with("The module `util` of `"), "{:?}", diagnostics[0]);
+        assert_eq!(diagnostics[0].span.line, 2);
+    }
+
+    #[test]
+    fn modules_cannot_have_the_name_of_a_class() {
+        let diagnostics = assemble_files("class-name", &[
+            ("main.masm", "import \"one.masm\"\ndefineclass one\nendclass\n"),
+            ("one.masm", MATH),
+        ]).unwrap_err();
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].message.ends_with("has the same name as the class `one`"), "{:?}", diagnostics[0]);
+        assert_eq!(diagnostics[0].span.line, 1);
+    }
+}
@@ -1,458 +1,846 @@
+use fxhash::FxHashMap;
+
+use crate::debug_info::RegisterAlias;
 use crate::instructions::Instruction;
 use crate::value::IntoValue;
 use crate::value::MiType;
 use crate::value::MiValue;
+use super::diagnostic::{Diagnostic, Span};
+use super::macros::note_expansions;
 use super::tokens::{Token, TokenType};
 
 pub struct Parser {
     tokens: Vec<Token>,
+    /// Name of the file the tokens come from, for diagnostics
+    filename: String,
     pc: usize,
+    /// Line and column of the keyword of every parsed instruction
+    locations: Vec<(usize, usize)>,
+    /// The `import` directives, in order
+    imports: Vec<Import>,
+    /// The values defined with `.const NAME <value>`, with where they are defined
+    constants: FxHashMap<String, (MiValue, Span)>,
+    /// The register aliases in scope, by name, as indices in `register_aliases`
+    aliases: FxHashMap<String, usize>,
+    /// The aliases of the enclosing scopes, put aside while parsing a function body
+    outer_aliases: Vec<FxHashMap<String, usize>>,
+    /// Every alias defined with `.alias NAME rN`, with the instructions it applies to
+    register_aliases: Vec<RegisterAlias>,
+}
+
+/// An `import "path.masm"` directive
+#[derive(Clone, PartialEq, Debug)]
+pub struct Import {
+    /// The path of the imported file, relative to the importing one
+    pub path: String,
+    /// The number of instructions parsed before the directive
+    pub position: usize,
+    /// Where the directive is in the importing file
+    pub span: Span,
 }
 
 impl Parser {
-    pub fn new(tokens: Vec<Token>) -> Parser {
+    pub fn new(tokens: Vec<Token>, filename: &str) -> Parser {
         Parser {
             tokens,
+            filename: filename.to_string(),
             pc: 0,
+            locations: Vec::new(),
+            imports: Vec::new(),
+            constants: FxHashMap::default(),
+            aliases: FxHashMap::default(),
+            outer_aliases: Vec::new(),
+            register_aliases: Vec::new(),
         }
     }
 
-    pub fn parse(&mut self) -> Result<Vec<Instruction>, String> {
+    /// Parses the tokens into instructions. When an instruction is invalid, parsing resumes
+    /// at the next keyword so that all the errors of the source are reported.
+    pub fn parse(&mut self) -> Result<Vec<Instruction>, Vec<Diagnostic>> {
         let mut instructions = vec![];
+        let mut diagnostics = vec![];
         while let Some(ctoken) = self.tokens.get(self.pc) {
-            self.pc += 1;
-            match &ctoken.token_type {
-                TokenType::Keyword(kw) => match kw.as_str() {
-                    "move" => {
-                        let addr1 = self.parse_reg()?;
-                        
-                        let val = self.parse_value()?;
-                        instructions.push(Instruction::Move(addr1, val))
-                    }
-                    "movebetween" => {
-                        let addr1 = self.parse_reg()?;
-                        
-                        let addr2 = self.parse_reg()?;
-                        instructions.push(Instruction::MoveBetween(addr1, addr2))
-                    }
-                    "moveargument" => {
-                        let arg = self.parse_string()?;
-                        
-                        let addr = self.parse_reg()?;
-                        instructions.push(Instruction::MoveArgument(arg, addr))
-                    }
-                    "moveasargument" => {
-                        let reg = self.parse_reg()?;
-                        instructions.push(Instruction::MoveAsArgument(reg))
-                    }
-                    "add" => {
-                        let op1 = self.parse_reg()?;
-                        
-                        let op2 = self.parse_reg()?;
-                        
-                        let dst = self.parse_reg()?;
-                        instructions.push(Instruction::Add(op1, op2, dst))
-                    }
-                    "sub" => {
-                        let op1 = self.parse_reg()?;
-                        
-                        let op2 = self.parse_reg()?;
-                        
-                        let dst = self.parse_reg()?;
-                        instructions.push(Instruction::Sub(op1, op2, dst))
-                    }
-                    "mul" => {
-                        let op1 = self.parse_reg()?;
-                        
-                        let op2 = self.parse_reg()?;
-                        
-                        let dst = self.parse_reg()?;
-                        instructions.push(Instruction::Mul(op1, op2, dst))
-                    }
-                    "div" => {
-                        let op1 = self.parse_reg()?;
-                        
-                        let op2 = self.parse_reg()?;
-                        
-                        let dst = self.parse_reg()?;
-                        instructions.push(Instruction::Div(op1, op2, dst))
-                    }
-                    "rem" => {
-                        let op1 = self.parse_reg()?;
-                        
-                        let op2 = self.parse_reg()?;
-                        
-                        let dst = self.parse_reg()?;
-                        instructions.push(Instruction::Rem(op1, op2, dst))
-                    }
-                    "pow" => {
-                        let op1 = self.parse_reg()?;
-                        
-                        let op2 = self.parse_reg()?;
-                        
-                        let dst = self.parse_reg()?;
-                        instructions.push(Instruction::Pow(op1, op2, dst))
-                    }
-                    "or" => {
-                        let op1 = self.parse_reg()?;
-                        
-                        let op2 = self.parse_reg()?;
-                        
-                        let dst = self.parse_reg()?;
-                        instructions.push(Instruction::Or(op1, op2, dst))
-                    }
-                    "xor" => {
-                        let op1 = self.parse_reg()?;
-                        
-                        let op2 = self.parse_reg()?;
-                        
-                        let dst = self.parse_reg()?;
-                        instructions.push(Instruction::Xor(op1, op2, dst))
-                    }
-                    "and" => {
-                        let op1 = self.parse_reg()?;
-                        
-                        let op2 = self.parse_reg()?;
-                        
-                        let dst = self.parse_reg()?;
-                        instructions.push(Instruction::And(op1, op2, dst))
-                    }
-                    "not" => {
-                        let op1 = self.parse_reg()?;
-                        
-                        let dst = self.parse_reg()?;
-                        instructions.push(Instruction::Not(op1, dst))
-                    }
-                    "lt" => {
-                        let op1 = self.parse_reg()?;
-                        
-                        let op2 = self.parse_reg()?;
-                        
-                        let dst = self.parse_reg()?;
-                        instructions.push(Instruction::Lt(op1, op2, dst))
-                    }
-                    "le" => {
-                        let op1 = self.parse_reg()?;
-                        
-                        let op2 = self.parse_reg()?;
-                        
-                        let dst = self.parse_reg()?;
-                        instructions.push(Instruction::Le(op1, op2, dst))
-                    }
-                    "gt" => {
-                        let op1 = self.parse_reg()?;
-                        
-                        let op2 = self.parse_reg()?;
-                        
-                        let dst = self.parse_reg()?;
-                        instructions.push(Instruction::Gt(op1, op2, dst))
-                    }
-                    "ge" => {
-                        let op1 = self.parse_reg()?;
-                        
-                        let op2 = self.parse_reg()?;
-                        
-                        let dst = self.parse_reg()?;
-                        instructions.push(Instruction::Ge(op1, op2, dst))
-                    }
-                    "return" => {
-                        instructions.push(Instruction::Return)
-                    }
-                    "setvariable" => {
-                        let reg = self.parse_reg()?;
-                        
-                        let var = self.parse_identifier()?;
-                        instructions.push(Instruction::SetVariable(reg, var));
-                    }
-                    "movfromvariable" => {
-                        let ident = self.parse_identifier()?;
-                        
-                        let reg = self.parse_reg()?;
-                        instructions.push(Instruction::MovFromVariable(ident, reg))
-                    }
-                    "throwfrom" => {
-                        let addr1 = self.parse_reg()?;
-                        
-                        let addr2 = self.parse_reg()?;
-                        instructions.push(Instruction::ThrowFrom(addr1, addr2))
-                    }
-                    "eq" => {
-                        let op1 = self.parse_reg()?;
-                        
-                        let op2 = self.parse_reg()?;
-                        
-                        let dst = self.parse_reg()?;
-                        instructions.push(Instruction::Eq(op1, op2, dst))
-                    }
-                    "ne" => {
-                        let op1 = self.parse_reg()?;
-                        
-                        let op2 = self.parse_reg()?;
-                        
-                        let dst = self.parse_reg()?;
-                        instructions.push(Instruction::Ne(op1, op2, dst))
-                    }
-                    "definelabel" => {
-                        let label = self.parse_identifier()?;
-                        instructions.push(Instruction::DefineLabel(label))
-                    }
-                    "jumpunc" => {
-                        let label = self.parse_identifier()?;
-                        instructions.push(Instruction::JumpUnconditional(label))
-                    }
-                    "jumpc" => {
-                        let reg = self.parse_reg()?;
-                        
-                        let label = self.parse_identifier()?;
-                        instructions.push(Instruction::JumpConditional(reg, label))
-                    }
-                    "call" => {
-                        let name = self.parse_identifier()?;
-                        instructions.push(Instruction::Call(name))
+            let start = self.pc;
+            let location = (ctoken.line, ctoken.column);
+            if ctoken.token_type == TokenType::Keyword("import".to_string()) {
+                let span = Span::new(ctoken.line, ctoken.column, ctoken.length);
+                self.pc += 1;
+                match self.parse_string() {
+                    Ok(path) => self.imports.push(Import { path, position: instructions.len(), span }),
+                    Err(diagnostic) => {
+                        diagnostics.push(diagnostic);
+                        self.recover(start);
                     }
-                    "definefnlabel" => {
-                        let mut args: Vec<String> = vec![];
-                        let name = self.parse_identifier()?;
-
-                        let len = self.parse_int()? as usize;
-                        if len != 0 {
-                            for _ in 0..len - 1 {
-                                args.push(self.parse_identifier()?);
+                }
+                continue;
+            }
+            if ctoken.token_type == TokenType::Directive("const".to_string()) {
+                self.pc += 1;
+                if let Err(diagnostic) = self.parse_constant() {
+                    diagnostics.push(diagnostic);
+                    self.recover(start);
+                }
+                continue;
+            }
+            if ctoken.token_type == TokenType::Directive("alias".to_string()) {
+                self.pc += 1;
+                if let Err(diagnostic) = self.parse_alias(instructions.len()) {
+                    diagnostics.push(diagnostic);
+                    self.recover(start);
+                }
+                continue;
+            }
+            match self.parse_instruction() {
+                Ok(instruction) => {
+                    // aliases are scoped to the function they are defined in
+                    match instruction {
+                        Instruction::DefineFnLabel(..) => {
+                            self.close_aliases(instructions.len());
+                            self.outer_aliases.push(std::mem::take(&mut self.aliases));
+                        }
+                        Instruction::EndFunction => {
+                            self.close_aliases(instructions.len());
+                            if let Some(outer) = self.outer_aliases.pop() {
+                                self.aliases = outer;
+                                self.open_aliases(instructions.len() + 1);
                             }
                         }
-                        let returns = self.parse_type()?;
-                        instructions.push(Instruction::DefineFnLabel(name, args, returns))
-                    }
-                    "endfunction" => {
-                        instructions.push(Instruction::EndFunction)
-                    }
-                    "stdoutwrite" => {
-                        let reg = self.parse_reg()?;
-                        instructions.push(Instruction::StdoutWrite(reg))
-                    }
-                    "stdoutwritedebugged" => {
-                        let reg = self.parse_reg()?;
-                        instructions.push(Instruction::StdoutWriteDebugged(reg))
-                    }
-                    "stdoutflush" => {
-                        instructions.push(Instruction::StdoutFlush)
-                    }
-                    "stderrwrite" => {
-                        let reg = self.parse_reg()?;
-                        instructions.push(Instruction::StderrWrite(reg))
-                    }
-                    "stderrwritedebugged" => {
-                        let reg = self.parse_reg()?;
-                        instructions.push(Instruction::StderrWriteDebugged(reg))
-                    }
-                    "stderrflush" => {
-                        instructions.push(Instruction::StderrFlush)
-                    }
-                    "bufferedstdinread" => {
-                        let reg = self.parse_reg()?;
-                        instructions.push(Instruction::BufferedStdinRead(reg))
+                        _ => {}
                     }
-                    _ => return Err(format!("{}:{}->{}: Invalid keyword '{}'", ctoken.line, ctoken.column, ctoken.length + ctoken.column, kw)),
-                },
-                _ => {
-                    return Err(format!(
-                        "{}:{}->{}: Invalid position for token {:?}",
-                        ctoken.line, ctoken.column, ctoken.length + ctoken.column, ctoken.token_type
-                    ))
+                    instructions.push(instruction);
+                    self.locations.push(location);
                 }
+                Err(diagnostic) => {
+                    diagnostics.push(diagnostic);
+                    self.recover(start);
+                }
+            }
+        }
+        self.close_aliases(instructions.len());
+        if diagnostics.is_empty() {
+            Ok(instructions)
+        } else {
+            Err(diagnostics)
+        }
+    }
+
+    /// Skips the tokens of the invalid instruction starting at the specified token,
+    /// up to the keyword or directive of the next instruction
+    fn recover(&mut self, start: usize) {
+        self.pc = start + 1;
+        while let Some(token) = self.tokens.get(self.pc) {
+            if let TokenType::Keyword(_) | TokenType::Directive(_) = token.token_type {
+                break;
             }
+            self.pc += 1;
         }
-        Ok(instructions)
     }
 
-    pub fn expect_kind(&mut self, kind: TokenType) -> Result<(), String> {
+    fn parse_instruction(&mut self) -> Result<Instruction, Diagnostic> {
+        let ctoken = self.tokens[self.pc].clone();
         self.pc += 1;
+        match &ctoken.token_type {
+            TokenType::Keyword(kw) => match kw.as_str() {
+                "move" => {
+                    let addr1 = self.parse_reg()?;
+                    
+                    let val = self.parse_value()?;
+                    Ok(Instruction::Move(addr1, val))
+                }
+                "movebetween" => {
+                    let addr1 = self.parse_reg()?;
+                    
+                    let addr2 = self.parse_reg()?;
+                    Ok(Instruction::MoveBetween(addr1, addr2))
+                }
+                "moveargument" => {
+                    let arg = self.parse_string()?;
+                    
+                    let addr = self.parse_reg()?;
+                    Ok(Instruction::MoveArgument(arg, addr))
+                }
+                "moveasargument" => {
+                    let reg = self.parse_reg()?;
+                    Ok(Instruction::MoveAsArgument(reg))
+                }
+                "add" => {
+                    let op1 = self.parse_reg()?;
+                    
+                    let op2 = self.parse_reg()?;
+                    
+                    let dst = self.parse_reg()?;
+                    Ok(Instruction::Add(op1, op2, dst))
+                }
+                "sub" => {
+                    let op1 = self.parse_reg()?;
+                    
+                    let op2 = self.parse_reg()?;
+                    
+                    let dst = self.parse_reg()?;
+                    Ok(Instruction::Sub(op1, op2, dst))
+                }
+                "mul" => {
+                    let op1 = self.parse_reg()?;
+                    
+                    let op2 = self.parse_reg()?;
+                    
+                    let dst = self.parse_reg()?;
+                    Ok(Instruction::Mul(op1, op2, dst))
+                }
+                "div" => {
+                    let op1 = self.parse_reg()?;
+                    
+                    let op2 = self.parse_reg()?;
+                    
+                    let dst = self.parse_reg()?;
+                    Ok(Instruction::Div(op1, op2, dst))
+                }
+                "rem" => {
+                    let op1 = self.parse_reg()?;
+                    
+                    let op2 = self.parse_reg()?;
+                    
+                    let dst = self.parse_reg()?;
+                    Ok(Instruction::Rem(op1, op2, dst))
+                }
+                "pow" => {
+                    let op1 = self.parse_reg()?;
+                    
+                    let op2 = self.parse_reg()?;
+                    
+                    let dst = self.parse_reg()?;
+                    Ok(Instruction::Pow(op1, op2, dst))
+                }
+                "or" => {
+                    let op1 = self.parse_reg()?;
+                    
+                    let op2 = self.parse_reg()?;
+                    
+                    let dst = self.parse_reg()?;
+                    Ok(Instruction::Or(op1, op2, dst))
+                }
+                "xor" => {
+                    let op1 = self.parse_reg()?;
+                    
+                    let op2 = self.parse_reg()?;
+                    
+                    let dst = self.parse_reg()?;
+                    Ok(Instruction::Xor(op1, op2, dst))
+                }
+                "and" => {
+                    let op1 = self.parse_reg()?;
+                    
+                    let op2 = self.parse_reg()?;
+                    
+                    let dst = self.parse_reg()?;
+                    Ok(Instruction::And(op1, op2, dst))
+                }
+                "not" => {
+                    let op1 = self.parse_reg()?;
+                    
+                    let dst = self.parse_reg()?;
+                    Ok(Instruction::Not(op1, dst))
+                }
+                "lt" => {
+                    let op1 = self.parse_reg()?;
+                    
+                    let op2 = self.parse_reg()?;
+                    
+                    let dst = self.parse_reg()?;
+                    Ok(Instruction::Lt(op1, op2, dst))
+                }
+                "le" => {
+                    let op1 = self.parse_reg()?;
+                    
+                    let op2 = self.parse_reg()?;
+                    
+                    let dst = self.parse_reg()?;
+                    Ok(Instruction::Le(op1, op2, dst))
+                }
+                "gt" => {
+                    let op1 = self.parse_reg()?;
+                    
+                    let op2 = self.parse_reg()?;
+                    
+                    let dst = self.parse_reg()?;
+                    Ok(Instruction::Gt(op1, op2, dst))
+                }
+                "ge" => {
+                    let op1 = self.parse_reg()?;
+                    
+                    let op2 = self.parse_reg()?;
+                    
+                    let dst = self.parse_reg()?;
+                    Ok(Instruction::Ge(op1, op2, dst))
+                }
+                "return" => {
+                    Ok(Instruction::Return)
+                }
+                "setvariable" => {
+                    let reg = self.parse_reg()?;
+                    
+                    let var = self.parse_identifier()?;
+                    Ok(Instruction::SetVariable(reg, var))
+                }
+                "movfromvariable" => {
+                    let ident = self.parse_identifier()?;
+                    
+                    let reg = self.parse_reg()?;
+                    Ok(Instruction::MovFromVariable(ident, reg))
+                }
+                "throwfrom" => {
+                    let addr1 = self.parse_reg()?;
+                    
+                    let addr2 = self.parse_reg()?;
+                    Ok(Instruction::ThrowFrom(addr1, addr2))
+                }
+                "eq" => {
+                    let op1 = self.parse_reg()?;
+                    
+                    let op2 = self.parse_reg()?;
+                    
+                    let dst = self.parse_reg()?;
+                    Ok(Instruction::Eq(op1, op2, dst))
+                }
+                "ne" => {
+                    let op1 = self.parse_reg()?;
+                    
+                    let op2 = self.parse_reg()?;
+                    
+                    let dst = self.parse_reg()?;
+                    Ok(Instruction::Ne(op1, op2, dst))
+                }
+                "definelabel" => {
+                    let label = self.parse_identifier()?;
+                    Ok(Instruction::DefineLabel(label))
+                }
+                "jumpunc" => {
+                    let label = self.parse_identifier()?;
+                    Ok(Instruction::JumpUnconditional(label))
+                }
+                "jumpc" => {
+                    let reg = self.parse_reg()?;
+                    
+                    let label = self.parse_identifier()?;
+                    Ok(Instruction::JumpConditional(reg, label))
+                }
+                "call" => {
+                    let name = self.parse_identifier()?;
+                    Ok(Instruction::Call(name))
+                }
+                "definefnlabel" => {
+                    let mut args: Vec<String> = vec![];
+                    let name = self.parse_identifier()?;
+
+                    let len = self.parse_int()? as usize;
+                    for _ in 0..len {
+                        args.push(self.parse_identifier()?);
+                    }
+                    let returns = self.parse_type()?;
+                    Ok(Instruction::DefineFnLabel(name, args, returns))
+                }
+                "endfunction" => {
+                    Ok(Instruction::EndFunction)
+                }
+                "stdoutwrite" => {
+                    let reg = self.parse_reg()?;
+                    Ok(Instruction::StdoutWrite(reg))
+                }
+                "stdoutwritedebugged" => {
+                    let reg = self.parse_reg()?;
+                    Ok(Instruction::StdoutWriteDebugged(reg))
+                }
+                "stdoutflush" => {
+                    Ok(Instruction::StdoutFlush)
+                }
+                "stderrwrite" => {
+                    let reg = self.parse_reg()?;
+                    Ok(Instruction::StderrWrite(reg))
+                }
+                "stderrwritedebugged" => {
+                    let reg = self.parse_reg()?;
+                    Ok(Instruction::StderrWriteDebugged(reg))
+                }
+                "stderrflush" => {
+                    Ok(Instruction::StderrFlush)
+                }
+                "bufferedstdinread" => {
+                    let reg = self.parse_reg()?;
+                    Ok(Instruction::BufferedStdinRead(reg))
+                }
+                "try" => {
+                    let label = self.parse_identifier()?;
+
+                    let name_reg = self.parse_reg()?;
+
+                    let message_reg = self.parse_reg()?;
+                    Ok(Instruction::Try(label, name_reg, message_reg))
+                }
+                "endtry" => {
+                    Ok(Instruction::EndTry)
+                }
+                "arraynew" => {
+                    let dst = self.parse_reg()?;
+                    Ok(Instruction::ArrayNew(dst))
+                }
+                "arraypush" => {
+                    let array = self.parse_reg()?;
+
+                    let value = self.parse_reg()?;
+                    Ok(Instruction::ArrayPush(array, value))
+                }
+                "arraypop" => {
+                    let array = self.parse_reg()?;
+
+                    let dst = self.parse_reg()?;
+                    Ok(Instruction::ArrayPop(array, dst))
+                }
+                "arrayget" => {
+                    let array = self.parse_reg()?;
+
+                    let index = self.parse_reg()?;
+
+                    let dst = self.parse_reg()?;
+                    Ok(Instruction::ArrayGet(array, index, dst))
+                }
+                "arrayset" => {
+                    let array = self.parse_reg()?;
+
+                    let index = self.parse_reg()?;
+
+                    let value = self.parse_reg()?;
+                    Ok(Instruction::ArraySet(array, index, value))
+                }
+                "arraylen" => {
+                    let array = self.parse_reg()?;
+
+                    let dst = self.parse_reg()?;
+                    Ok(Instruction::ArrayLen(array, dst))
+                }
+                "arrayslice" => {
+                    let array = self.parse_reg()?;
+
+                    let start = self.parse_reg()?;
+
+                    let end = self.parse_reg()?;
+
+                    let dst = self.parse_reg()?;
+                    Ok(Instruction::ArraySlice(array, start, end, dst))
+                }
+                "mapnew" => {
+                    let dst = self.parse_reg()?;
+                    Ok(Instruction::MapNew(dst))
+                }
+                "mapinsert" => {
+                    let map = self.parse_reg()?;
+
+                    let key = self.parse_reg()?;
+
+                    let value = self.parse_reg()?;
+                    Ok(Instruction::MapInsert(map, key, value))
+                }
+                "mapget" => {
+                    let map = self.parse_reg()?;
+
+                    let key = self.parse_reg()?;
+
+                    let dst = self.parse_reg()?;
+                    Ok(Instruction::MapGet(map, key, dst))
+                }
+                "mapremove" => {
+                    let map = self.parse_reg()?;
+
+                    let key = self.parse_reg()?;
+
+                    let dst = self.parse_reg()?;
+                    Ok(Instruction::MapRemove(map, key, dst))
+                }
+                "mapcontains" => {
+                    let map = self.parse_reg()?;
+
+                    let key = self.parse_reg()?;
+
+                    let dst = self.parse_reg()?;
+                    Ok(Instruction::MapContains(map, key, dst))
+                }
+                "mapkeys" => {
+                    let map = self.parse_reg()?;
+
+                    let dst = self.parse_reg()?;
+                    Ok(Instruction::MapKeys(map, dst))
+                }
+                "maplen" => {
+                    let map = self.parse_reg()?;
+
+                    let dst = self.parse_reg()?;
+                    Ok(Instruction::MapLen(map, dst))
+                }
+                "defineclass" => {
+                    let name = self.parse_identifier()?;
+                    Ok(Instruction::DefineClass(name))
+                }
+                "field" => {
+                    let name = self.parse_identifier()?;
+
+                    let variant = self.parse_type()?;
+                    Ok(Instruction::ClassField(name, variant))
+                }
+                "endclass" => {
+                    Ok(Instruction::EndClass)
+                }
+                "new" => {
+                    let name = self.parse_identifier()?;
+
+                    let dst = self.parse_reg()?;
+                    Ok(Instruction::New(name, dst))
+                }
+                "getfield" => {
+                    let obj = self.parse_reg()?;
+
+                    let field = self.parse_identifier()?;
+
+                    let dst = self.parse_reg()?;
+                    Ok(Instruction::GetField(obj, field, dst))
+                }
+                "setfield" => {
+                    let obj = self.parse_reg()?;
+
+                    let field = self.parse_identifier()?;
+
+                    let src = self.parse_reg()?;
+                    Ok(Instruction::SetField(obj, field, src))
+                }
+                "callmethod" => {
+                    let obj = self.parse_reg()?;
+
+                    let method = self.parse_identifier()?;
+                    Ok(Instruction::CallMethod(obj, method))
+                }
+                "cast" => {
+                    let src = self.parse_reg()?;
+
+                    let variant = self.parse_type()?;
+
+                    let dst = self.parse_reg()?;
+                    Ok(Instruction::Cast(src, variant, dst))
+                }
+                "typeof" => {
+                    let src = self.parse_reg()?;
+
+                    let dst = self.parse_reg()?;
+                    Ok(Instruction::TypeOf(src, dst))
+                }
+                _ => Err(self.error(&ctoken, format!("Invalid keyword '{}'", kw))),
+            },
+            _ => Err(self.error(&ctoken, format!("Expected an instruction, found {}", describe(&ctoken.token_type)))),
+        }
+    }
+
+    /// Returns the line and column of the keyword of every parsed instruction, by instruction index
+    pub fn locations(&self) -> &[(usize, usize)] {
+        &self.locations
+    }
+
+    /// Returns the `import` directives of the tokens, in order
+    pub fn imports(&self) -> &[Import] {
+        &self.imports
+    }
+
+    /// Returns the register aliases, with the range of instruction indices they apply to
+    pub fn aliases(&self) -> &[RegisterAlias] {
+        &self.register_aliases
+    }
+
+    /// Creates an error pointing at the token, noting the macro calls it was expanded from
+    fn error<T: ToString>(&self, token: &Token, message: T) -> Diagnostic {
+        let diagnostic = Diagnostic::error(message, &self.filename, Span::new(token.line, token.column, token.length));
+        note_expansions(diagnostic, token, &self.filename)
+    }
+
+    /// Parses a `.const NAME <value>` definition
+    fn parse_constant(&mut self) -> Result<(), Diagnostic> {
+        let name_token = self.next_token("the name of the constant")?;
+        let TokenType::Identifier(name) = &name_token.token_type else {
+            return Err(self.unexpected(&name_token, "the name of the constant"));
+        };
+        if let Some((_, span)) = self.constants.get(name) {
+            return Err(self.error(&name_token, format!("The constant `{}` is defined twice", name))
+                .with_note(format!("it was first defined at {}:{}:{}", self.filename, span.line, span.column)));
+        }
+        let value = self.parse_value()?;
+        let span = Span::new(name_token.line, name_token.column, name_token.length);
+        self.constants.insert(name.clone(), (value, span));
+        Ok(())
+    }
+
+    /// Returns the value of the constant named by an identifier token
+    fn constant(&self, token: &Token, name: &str) -> Result<MiValue, Diagnostic> {
+        match self.constants.get(name) {
+            Some((value, _)) => Ok(value.clone()),
+            None => match self.aliases.get(name) {
+                Some(&alias) => Err(self.error(token, format!("Expected a value, found the register alias `{}`", name))
+                    .with_note(format!("`{}` is an alias of the register `r{}`", name, self.register_aliases[alias].register))),
+                None => Err(self.error(token, format!("Unknown constant `{}`", name))
+                    .with_note("constants are defined before being used, in the sense of `.const NAME int 1`")),
+            },
+        }
+    }
+
+    /// Parses a `.alias NAME rN` definition, which applies from the instruction at the specified
+    /// index to the end of the enclosing function. Defining an alias again replaces it.
+    fn parse_alias(&mut self, position: usize) -> Result<(), Diagnostic> {
+        let name_token = self.next_token("the name of the alias")?;
+        let TokenType::Identifier(name) = &name_token.token_type else {
+            return Err(self.unexpected(&name_token, "the name of the alias")
+                .with_note("aliases are identifiers, which cannot be keywords, types or registers"));
+        };
+        let register = self.parse_reg()?;
+        if let Some(&previous) = self.aliases.get(name) {
+            self.register_aliases[previous].end = position;
+        }
+        self.aliases.insert(name.clone(), self.register_aliases.len());
+        self.register_aliases.push(RegisterAlias { name: name.clone(), register, start: position, end: position });
+        Ok(())
+    }
+
+    /// Ends the aliases in scope before the instruction at the specified index
+    fn close_aliases(&mut self, end: usize) {
+        for &alias in self.aliases.values() {
+            self.register_aliases[alias].end = end;
+        }
+    }
+
+    /// Starts the aliases in scope again from the instruction at the specified index
+    fn open_aliases(&mut self, start: usize) {
+        for alias in self.aliases.values_mut() {
+            let reopened = RegisterAlias { start, end: start, ..self.register_aliases[*alias].clone() };
+            *alias = self.register_aliases.len();
+            self.register_aliases.push(reopened);
+        }
+    }
+
+    /// Creates an error for a token that is not the expected one
+    fn unexpected(&self, token: &Token, expected: &str) -> Diagnostic {
+        self.error(token, format!("Expected {}, found {}", expected, describe(&token.token_type)))
+    }
+
+    /// Returns the next token, failing at the end of the tokens
+    fn next_token(&mut self, expected: &str) -> Result<Token, Diagnostic> {
         match self.tokens.get(self.pc) {
             Some(token) => {
-                if &token.token_type != &kind {
-                    return Err(format!("Expected token `{:?}`, found `{:?}`", kind, &token.token_type))
-                } else {
-                    Ok(())
-                }
+                self.pc += 1;
+                Ok(token.clone())
             }
             None => {
-                return Err(format!("Expected token `{:?}`, found `EOF`", kind))
+                let span = match self.tokens.last() {
+                    Some(last) => Span::new(last.line, last.column + last.length, 1),
+                    None => Span::new(1, 1, 1),
+                };
+                Err(Diagnostic::error(format!("Expected {}, found the end of the file", expected), &self.filename, span))
             }
         }
     }
 
-    fn parse_identifier(&mut self) -> Result<String, String> {
-        if let Some(ctoken) = self.tokens.get(self.pc) {
-            self.pc += 1;
-            match &ctoken.token_type {
-                TokenType::Identifier(name) => {
-                    Ok(name.clone())
-                },
-                _ => Err(format!(
-                    "{}:{}->{}: Expected an identifier, found {:?}",
-                    ctoken.line, ctoken.column, ctoken.length + ctoken.column, ctoken.token_type, 
-                )),
-            }
-        } else {
-            let tok = self.tokens.get(self.pc - 1).unwrap();
-            Err(format!("{}:{}->{}: Unexpected end of tokens", tok.line, tok.column, tok.length + tok.column))
+    pub fn expect_kind(&mut self, kind: TokenType) -> Result<(), Diagnostic> {
+        let expected = describe(&kind);
+        let token = self.next_token(&expected)?;
+        if token.token_type != kind {
+            return Err(self.unexpected(&token, &expected));
         }
+        Ok(())
     }
 
-    fn parse_value(&mut self) -> Result<MiValue, String> {
-        if let Some(ctoken) = self.tokens.get(self.pc) {
-            self.pc += 1;
-            match &ctoken.token_type {
-                TokenType::Type(kw) => match kw.as_str() {
-                    "None" => Ok(MiValue::new(vec![], MiType::None)),
-                    "int" => {
-                        let value = self.parse_int()?;
-                        Ok(value.into_value())
-                    },
-                    "float" => {
-                        let value = self.parse_float()?;
-                        Ok(value.into_value())
-                    },
-                    "string" => {
-                        let value = self.parse_string()?;
-                        Ok(value.into_value())
-                    },
-                    "bool" => {
-                        let value = self.parse_bool()?;
-                        Ok(value.into_value())
-                    },
-                    _ => Err(format!("{}:{}: Invalid value keyword '{}'", ctoken.line, ctoken.column, kw)),
-                },
-                _ => Err(format!(
-                    "{}:{}->{}: Expected a value keyword, found {:?}",
-                    ctoken.line, ctoken.column, ctoken.length + ctoken.column, ctoken.token_type
-                )),
-            }
-        } else {
-            let tok = self.tokens.get(self.pc - 1).unwrap();
-            Err(format!("{}:{}->{}: Unexpected end of tokens", tok.line, tok.column, tok.column + tok.length))
+    fn parse_identifier(&mut self) -> Result<String, Diagnostic> {
+        let token = self.next_token("an identifier")?;
+        match token.token_type {
+            TokenType::Identifier(name) => Ok(name),
+            _ => Err(self.unexpected(&token, "an identifier")),
         }
     }
 
-    fn parse_int(&mut self) -> Result<i32, String> {
-        if let Some(ctoken) = self.tokens.get(self.pc) {
-            self.pc += 1;
-            match &ctoken.token_type {
-                TokenType::Int(i) => {
-                    Ok(*i)
-                }
-                _ => {
-                    return Err(format!("{}:{}->{}: Unexpected token: {:?} expected token int", ctoken.line, ctoken.column, ctoken.column + ctoken.length, ctoken.token_type))
-                }
-            }
-        } else {
-            let tok = self.tokens.get(self.pc - 1).unwrap();
-            return Err(format!("{}:{}->{}: Unexpected end of tokens", tok.line, tok.column, tok.column + tok.length))
+    fn parse_value(&mut self) -> Result<MiValue, Diagnostic> {
+        let token = self.next_token("a value")?;
+        match &token.token_type {
+            TokenType::Type(kw) => match kw.as_str() {
+                "None" => Ok(MiValue::None),
+                "int" => Ok(self.parse_int()?.into_value()),
+                "float" => Ok(self.parse_float()?.into_value()),
+                "string" => Ok(self.parse_string()?.into_value()),
+                "bool" => Ok(self.parse_bool()?.into_value()),
+                "array" => Ok(self.parse_array()?.into_value()),
+                _ => Err(self.error(&token, format!("Values of type `{}` cannot be written as literals", kw))
+                    .with_note("literals are of type `int`, `float`, `string`, `bool`, `array` or `None`")),
+            },
+            TokenType::Identifier(name) => self.constant(&token, name),
+            _ => Err(self.unexpected(&token, "a value")
+                .with_note("values are written with their type first, in the sense of `int 1`")),
         }
     }
 
-    fn parse_reg(&mut self) -> Result<usize, String> {
-        if let Some(ctoken) = self.tokens.get(self.pc) {
+    /// Parses the elements of an array value in the sense of `[int 1, string "two"]`
+    fn parse_array(&mut self) -> Result<Vec<MiValue>, Diagnostic> {
+        let mut elements = vec![];
+        self.expect_kind(TokenType::LeftBracket)?;
+        if let Some(Token { token_type: TokenType::RightBracket, .. }) = self.tokens.get(self.pc) {
             self.pc += 1;
-            match &ctoken.token_type {
-                TokenType::Register(i) => {
-                    Ok(*i)
-                }
-                _ => {
-                    return Err(format!("{}:{}->{}: Unexpected token: {:?} expected token register", ctoken.line, ctoken.column, ctoken.column + ctoken.length, ctoken.token_type))
-                }
+            return Ok(elements);
+        }
+        loop {
+            elements.push(self.parse_value()?);
+            let token = self.next_token("`,` or `]`")?;
+            match token.token_type {
+                TokenType::Comma => {}
+                TokenType::RightBracket => return Ok(elements),
+                _ => return Err(self.unexpected(&token, "`,` or `]` in array value")),
             }
-        } else {
-            let tok = self.tokens.get(self.pc - 1).unwrap();
-            return Err(format!("{}:{}->{}: Unexpected end of tokens", tok.line, tok.column, tok.column + tok.length))
         }
     }
 
-    fn parse_float(&mut self) -> Result<f64, String> {
-        if let Some(ctoken) = self.tokens.get(self.pc) {
-            self.pc += 1;
-            match &ctoken.token_type {
-                TokenType::Float(i) => {
-                    Ok(*i)
-                }
-                _ => {
-                    return Err(format!("{}:{}->{}: Unexpected token: {:?} expected token float", ctoken.line, ctoken.column, ctoken.column + ctoken.length, ctoken.token_type))
-                }
-            }
-        } else {
-            let tok = self.tokens.get(self.pc - 1).unwrap();
-            return Err(format!("{}:{}->{}: Unexpected end of tokens", tok.line, tok.column, tok.column + tok.length))
+    fn parse_int(&mut self) -> Result<i64, Diagnostic> {
+        let token = self.next_token("an int")?;
+        match &token.token_type {
+            TokenType::Int(int) => Ok(*int),
+            TokenType::Identifier(name) => match self.constant(&token, name)? {
+                MiValue::Int(int) => Ok(int),
+                _ => Err(self.error(&token, format!("Expected an int, but the constant `{}` is not an int", name))),
+            },
+            _ => Err(self.unexpected(&token, "an int")),
         }
     }
 
-    fn parse_string(&mut self) -> Result<String, String> {
-        if let Some(ctoken) = self.tokens.get(self.pc) {
-            self.pc += 1;
-            match &ctoken.token_type {
-                TokenType::String(s) => {
-                    Ok(s.clone())
-                }
-                _ => {
-                    return Err(format!("{}:{}->{}: Unexpected token: {:?} expected token string", ctoken.line, ctoken.column, ctoken.column + ctoken.length, ctoken.token_type))
-                }
-            }
-        } else {
-            let tok = self.tokens.get(self.pc - 1).unwrap();
-            return Err(format!("{}:{}->{}: Unexpected end of tokens", tok.line, tok.column, tok.column + tok.length))
+    fn parse_reg(&mut self) -> Result<usize, Diagnostic> {
+        let token = self.next_token("a register")?;
+        match &token.token_type {
+            TokenType::Register(reg) => Ok(*reg),
+            TokenType::Identifier(name) => match self.aliases.get(name) {
+                Some(&alias) => Ok(self.register_aliases[alias].register),
+                None => Err(self.error(&token, format!("Unknown register alias `{}`", name))
+                    .with_note("aliases are defined with `.alias NAME rN`, in the function they are used in")),
+            },
+            _ => Err(self.unexpected(&token, "a register")),
         }
     }
 
-    fn parse_bool(&mut self) -> Result<bool, String> {
-        if let Some(ctoken) = self.tokens.get(self.pc) {
-            self.pc += 1;
-            match &ctoken.token_type {
-                TokenType::Boolean(b) => {
-                    Ok(*b)
-                }
-                _ => {
-                    return Err(format!("{}:{}->{}: Unexpected token: {:?} expected token bool", ctoken.line, ctoken.column, ctoken.column + ctoken.length, ctoken.token_type))
-                }
-            }
-        } else {
-            let tok = self.tokens.get(self.pc - 1).unwrap();
-            return Err(format!("{}:{}->{}: Unexpected end of tokens", tok.line, tok.column, tok.column + tok.length))
+    fn parse_float(&mut self) -> Result<f64, Diagnostic> {
+        let token = self.next_token("a float")?;
+        match token.token_type {
+            TokenType::Float(float) => Ok(float),
+            _ => Err(self.unexpected(&token, "a float")),
         }
     }
 
-    
-    fn parse_type(&mut self) -> Result<MiType, String> {
-        if let Some(ctoken) = self.tokens.get(self.pc) {
-            self.pc += 1;
-            match &ctoken.token_type {
-                TokenType::Type(ttype) => {
-                    match ttype.as_str() {
-                        "None" => {
-                            Ok(MiType::None)
-                        }
-                        "int" => {
-                            Ok(MiType::Int)
-                        }
-                        "float" => {
-                            Ok(MiType::Float)
-                        }
-                        "string" => {
-                            Ok(MiType::String)
-                        }
-                        "boolean" => {
-                            Ok(MiType::Bool)
-                        }
-                        _ => {
-                            return Err(format!("{}:{}->{}: Unrecognized type '{}'", ctoken.line, ctoken.column, ctoken.column + ctoken.length, ttype));
-                        }
-                    }
-                }
-                _ => {
-                    return Err(format!("{}:{}->{}: Expected Type token, found: '{:?}'", ctoken.line, ctoken.column, ctoken.column + ctoken.length, &ctoken.token_type));
-                }
-            }
-        } else {
-            let tok = self.tokens.get(self.pc - 1).unwrap();
-            return Err(format!("{}:{}->{}: Unexpected end of tokens", tok.line, tok.column, tok.column + tok.length))
+    fn parse_string(&mut self) -> Result<String, Diagnostic> {
+        let token = self.next_token("a string")?;
+        match token.token_type {
+            TokenType::String(string) => Ok(string),
+            _ => Err(self.unexpected(&token, "a string")),
+        }
+    }
+
+    fn parse_bool(&mut self) -> Result<bool, Diagnostic> {
+        let token = self.next_token("a bool")?;
+        match token.token_type {
+            TokenType::Boolean(boolean) => Ok(boolean),
+            _ => Err(self.unexpected(&token, "a bool")),
+        }
+    }
+
+    fn parse_type(&mut self) -> Result<MiType, Diagnostic> {
+        let token = self.next_token("a type")?;
+        match &token.token_type {
+            TokenType::Type(ttype) => match ttype.as_str() {
+                "None" => Ok(MiType::None),
+                "int" => Ok(MiType::Int),
+                "float" => Ok(MiType::Float),
+                "string" => Ok(MiType::String),
+                "bool" | "boolean" => Ok(MiType::Bool),
+                "array" => Ok(MiType::Array),
+                "map" => Ok(MiType::Map),
+                "class" => Ok(MiType::Class),
+                "function" => Ok(MiType::Function),
+                _ => Err(self.error(&token, format!("Unrecognized type '{}'", ttype))),
+            },
+            _ => Err(self.unexpected(&token, "a type")),
         }
     }
 }
+
+/// Describes a token for error messages
+fn describe(token_type: &TokenType) -> String {
+    match token_type {
+        TokenType::Register(reg) => format!("register `r{}`", reg),
+        TokenType::Keyword(keyword) => format!("keyword `{}`", keyword),
+        TokenType::Directive(directive) => format!("directive `.{}`", directive),
+        TokenType::Identifier(identifier) => format!("identifier `{}`", identifier),
+        TokenType::Type(ttype) => format!("type `{}`", ttype),
+        TokenType::Int(int) => format!("int `{}`", int),
+        TokenType::Float(float) => format!("float `{}`", float),
+        TokenType::String(string) => format!("string {:?}", string),
+        TokenType::Boolean(boolean) => format!("bool `{}`", boolean),
+        TokenType::Comma => "`,`".to_string(),
+        TokenType::LeftBracket => "`[`".to_string(),
+        TokenType::RightBracket => "`]`".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembly::{macros, tokens};
+
+    fn parse(source: &str) -> Result<Vec<Instruction>, Vec<Diagnostic>> {
+        let tokens = tokens::tokenize(source, "test.masm")?;
+        let tokens = macros::expand(tokens, "test.masm")?;
+        Parser::new(tokens, "test.masm").parse()
+    }
+
+    #[test]
+    fn functions_take_as_many_arguments_as_their_count() {
+        let instructions = parse("definefnlabel f 2 a b int\nendfunction\ndefinefnlabel g 0 None\nendfunction").unwrap();
+        assert_eq!(instructions[0], Instruction::DefineFnLabel("f".to_string(), vec!["a".to_string(), "b".to_string()], MiType::Int));
+        assert_eq!(instructions[2], Instruction::DefineFnLabel("g".to_string(), Vec::new(), MiType::None));
+        // the extra argument is read where the return type is expected
+        assert!(parse("definefnlabel f 1 a b int\nendfunction").is_err());
+    }
+
+    #[test]
+    fn every_invalid_instruction_is_reported() {
+        let source = "move r0 int 1\nadd r0 r0\nmove r1 int 2\njumpunc 5\nstdoutwrite r0\nfrobnicate r1\nmove r2 string \"ok\"\n";
+        let diagnostics = parse(source).unwrap_err();
+        let errors = diagnostics.iter().map(|diagnostic| (diagnostic.span.line, diagnostic.message.as_str())).collect::<Vec<_>>();
+        // parsing resumes at the next keyword, so the valid instructions around the errors are not reported
+        assert_eq!(errors, vec![
+            (3, "Expected a register, found keyword `move`"),
+            (4, "Expected an identifier, found int `5`"),
+            (6, "Expected an instruction, found identifier `frobnicate`"),
+        ]);
+    }
+
+    #[test]
+    fn aliases_are_scoped_to_their_function() {
+        let source = "\
+.alias count r0
+move count int 1
+definefnlabel f 0 int
+.alias count r1
+move count int 2
+return
+endfunction
+move count int 3
+";
+        let tokens = tokens::tokenize(source, "test.masm").unwrap();
+        let mut parser = Parser::new(tokens, "test.masm");
+        let instructions = parser.parse().unwrap();
+        assert_eq!(instructions[0], Instruction::Move(0, 1.into_value()));
+        assert_eq!(instructions[2], Instruction::Move(1, 2.into_value()));
+        assert_eq!(instructions[5], Instruction::Move(0, 3.into_value()));
+        // the alias of the main code is reopened after the function
+        let ranges = parser.aliases().iter().map(|alias| (alias.register, alias.start, alias.end)).collect::<Vec<_>>();
+        assert_eq!(ranges, vec![(0, 0, 1), (1, 2, 4), (0, 5, 6)]);
+
+        // the aliases of the enclosing code are not visible in functions
+        let diagnostics = parse(".alias count r0\ndefinefnlabel f 0 int\nmove count int 1\nreturn\nendfunction").unwrap_err();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].span.line, 3);
+    }
+}
@@ -1,14 +1,26 @@
+use std::iter::Peekable;
+use std::rc::Rc;
+use std::str::Chars;
+
+use crate::registers::REGISTER_COUNT;
+use super::diagnostic::{Diagnostic, Span};
+use super::macros::Expansion;
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum TokenType {
     Register(usize),
     Keyword(String),
+    /// An assembler directive such as `.const`, without the dot
+    Directive(String),
     Identifier(String),
     Type(String),
-    Int(i32),
+    Int(i64),
     Float(f64),
     String(String),
     Boolean(bool),
     Comma,
+    LeftBracket,
+    RightBracket,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -17,16 +29,57 @@ pub struct Token {
     pub length: usize,
     pub line: usize,
     pub column: usize,
+    /// The macro call the token was expanded from, if any
+    pub expansion: Option<Rc<Expansion>>,
+}
+
+/// Iterates over the characters of a source, keeping track of the
+/// line and column of the last character returned
+struct Cursor<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+    column: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(input: &'a str) -> Self {
+        Self {
+            chars: input.chars().peekable(),
+            line: 1,
+            column: 0,
+        }
+    }
+
+    fn peek(&mut self) -> Option<&char> {
+        self.chars.peek()
+    }
 }
 
-pub fn tokenize(input: &str, filename: &str) -> Result<Vec<Token>, String> {
-    let mut iterator = input.chars().peekable();
+impl Iterator for Cursor<'_> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let character = self.chars.next()?;
+        if character == '\n' {
+            self.line += 1;
+            self.column = 0;
+        } else {
+            self.column += 1;
+        }
+        Some(character)
+    }
+}
+
+/// Splits a source into tokens, each one holding the line and column it starts at.
+/// Invalid characters are skipped so that all the errors of the source are reported.
+pub fn tokenize(input: &str, filename: &str) -> Result<Vec<Token>, Vec<Diagnostic>> {
+    let mut iterator = Cursor::new(input);
     let mut tokens_stream: Vec<Token> = Vec::new();
-    let mut line = 1;
-    let mut column = 0;
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
     loop {
         match iterator.next() {
             Some(character) => {
+                let (line, column) = (iterator.line, iterator.column);
                 match character {
                     'a'..='z' | 'A'..='Z' | '_' => {
                         let mut identifier = String::new();
@@ -41,7 +94,6 @@ pub fn tokenize(input: &str, filename: &str) -> Result<Vec<Token>, String> {
                             }
                         }
                         let identifier_len = identifier.len();
-                        column += identifier_len;
                         if [
                             "move", "movebetween", "moveargument", "moveasargument",
                             "add", "sub", "mul", "div", "rem", "pow", "or", "xor", "and",
@@ -49,12 +101,17 @@ pub fn tokenize(input: &str, filename: &str) -> Result<Vec<Token>, String> {
                             "throwfrom", "eq", "ne", "definelabel", "jumpunc", "jumpc",
                             "call", "definefnlabel", "endfunction", "stdoutwrite", "stdoutwritedebugged",
                             "stdoutflush", "stderrwrite", "stderrwritedebugged", "stderrflush", "bufferedstdinread",
+                            "try", "endtry", "arraynew", "arraypush", "arraypop", "arrayget", "arrayset",
+                            "arraylen", "arrayslice", "mapnew", "mapinsert", "mapget", "mapremove", "mapcontains",
+                            "mapkeys", "maplen", "defineclass", "field", "endclass", "new", "getfield", "setfield",
+                            "callmethod", "cast", "typeof", "import",
                         ].contains(&identifier.as_str()) {
                             tokens_stream.push(Token {
                                 token_type: TokenType::Keyword(identifier),
                                 length: identifier_len,
                                 line,
                                 column,
+                                expansion: None,
                             })
                         } else if ["true", "false"].contains(&identifier.as_str()) {
                             tokens_stream.push(Token {
@@ -62,37 +119,37 @@ pub fn tokenize(input: &str, filename: &str) -> Result<Vec<Token>, String> {
                                 length: identifier_len,
                                 line,
                                 column,
+                                expansion: None,
                             })
                         } else if [
-                            "int", "float", "string", "class", "function", "None"
+                            "int", "float", "string", "bool", "class", "function", "array", "map", "None"
                         ].contains(&identifier.as_str()) {
                             tokens_stream.push(Token {
                                 token_type: TokenType::Type(identifier),
                                 length: identifier_len,
                                 line,
                                 column,
+                                expansion: None,
                             })
                         } else if identifier.as_str() == "plch" {
                             continue;
-                        } else if identifier.starts_with("r") && identifier.chars().last().unwrap().is_numeric()
-                                                              && identifier.len() <= 3 {
-                            let num: String = identifier.chars().skip(1).collect();
-                            let res = num.parse::<u8>();
-                            match res {
-                                Ok(reg) => {
-                                    if reg >= 16 {
-                                        return Err(format!("Invalid register index {}", reg))
-                                    }
+                        } else if identifier.len() > 1 && identifier.starts_with('r') && identifier[1..].chars().all(|c| c.is_ascii_digit()) {
+                            // only `r` followed by digits is a register, other names such as `r2d` are identifiers
+                            match identifier[1..].parse::<usize>() {
+                                Ok(reg) if reg < REGISTER_COUNT => {
                                     tokens_stream.push(Token {
-                                        token_type: TokenType::Register(reg as usize),
+                                        token_type: TokenType::Register(reg),
                                         length: identifier_len,
                                         line,
-                                        column
+                                        column,
+                                        expansion: None,
                                     });
-                                    column += num.len() + 1;
                                 }
-                                Err(reg) => {
-                                    return Err(format!("Unable to parse register value {}", reg))
+                                _ => {
+                                    diagnostics.push(
+                                        Diagnostic::error(format!("Invalid register `{}`", identifier), filename, Span::new(line, column, identifier_len))
+                                            .with_note("the registers go from r0 to r15")
+                                    );
                                 }
                             }
                         } else {
@@ -101,58 +158,89 @@ pub fn tokenize(input: &str, filename: &str) -> Result<Vec<Token>, String> {
                                 length: identifier_len,
                                 line,
                                 column,
+                                expansion: None,
                             });
-                            column += identifier_len;
                         }
                     }
+                    '.' => {
+                        let mut directive = String::new();
+                        while let Some(c) = iterator.peek() {
+                            if c.is_alphanumeric() || c == &'_' {
+                                directive.push(iterator.next().unwrap());
+                            } else {
+                                break;
+                            }
+                        }
+                        if !["const", "macro", "endmacro", "alias"].contains(&directive.as_str()) {
+                            diagnostics.push(
+                                Diagnostic::error(format!("Unknown directive '.{}'", directive), filename, Span::new(line, column, directive.len() + 1))
+                                    .with_note("the directives are `.const`, `.macro`, `.endmacro` and `.alias`")
+                            );
+                            continue;
+                        }
+                        tokens_stream.push(Token {
+                            length: directive.len() + 1,
+                            token_type: TokenType::Directive(directive),
+                            line,
+                            column,
+                            expansion: None,
+                        });
+                    }
                     ',' => {
                         tokens_stream.push(Token {
                             token_type: TokenType::Comma,
                             length: 1,
                             line,
                             column,
+                            expansion: None,
+                        });
+                    }
+                    '[' => {
+                        tokens_stream.push(Token {
+                            token_type: TokenType::LeftBracket,
+                            length: 1,
+                            line,
+                            column,
+                            expansion: None,
+                        });
+                    }
+                    ']' => {
+                        tokens_stream.push(Token {
+                            token_type: TokenType::RightBracket,
+                            length: 1,
+                            line,
+                            column,
+                            expansion: None,
                         });
-                        column += 1;
                     }
-                    // Inside the 'match character' block:
                     '0'..='9' => {
-                        let mut number = String::new();
-                        number.push(character);
-                        while let Some(c) = iterator.next() {
-                            if c.is_numeric() {
-                                number.push(c);
-                            } else {
-                                break;
-                            }
+                        match lex_number(&mut iterator, filename, (line, column), character, false) {
+                            Ok((token_type, length)) => tokens_stream.push(Token {
+                                token_type,
+                                length,
+                                line,
+                                column,
+                                expansion: None,
+                            }),
+                            Err(diagnostic) => diagnostics.push(diagnostic),
                         }
-                        if number.contains('.') {
-                            let parsed_number = number.parse::<f64>();
-                            if let Ok(num) = parsed_number {
-                                let identifier_len = number.len();
-                                tokens_stream.push(Token {
-                                    token_type: TokenType::Float(num),
-                                    length: identifier_len,
-                                    line,
-                                    column,
-                                });
-                                column += identifier_len;
-                            } else if let Err(err) = parsed_number {
-                                return Err(format!("{}:{}:{}: Error parsing float number: {}", filename, line, column, err));
-                            }
-                        } else {
-                            let parsed_number = number.parse::<i32>();
-                            if let Ok(num) = parsed_number {
-                                let identifier_len = number.len();
-                                tokens_stream.push(Token {
-                                    token_type: TokenType::Int(num),
-                                    length: identifier_len,
-                                    line,
-                                    column,
-                                });
-                                column += identifier_len; 
-                            } else if let Err(err) = parsed_number {
-                                return Err(format!("{}:{}:{}: Error parsing int number: {}", filename, line, column, err));
+                    }
+                    '+' => {
+                        match iterator.peek() {
+                            Some(c) if c.is_ascii_digit() => {
+                                let first = iterator.next().unwrap();
+                                match lex_number(&mut iterator, filename, (line, column), first, false) {
+                                    Ok((token_type, length)) => tokens_stream.push(Token {
+                                        token_type,
+                                        length,
+                                        line,
+                                        column,
+                                        expansion: None,
+                                    }),
+                                    Err(diagnostic) => diagnostics.push(diagnostic),
+                                }
                             }
+                            _ => diagnostics.push(Diagnostic::error("Unrecognized token '+'", filename, Span::new(line, column, 1))),
                         }
                     }
 
@@ -166,12 +254,10 @@ pub fn tokenize(input: &str, filename: &str) -> Result<Vec<Token>, String> {
                                     break;
                                 }
                                 '\n' => {
-                                    line += 1;
                                     string.push('\n');
                                 }
                                 '\r' => {
                                     if Some(&'\n') == iterator.peek() {
-                                        line += 1;
                                         string.push_str("\r\n");
                                         iterator.next();
                                     } else {
@@ -198,50 +284,39 @@ pub fn tokenize(input: &str, filename: &str) -> Result<Vec<Token>, String> {
                                                     string.push('\0');
                                                 }
                                                 'u' => {
+                                                    let escape = Span::new(iterator.line, iterator.column - 1, 2);
                                                     let mut digits = String::new();
-                                                    for _ in 0..4 {
-                                                        match iterator.next() {
-                                                            Some(digit) => {
-                                                                digits.push(digit);
-                                                            }
-                                                            None => {
-                                                                return Err(format!(
-                                                                    "{}:{}: A unicode escape sequence must have 4 hexadecimal digits in the sense of \\u{{7FFF}}",
-                                                                    line, column
-                                                                ))
+                                                    while digits.len() < 4 {
+                                                        match iterator.peek() {
+                                                            Some(digit) if digit.is_ascii_hexdigit() => {
+                                                                digits.push(*digit);
+                                                                iterator.next();
                                                             }
+                                                            _ => break,
                                                         }
                                                     }
-                                                    if let Ok(num) = u32::from_str_radix(&digits, 16) {
-                                                        if let Some(ch) = char::from_u32(num) {
-                                                            string.push(ch);
-                                                        }
-                                                    } else if let Err(err) =
-                                                        u32::from_str_radix(&digits, 16)
-                                                    {
-                                                        return Err(format!(
-                                                            "{}:{}: Error during unicode escape sequence '\\u{}' parsing: {}",
-                                                            line, column, digits, err
-                                                        ));
+                                                    let escape = Span::new(escape.line, escape.column, digits.len() + 2);
+                                                    match u32::from_str_radix(&digits, 16).ok().and_then(char::from_u32) {
+                                                        Some(ch) if digits.len() == 4 => string.push(ch),
+                                                        _ => diagnostics.push(
+                                                            Diagnostic::error(format!("Invalid unicode escape sequence '\\u{}'", digits), filename, escape)
+                                                                .with_note("a unicode escape sequence must have 4 hexadecimal digits in the sense of \\u7FFF")
+                                                        ),
                                                     }
                                                 }
                                                 '"' => {
                                                     string.push('"');
                                                 }
                                                 _ => {
-                                                    return Err(format!(
-                                                        "{}:{}: Unknown escape sequence '\\{}'",
-                                                        line, column, c
+                                                    diagnostics.push(Diagnostic::error(
+                                                        format!("Unknown escape sequence '\\{}'", c),
+                                                        filename,
+                                                        Span::new(iterator.line, iterator.column - 1, 2),
                                                     ))
                                                 }
                                             }
                                         }
-                                        None => {
-                                            return Err(format!(
-                                                "{}:{}: Unclosed string literal",
-                                                line, column
-                                            ));
-                                        }
+                                        None => break,
                                     }
                                 }
                                 _ => {
@@ -250,10 +325,11 @@ pub fn tokenize(input: &str, filename: &str) -> Result<Vec<Token>, String> {
                             }
                         }
                         if !reached {
-                            return Err(format!(
-                                "{}:{}: Unclosed string literal",
-                                line, column
-                            ));
+                            diagnostics.push(
+                                Diagnostic::error("Unclosed string literal", filename, Span::new(line, column, 1))
+                                    .with_note("add a `\"` to close the string")
+                            );
+                            continue;
                         }
                         let strlen = string.len();
                         tokens_stream.push(Token {
@@ -261,6 +337,7 @@ pub fn tokenize(input: &str, filename: &str) -> Result<Vec<Token>, String> {
                             length: strlen + 2,
                             line,
                             column,
+                            expansion: None,
                         });
                     }
                     '-' => {
@@ -268,26 +345,37 @@ pub fn tokenize(input: &str, filename: &str) -> Result<Vec<Token>, String> {
                             iterator.next();
                             while let Some(c) = iterator.next() {
                                 if c == '\n' {
-                                    line += 1;
-                                    column = 0;
                                     break;
                                 } else {
                                     continue;
                                 }
                             }
+                        } else if iterator.peek().is_some_and(|c| c.is_ascii_digit()) {
+                            let first = iterator.next().unwrap();
+                            match lex_number(&mut iterator, filename, (line, column), first, true) {
+                                Ok((token_type, length)) => tokens_stream.push(Token {
+                                    token_type,
+                                    length,
+                                    line,
+                                    column,
+                                    expansion: None,
+                                }),
+                                Err(diagnostic) => diagnostics.push(diagnostic),
+                            }
                         } else {
-                            return Err(format!("{}:{}:{}: Unrecognized token '-{}'", filename, line, column, iterator.peek().unwrap_or(&'?')))
+                            diagnostics.push(
+                                Diagnostic::error("Unrecognized token '-'", filename, Span::new(line, column, 1))
+                                    .with_note("comments start with `--`, negative numbers with `-` followed by a digit")
+                            );
                         }
                     }
-                    '\n' => {
-                        column = 0;
-                        line += 1;
-                    }
                     _ => {
                         if character.is_whitespace() {
                             continue;
                         } else {
-                            return Err(format!("{}:{}:{}: Unrecognized token '{}'", filename, line, column, character))
+                            diagnostics.push(Diagnostic::error(
+                                format!("Unrecognized token '{}'", character), filename, Span::new(line, column, 1)
+                            ));
                         }
                     }
                 }
@@ -295,5 +383,135 @@ pub fn tokenize(input: &str, filename: &str) -> Result<Vec<Token>, String> {
             None => break,
         }
     }
-    Ok(tokens_stream)
-}
\ No newline at end of file
+    if diagnostics.is_empty() {
+        Ok(tokens_stream)
+    } else {
+        Err(diagnostics)
+    }
+}
+
+/// Lexes a number literal starting at the specified line and column, whose sign if any and
+/// first digit were consumed. The character following the literal is not consumed.
+/// Returns the token with the length of the literal, sign included.
+///
+/// Ints are written in decimal or with a `0x`, `0b` or `0o` prefix, and floats in decimal
+/// with a fractional part, an exponent or both. Digits can be separated by `_`.
+fn lex_number(iterator: &mut Cursor, filename: &str, (line, column): (usize, usize), first: char, negative: bool) -> Result<(TokenType, usize), Diagnostic> {
+    // the whole word is consumed so that invalid digits or suffixes are part of the error
+    let mut literal = String::from(first);
+    while let Some(&c) = iterator.peek() {
+        let exponent_sign = (c == '+' || c == '-')
+            && !literal.to_ascii_lowercase().starts_with("0x")
+            && literal.ends_with(['e', 'E']);
+        if c.is_alphanumeric() || c == '_' || c == '.' || exponent_sign {
+            literal.push(c);
+            iterator.next();
+        } else {
+            break;
+        }
+    }
+    let length = iterator.column + 1 - column;
+    let span = Span::new(line, column, length);
+    let sign = if negative { "-" } else { "" };
+    let out_of_range = || {
+        Diagnostic::error(format!("The int literal `{}{}` does not fit in 64 bits", sign, literal), filename, span)
+            .with_note(format!("ints go from {} to {}", i64::MIN, i64::MAX))
+    };
+    let invalid = |message: String| {
+        Diagnostic::error(message, filename, span)
+            .with_note("numbers are written in the sense of `42`, `-7`, `1_000`, `2.5`, `6.02e23`, `0xFF`, `0b1010` or `0o17`")
+    };
+
+    let prefix = literal.get(..2).map(str::to_ascii_lowercase);
+    let radix = match prefix.as_deref() {
+        Some("0x") => Some((16, "hexadecimal")),
+        Some("0b") => Some((2, "binary")),
+        Some("0o") => Some((8, "octal")),
+        _ => None,
+    };
+    if let Some((radix, name)) = radix {
+        let digits: String = literal[2..].chars().filter(|c| *c != '_').collect();
+        if let Some(digit) = digits.chars().find(|c| !c.is_digit(radix)) {
+            return Err(invalid(format!("Invalid digit `{}` in {} literal `{}`", digit, name, literal)));
+        }
+        if digits.is_empty() {
+            return Err(invalid(format!("Expected {} digits after `{}`", name, &literal[..2])));
+        }
+        let magnitude = u128::from_str_radix(&digits, radix).map_err(|_| out_of_range())?;
+        return signed_int(magnitude, negative).map(|int| (TokenType::Int(int), length)).ok_or_else(out_of_range);
+    }
+
+    let digits: String = literal.chars().filter(|c| *c != '_').collect();
+    let (mantissa, exponent) = match digits.find(['e', 'E']) {
+        Some(index) => (&digits[..index], Some(&digits[index + 1..])),
+        None => (digits.as_str(), None),
+    };
+    let (integer, fraction) = match mantissa.split_once('.') {
+        Some((integer, fraction)) => (integer, Some(fraction)),
+        None => (mantissa, None),
+    };
+    let all_digits = |part: &str| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit());
+    let exponent_digits = exponent.map(|exponent| exponent.strip_prefix(['+', '-']).unwrap_or(exponent));
+    if !all_digits(integer) || !fraction.is_none_or(all_digits) || !exponent_digits.is_none_or(all_digits) {
+        return Err(invalid(format!("Invalid number literal `{}{}`", sign, literal)));
+    }
+    if fraction.is_none() && exponent.is_none() {
+        let magnitude = integer.parse::<u128>().map_err(|_| out_of_range())?;
+        return signed_int(magnitude, negative).map(|int| (TokenType::Int(int), length)).ok_or_else(out_of_range);
+    }
+    let float = format!("{}{}", sign, digits).parse::<f64>().map_err(|err| invalid(err.to_string()))?;
+    if float.is_infinite() {
+        return Err(Diagnostic::error(format!("The float literal `{}{}` is out of range", sign, literal), filename, span)
+            .with_note(format!("floats go up to {:e}", f64::MAX)));
+    }
+    Ok((TokenType::Float(float), length))
+}
+
+/// Applies the sign to the magnitude of an int literal, if the result fits in an int
+fn signed_int(magnitude: u128, negative: bool) -> Option<i64> {
+    let value = i128::try_from(magnitude).ok()?;
+    i64::try_from(if negative { -value } else { value }).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembly::diagnostic::Span;
+
+    fn token_types(source: &str) -> Vec<TokenType> {
+        tokenize(source, "numbers.masm").unwrap().into_iter().map(|token| token.token_type).collect()
+    }
+
+    #[test]
+    fn numeric_literals_are_lexed_with_their_sign_base_and_exponent() {
+        assert_eq!(token_types("-0x10 1_000.5e-3 +0b11 0o17 -9223372036854775808 6.02E23"), vec![
+            TokenType::Int(-16),
+            TokenType::Float(1.0005),
+            TokenType::Int(3),
+            TokenType::Int(15),
+            TokenType::Int(i64::MIN),
+            TokenType::Float(6.02e23),
+        ]);
+        assert!(matches!(token_types("-0.0")[..], [TokenType::Float(float)] if float == 0.0 && float.is_sign_negative()));
+    }
+
+    #[test]
+    fn numeric_literals_out_of_range_point_at_the_literal() {
+        let diagnostics = tokenize("1\n-9223372036854775809", "range.masm").unwrap_err();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].span, Span::new(2, 1, 20));
+        assert_eq!(diagnostics[0].message, "The int literal `-9223372036854775809` does not fit in 64 bits");
+
+        // magnitudes beyond the range of an i128 are out of range instead of wrapping around
+        for literal in ["0xFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF", "340282366920938463463374607431768211455", "-0x80000000000000000000000000000000"] {
+            let diagnostics = tokenize(literal, "range.masm").unwrap_err();
+            assert_eq!(diagnostics.len(), 1);
+            assert_eq!(diagnostics[0].message, format!("The int literal `{literal}` does not fit in 64 bits"));
+        }
+
+        let diagnostics = tokenize("1e309 0b102", "range.masm").unwrap_err();
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].span, Span::new(1, 1, 5));
+        assert_eq!(diagnostics[1].span, Span::new(1, 7, 5));
+    }
+}
@@ -0,0 +1,264 @@
+use fxhash::FxHashMap;
+
+use crate::result::{MiResult, MiError};
+use crate::value::{MiValue, MiType, IntoValue};
+
+/// A native function callable from the VM through the `call` instruction
+pub type BuiltinFn = Box<dyn Fn(&[MiValue]) -> MiResult>;
+
+/// Represents a native function registered in the VM
+pub struct Builtin {
+    pub name: String,
+    pub arguments: Vec<MiType>,
+    /// Type of the values the function returns, checked after every call
+    pub returns: MiType,
+    pub function: BuiltinFn,
+}
+
+impl Builtin {
+    /// Returns the number of arguments the builtin takes from the argument stack
+    pub fn arity(&self) -> usize {
+        self.arguments.len()
+    }
+}
+
+/// Holds the builtin functions of the VM. The index of a builtin is the
+/// `u32` stored in `Function::Builtin`.
+///
+/// Builtins bind their arguments like defined functions do: the first argument
+/// is the value on top of the argument stack, that is, the last one pushed.
+/// String builtins index by characters, not bytes.
+pub struct Builtins {
+    functions: Vec<Builtin>,
+    indexes: FxHashMap<String, u32>,
+}
+
+impl Default for Builtins {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Builtins {
+    /// Creates an empty registry
+    pub fn new() -> Self {
+        Self {
+            functions: Vec::new(),
+            indexes: FxHashMap::default(),
+        }
+    }
+
+    /// Creates a registry holding the standard builtin functions
+    pub fn with_defaults() -> Self {
+        let mut builtins = Self::new();
+        builtins.register("strlen", vec![MiType::String], MiType::Int, |args| {
+            MiResult::Ok((string(&args[0]).chars().count() as i64).into_value())
+        });
+        builtins.register("bytelen", vec![MiType::String], MiType::Int, |args| {
+            MiResult::Ok((string(&args[0]).len() as i64).into_value())
+        });
+        builtins.register("concat", vec![MiType::String, MiType::String], MiType::String, |args| {
+            MiResult::Ok([string(&args[0]), string(&args[1])].concat().into_value())
+        });
+        builtins.register("substring", vec![MiType::String, MiType::Int, MiType::Int], MiType::String, |args| {
+            let text = string(&args[0]);
+            let (start, end) = (int(&args[1]), int(&args[2]));
+            let length = text.chars().count() as i64;
+            if start < 0 || end < start || end > length {
+                return error("IndexOutOfBounds", format!("The range `{start}..{end}` is out of bounds for a string of {length} characters"));
+            }
+            let substring: String = text.chars().skip(start as usize).take((end - start) as usize).collect();
+            MiResult::Ok(substring.into_value())
+        });
+        builtins.register("find", vec![MiType::String, MiType::String], MiType::Int, |args| {
+            let haystack = string(&args[0]);
+            let index = match haystack.find(string(&args[1])) {
+                Some(byte_index) => haystack[..byte_index].chars().count() as i64,
+                None => -1,
+            };
+            MiResult::Ok(index.into_value())
+        });
+        builtins.register("replace", vec![MiType::String, MiType::String, MiType::String], MiType::String, |args| {
+            MiResult::Ok(string(&args[0]).replace(string(&args[1]), string(&args[2])).into_value())
+        });
+        builtins.register("split", vec![MiType::String, MiType::String], MiType::Array, |args| {
+            let separator = string(&args[1]);
+            if separator.is_empty() {
+                return error("InvalidArgument", "Cannot split a string by an empty separator");
+            }
+            let parts = string(&args[0])
+                .split(separator)
+                .map(|part| part.into_value())
+                .collect::<Vec<MiValue>>();
+            MiResult::Ok(parts.into_value())
+        });
+        builtins.register("trim", vec![MiType::String], MiType::String, |args| {
+            MiResult::Ok(string(&args[0]).trim().into_value())
+        });
+        builtins.register("upper", vec![MiType::String], MiType::String, |args| {
+            MiResult::Ok(string(&args[0]).to_uppercase().into_value())
+        });
+        builtins.register("lower", vec![MiType::String], MiType::String, |args| {
+            MiResult::Ok(string(&args[0]).to_lowercase().into_value())
+        });
+        builtins.register("startswith", vec![MiType::String, MiType::String], MiType::Bool, |args| {
+            MiResult::Ok(string(&args[0]).starts_with(string(&args[1])).into_value())
+        });
+        builtins.register("endswith", vec![MiType::String, MiType::String], MiType::Bool, |args| {
+            MiResult::Ok(string(&args[0]).ends_with(string(&args[1])).into_value())
+        });
+        builtins.register("abs", vec![MiType::Int], MiType::Int, |args| {
+            match int(&args[0]).checked_abs() {
+                Some(num) => MiResult::Ok(num.into_value()),
+                None => error("IntegerOverflow", format!("The absolute value of `{}` does not fit in an int", int(&args[0]))),
+            }
+        });
+        builtins.register("min", vec![MiType::Int, MiType::Int], MiType::Int, |args| {
+            MiResult::Ok(int(&args[0]).min(int(&args[1])).into_value())
+        });
+        builtins.register("max", vec![MiType::Int, MiType::Int], MiType::Int, |args| {
+            MiResult::Ok(int(&args[0]).max(int(&args[1])).into_value())
+        });
+        builtins.register("sqrt", vec![MiType::Float], MiType::Float, |args| {
+            MiResult::Ok(float(&args[0]).sqrt().into_value())
+        });
+        builtins.register("floor", vec![MiType::Float], MiType::Float, |args| {
+            MiResult::Ok(float(&args[0]).floor().into_value())
+        });
+        builtins.register("ceil", vec![MiType::Float], MiType::Float, |args| {
+            MiResult::Ok(float(&args[0]).ceil().into_value())
+        });
+        builtins.register("round", vec![MiType::Float], MiType::Float, |args| {
+            MiResult::Ok(float(&args[0]).round().into_value())
+        });
+        builtins
+    }
+
+    /// Registers a builtin function and returns its index.
+    /// A builtin registered under an existing name replaces the previous one.
+    pub fn register<T, F>(&mut self, name: T, arguments: Vec<MiType>, returns: MiType, function: F) -> u32
+    where
+        T: ToString,
+        F: Fn(&[MiValue]) -> MiResult + 'static,
+    {
+        let builtin = Builtin {
+            name: name.to_string(),
+            arguments,
+            returns,
+            function: Box::new(function),
+        };
+        match self.indexes.get(&builtin.name) {
+            Some(&index) => {
+                self.functions[index as usize] = builtin;
+                index
+            }
+            None => {
+                let index = self.functions.len() as u32;
+                self.indexes.insert(builtin.name.clone(), index);
+                self.functions.push(builtin);
+                index
+            }
+        }
+    }
+
+    /// Returns the index of the builtin with the specified name
+    pub fn index_of(&self, name: &str) -> Option<u32> {
+        self.indexes.get(name).copied()
+    }
+
+    /// Returns the builtin stored at the specified index
+    pub fn get(&self, index: u32) -> Option<&Builtin> {
+        self.functions.get(index as usize)
+    }
+}
+
+fn error<T: ToString>(name: &str, message: T) -> MiResult {
+    MiResult::Err(MiError {
+        name: name.to_string(),
+        message: message.to_string(),
+        backtrace: String::new(),
+    })
+}
+
+fn int(value: &MiValue) -> i64 {
+    match value {
+        MiValue::Int(num) => *num,
+        _ => unreachable!("builtin arguments are type checked before the call"),
+    }
+}
+
+fn float(value: &MiValue) -> f64 {
+    match value {
+        MiValue::Float(num) => *num,
+        _ => unreachable!("builtin arguments are type checked before the call"),
+    }
+}
+
+fn string(value: &MiValue) -> &str {
+    match value {
+        MiValue::Str(string) => string,
+        _ => unreachable!("builtin arguments are type checked before the call"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Calls a default builtin with its arguments in declaration order
+    fn call(name: &str, args: &[MiValue]) -> Result<MiValue, MiError> {
+        let builtins = Builtins::with_defaults();
+        let builtin = builtins.get(builtins.index_of(name).unwrap()).unwrap();
+        match (builtin.function)(args) {
+            MiResult::Ok(value) => Ok(value),
+            MiResult::Err(error) => Err(error),
+        }
+    }
+
+    fn text(string: &str) -> MiValue {
+        string.into_value()
+    }
+
+    #[test]
+    fn strings_are_measured_in_characters() {
+        assert_eq!(call("strlen", &[text("héllo wörld")]), Ok(11.into_value()));
+        assert_eq!(call("bytelen", &[text("héllo wörld")]), Ok(13.into_value()));
+        assert_eq!(call("strlen", &[text("日本語🦀")]), Ok(4.into_value()));
+        assert_eq!(call("bytelen", &[text("日本語🦀")]), Ok(13.into_value()));
+    }
+
+    #[test]
+    fn strings_are_indexed_by_characters() {
+        let string = text("日本語のテキスト");
+        assert_eq!(call("substring", &[string.clone(), 1.into_value(), 3.into_value()]), Ok(text("本語")));
+        assert_eq!(call("substring", &[string.clone(), 8.into_value(), 8.into_value()]), Ok(text("")));
+        assert_eq!(call("find", &[string.clone(), text("テキスト")]), Ok(4.into_value()));
+        assert_eq!(call("find", &[string, text("ö")]), Ok((-1).into_value()));
+    }
+
+    #[test]
+    fn out_of_range_substrings_are_errors() {
+        let string = text("añb");
+        for (start, end) in [(0, 4), (2, 1), (-1, 2)] {
+            let error = call("substring", &[string.clone(), start.into_value(), end.into_value()]).unwrap_err();
+            assert_eq!(error.name, "IndexOutOfBounds");
+        }
+        // the length is counted in characters even though the string is 4 bytes long
+        let error = call("substring", &[string, 0.into_value(), 4.into_value()]).unwrap_err();
+        assert_eq!(error.message, "The range `0..4` is out of bounds for a string of 3 characters");
+    }
+
+    #[test]
+    fn split_keeps_multi_byte_characters_whole() {
+        let parts = vec![text("α"), text("β"), text(""), text("γδ")];
+        assert_eq!(call("split", &[text("α→β→→γδ"), text("→")]), Ok(parts.into_value()));
+        let error = call("split", &[text("αβ"), text("")]).unwrap_err();
+        assert_eq!(error.name, "InvalidArgument");
+    }
+
+    #[test]
+    fn case_conversions_handle_non_ascii_text() {
+        assert_eq!(call("upper", &[text("straße ärger ǆ")]), Ok(text("STRASSE ÄRGER Ǆ")));
+        assert_eq!(call("lower", &[text("ÉCOLE ΣΟΦΊΑ")]), Ok(text("école σοφία")));
+    }
+}
@@ -1,7 +1,6 @@
 use fxhash::FxHashMap;
 use serde_derive::{Serialize, Deserialize};
 use crate::value::{ToStringDebugged, MiValue, MiType};
-use crate::function::Function;
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
 
@@ -23,9 +22,10 @@ impl Class {
     }
 }
 
+/// The fields a class declares. Its methods are not part of it, they are called through
+/// the function table of the runtime as `Class.method`.
 #[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
 pub struct ClassBlueprint {
     pub name: String,
-    pub functions: FxHashMap<String, Function>,
     pub variables: FxHashMap<String, MiType>,
 }
\ No newline at end of file
@@ -0,0 +1,70 @@
+use serde_derive::{Serialize, Deserialize};
+
+/// A source file the program was assembled from
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct SourceFile {
+    pub name: String,
+    /// The contents of the file, only present if it was embedded at build time
+    pub source: Option<String>,
+}
+
+/// The position in the source of the keyword of an instruction
+#[derive(PartialEq, Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct SourceLocation {
+    /// Index of the file in `DebugInfo::files`
+    pub file: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// A name given to a register with `.alias`, for the instructions it was in scope for
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct RegisterAlias {
+    pub name: String,
+    pub register: usize,
+    /// Index of the first instruction the alias applies to
+    pub start: usize,
+    /// Index of the instruction after the last one the alias applies to
+    pub end: usize,
+}
+
+/// Maps the instructions of a program back to the `.masm` sources they were assembled from
+#[derive(PartialEq, Clone, Debug, Default, Serialize, Deserialize)]
+pub struct DebugInfo {
+    pub files: Vec<SourceFile>,
+    /// The location of every instruction, by instruction index
+    pub locations: Vec<SourceLocation>,
+    /// The register aliases, ordered by the instruction they start at
+    pub aliases: Vec<RegisterAlias>,
+}
+
+impl DebugInfo {
+    /// Returns the location of the instruction at the specified index
+    pub fn location(&self, index: usize) -> Option<&SourceLocation> {
+        self.locations.get(index)
+    }
+
+    /// Formats the location of the instruction at the specified index as `file:line:column`
+    pub fn format_location(&self, index: usize) -> Option<String> {
+        let location = self.location(index)?;
+        let file = self.files.get(location.file)?;
+        Some(format!("{}:{}:{}", file.name, location.line, location.column))
+    }
+
+    /// Returns the source line of the instruction at the specified index,
+    /// if the source of its file was embedded
+    pub fn source_line(&self, index: usize) -> Option<&str> {
+        let location = self.location(index)?;
+        let source = self.files.get(location.file)?.source.as_ref()?;
+        source.lines().nth(location.line.checked_sub(1)?)
+    }
+
+    /// Returns the alias of the register at the instruction at the specified index, if it has one
+    pub fn alias(&self, index: usize, register: usize) -> Option<&str> {
+        self.aliases
+            .iter()
+            .rev()
+            .find(|alias| alias.register == register && (alias.start..alias.end).contains(&index))
+            .map(|alias| alias.name.as_str())
+    }
+}
@@ -0,0 +1,350 @@
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Write};
+
+use crate::assembly::disasm::format_instruction;
+use crate::instructions::Instruction;
+use crate::registers::REGISTER_COUNT;
+use crate::result::MiError;
+use crate::runtime::{MirageRuntime, Step};
+use crate::value::{MiValue, ToStringDebugged};
+
+const HELP: &str = "\
+Commands:
+  break <label|index>    (b)  sets a breakpoint before the instruction
+  delete <label|index>   (d)  removes a breakpoint
+  breakpoints            (bl) lists the breakpoints
+  step                   (s)  executes one instruction, entering calls
+  next                   (n)  executes one instruction, running calls to their end
+  continue               (c)  runs until a breakpoint or the end of the program
+  list [count]           (l)  shows the instructions around the next one
+  registers              (r)  prints the registers of the current frame
+  frame                  (f)  prints the arguments and locals of the current frame
+  backtrace              (bt) prints the call stack
+  help                   (h)  shows this message
+  quit                   (q)  stops debugging
+An empty line repeats the last command.";
+
+/// Why the debugger stopped running the program
+#[derive(Clone, PartialEq, Debug)]
+pub enum Stop {
+    /// The requested steps were executed
+    Paused,
+    /// The next instruction has a breakpoint
+    Breakpoint(usize),
+    /// The program ended, holding the value of the return register
+    Finished(Option<MiValue>),
+    /// The program threw an error that no handler caught
+    Error(MiError),
+}
+
+/// An interactive debugger that drives a runtime one instruction at a time
+pub struct Debugger<'dbg, 'rtm> {
+    runtime: &'dbg mut MirageRuntime<'rtm>,
+    breakpoints: BTreeSet<usize>,
+    finished: bool,
+}
+
+impl<'dbg, 'rtm> Debugger<'dbg, 'rtm> {
+    /// Creates a debugger for a runtime, starting it with `MirageRuntime::start` unless it
+    /// was already started. Fails if the program cannot be linked.
+    pub fn new(runtime: &'dbg mut MirageRuntime<'rtm>) -> Result<Self, MiError> {
+        if runtime.call_stack().frames().is_empty() {
+            runtime.start()?;
+        }
+        Ok(Self {
+            runtime,
+            breakpoints: BTreeSet::new(),
+            finished: false,
+        })
+    }
+
+    /// Resolves a breakpoint location, which is either a label or an instruction index.
+    /// Jumps resume after the label they target, so a label resolves to the instruction after it.
+    pub fn location(&self, location: &str) -> Result<usize, String> {
+        if let Some(index) = self.runtime.label_index(location) {
+            return Ok(index + 1);
+        }
+        match location.parse::<usize>() {
+            Ok(index) if index < self.runtime.instructions().len() => Ok(index),
+            Ok(index) => Err(format!("The index `{index}` is out of bounds for a program of {} instructions", self.runtime.instructions().len())),
+            Err(_) => Err(format!("The label `{location}` is not defined")),
+        }
+    }
+
+    /// Sets a breakpoint before the instruction at the specified index
+    pub fn add_breakpoint(&mut self, index: usize) {
+        self.breakpoints.insert(index);
+    }
+
+    /// Removes the breakpoint at the specified index, returning whether there was one
+    pub fn remove_breakpoint(&mut self, index: usize) -> bool {
+        self.breakpoints.remove(&index)
+    }
+
+    /// Executes a single instruction, entering calls
+    pub fn step(&mut self) -> Stop {
+        if self.finished {
+            return Stop::Finished(None);
+        }
+        match self.runtime.step() {
+            Ok(Step::Running) => Stop::Paused,
+            Ok(Step::Returned(value)) | Ok(Step::Finished(value)) => {
+                self.finished = true;
+                Stop::Finished(value)
+            }
+            Err(error) => {
+                self.finished = true;
+                Stop::Error(error)
+            }
+        }
+    }
+
+    /// Executes a single instruction, running the functions it calls until they return
+    pub fn step_over(&mut self) -> Stop {
+        let depth = self.runtime.call_stack().frames().len();
+        let mut stop = self.step();
+        while stop == Stop::Paused && self.runtime.call_stack().frames().len() > depth {
+            if self.breakpoints.contains(&self.runtime.next_index()) {
+                return Stop::Breakpoint(self.runtime.next_index());
+            }
+            stop = self.step();
+        }
+        stop
+    }
+
+    /// Runs the program until the next instruction has a breakpoint or the program ends
+    pub fn resume(&mut self) -> Stop {
+        let mut stop = self.step();
+        while stop == Stop::Paused {
+            if self.breakpoints.contains(&self.runtime.next_index()) {
+                return Stop::Breakpoint(self.runtime.next_index());
+            }
+            stop = self.step();
+        }
+        stop
+    }
+
+    /// Reads commands from the input until the user quits, writing the session to the output
+    pub fn run<R: BufRead, W: Write>(&mut self, input: R, mut output: W) -> io::Result<()> {
+        writeln!(output, "Debugging {} instructions, type `help` for the list of commands", self.runtime.instructions().len())?;
+        self.print_next(&mut output)?;
+        let mut last_command = String::new();
+        let mut lines = input.lines();
+        loop {
+            write!(output, "(mirage) ")?;
+            output.flush()?;
+            let Some(line) = lines.next() else { break };
+            let line = line?;
+            let line = if line.trim().is_empty() { last_command.clone() } else { line.trim().to_string() };
+            let mut words = line.split_whitespace();
+            let Some(command) = words.next() else { continue };
+            let argument = words.next();
+            match command {
+                "break" | "b" | "delete" | "d" => match argument.map(|argument| self.location(argument)) {
+                    Some(Ok(index)) => {
+                        if command.starts_with('b') {
+                            self.add_breakpoint(index);
+                            writeln!(output, "Breakpoint set at instruction {index}")?;
+                        } else if self.remove_breakpoint(index) {
+                            writeln!(output, "Breakpoint removed from instruction {index}")?;
+                        } else {
+                            writeln!(output, "There is no breakpoint at instruction {index}")?;
+                        }
+                    }
+                    Some(Err(message)) => writeln!(output, "{message}")?,
+                    None => writeln!(output, "`{command}` requires a label or an instruction index")?,
+                }
+                "breakpoints" | "bl" => {
+                    if self.breakpoints.is_empty() {
+                        writeln!(output, "No breakpoints set")?;
+                    }
+                    for index in &self.breakpoints {
+                        match self.runtime.instructions().get(*index) {
+                            Some(instruction) => writeln!(output, "  {index}: {}", format(instruction))?,
+                            None => writeln!(output, "  {index}: end of the program")?,
+                        }
+                    }
+                }
+                "step" | "s" => {
+                    let stop = self.step();
+                    self.report(stop, &mut output)?;
+                }
+                "next" | "n" => {
+                    let stop = self.step_over();
+                    self.report(stop, &mut output)?;
+                }
+                "continue" | "c" => {
+                    let stop = self.resume();
+                    self.report(stop, &mut output)?;
+                }
+                "list" | "l" => {
+                    let count = argument.and_then(|argument| argument.parse::<usize>().ok()).unwrap_or(5);
+                    self.print_listing(count, &mut output)?;
+                }
+                "registers" | "r" => self.print_registers(&mut output)?,
+                "frame" | "f" => self.print_frame(&mut output)?,
+                "backtrace" | "bt" => self.print_backtrace(&mut output)?,
+                "help" | "h" => writeln!(output, "{HELP}")?,
+                "quit" | "q" => break,
+                _ => writeln!(output, "Unknown command `{command}`, type `help` for the list of commands")?,
+            }
+            last_command = line;
+        }
+        Ok(())
+    }
+
+    fn report<W: Write>(&self, stop: Stop, output: &mut W) -> io::Result<()> {
+        match stop {
+            Stop::Paused => self.print_next(output),
+            Stop::Breakpoint(index) => {
+                writeln!(output, "Breakpoint hit at instruction {index}")?;
+                self.print_next(output)
+            }
+            Stop::Finished(value) => match value {
+                Some(value) => writeln!(output, "The program finished returning {}", value.to_string_debugged()),
+                None => writeln!(output, "The program finished"),
+            }
+            Stop::Error(error) => {
+                writeln!(output, "The program threw an uncaught error")?;
+                writeln!(output, "{}: {}", error.name, error.message)?;
+                writeln!(output, "{}", error.backtrace)
+            }
+        }
+    }
+
+    fn print_next<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        let index = self.runtime.next_index();
+        match self.runtime.instructions().get(index) {
+            Some(instruction) => match self.runtime.debug_info().and_then(|debug_info| debug_info.format_location(index)) {
+                Some(location) => writeln!(output, "=> {index}: {} at {location}", format(instruction)),
+                None => writeln!(output, "=> {index}: {}", format(instruction)),
+            }
+            None => writeln!(output, "=> end of the program"),
+        }
+    }
+
+    fn print_listing<W: Write>(&self, count: usize, output: &mut W) -> io::Result<()> {
+        let next = self.runtime.next_index();
+        let start = next.saturating_sub(count);
+        let instructions = self.runtime.instructions();
+        for (index, instruction) in instructions.iter().enumerate().skip(start).take(count * 2 + 1) {
+            let marker = if index == next { "=>" } else { "  " };
+            let breakpoint = if self.breakpoints.contains(&index) { "*" } else { " " };
+            writeln!(output, "{marker}{breakpoint}{index}: {}", format(instruction))?;
+        }
+        Ok(())
+    }
+
+    fn print_registers<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        let index = self.runtime.next_index();
+        for reg in 0..REGISTER_COUNT {
+            if let Some(value) = self.runtime.registers.get(reg) {
+                match self.runtime.debug_info().and_then(|debug_info| debug_info.alias(index, reg)) {
+                    Some(alias) => writeln!(output, "  r{reg} ({alias}) = {}", value.to_string_debugged())?,
+                    None => writeln!(output, "  r{reg} = {}", value.to_string_debugged())?,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn print_frame<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        let Some(frame) = self.runtime.call_stack().last_frame() else {
+            return writeln!(output, "There is no frame");
+        };
+        writeln!(output, "at {}", frame.name)?;
+        writeln!(output, "  - Arguments:")?;
+        for (name, value) in &frame.args {
+            writeln!(output, "      {name}: {}", value.to_string_debugged())?;
+        }
+        writeln!(output, "  - Local Variables:")?;
+        for (name, value) in &frame.local_variables {
+            writeln!(output, "      {name}: {}", value.to_string_debugged())?;
+        }
+        if let Some(return_addr) = frame.return_addr {
+            writeln!(output, "  - Return Address: {return_addr}")?;
+        }
+        if let Some(handler) = frame.error_handlers.last() {
+            writeln!(output, "  - Error Handling Address: {}", handler.addr)?;
+        }
+        Ok(())
+    }
+
+    fn print_backtrace<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        for (depth, frame) in self.runtime.call_stack().frames().iter().rev().enumerate() {
+            match frame.return_addr {
+                Some(return_addr) => writeln!(output, "  #{depth} {} (returns to {return_addr})", frame.name)?,
+                None => writeln!(output, "  #{depth} {}", frame.name)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Formats an instruction the way it is written in the assembly, falling back to its
+/// debug representation when it holds values that have no literal syntax
+fn format(instruction: &Instruction) -> String {
+    format_instruction(instruction).unwrap_or_else(|_| format!("{:?}", instruction))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::value::IntoValue;
+
+    #[test]
+    fn new_starts_the_runtime() {
+        let source = "move r0 int 1\ndefinelabel middle\nmove r15 int 2";
+        let mut runtime = MirageRuntime::from_source(source, "test.masm").unwrap();
+        let mut debugger = Debugger::new(&mut runtime).unwrap();
+        let middle = debugger.location("middle").unwrap();
+        debugger.add_breakpoint(middle);
+        assert_eq!(debugger.resume(), Stop::Breakpoint(middle));
+        assert_eq!(debugger.resume(), Stop::Finished(Some(2.into_value())));
+    }
+
+    #[test]
+    fn new_reports_link_errors() {
+        let mut runtime = MirageRuntime::from_source("call missing", "test.masm").unwrap();
+        assert_eq!(Debugger::new(&mut runtime).err().map(|error| error.name), Some("LinkError".to_string()));
+    }
+
+    const CALLING: &str = "definefnlabel f 0 int\nmove r15 int 1\nreturn\nendfunction\ndefinelabel before\ncall f\nmove r0 int 2";
+
+    #[test]
+    fn step_enters_calls() {
+        let mut runtime = MirageRuntime::from_source(CALLING, "test.masm").unwrap();
+        let mut debugger = Debugger::new(&mut runtime).unwrap();
+        let call = debugger.location("before").unwrap();
+        debugger.add_breakpoint(call);
+        assert_eq!(debugger.resume(), Stop::Breakpoint(call));
+        assert_eq!(debugger.step(), Stop::Paused);
+        assert_eq!(debugger.runtime.call_stack().frames().len(), 2);
+        assert!(debugger.runtime.next_index() < call);
+    }
+
+    #[test]
+    fn next_steps_over_calls() {
+        let mut runtime = MirageRuntime::from_source(CALLING, "test.masm").unwrap();
+        let mut debugger = Debugger::new(&mut runtime).unwrap();
+        let call = debugger.location("before").unwrap();
+        debugger.add_breakpoint(call);
+        assert_eq!(debugger.resume(), Stop::Breakpoint(call));
+        assert_eq!(debugger.step_over(), Stop::Paused);
+        assert_eq!(debugger.runtime.call_stack().frames().len(), 1);
+        assert_eq!(debugger.runtime.next_index(), call + 1);
+        assert_eq!(debugger.runtime.registers.get(15), Some(&1.into_value()));
+    }
+
+    #[test]
+    fn instructions_are_printed_in_assembly_syntax() {
+        let mut runtime = MirageRuntime::from_source(CALLING, "test.masm").unwrap();
+        let mut debugger = Debugger::new(&mut runtime).unwrap();
+        let call = debugger.location("before").unwrap();
+        let mut output = Vec::new();
+        debugger.run(format!("break {call}\ncontinue\nnext\nquit\n").as_bytes(), &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains(&format!("=> {call}: call f")), "{output}");
+        assert!(output.contains(&format!("=> {}: move r0 int 2", call + 1)), "{output}");
+    }
+}
@@ -98,7 +98,8 @@ pub enum Instruction {
     Ge(usize, usize, usize),
 
     // ########### Other operations
-    /// Returns from the current stack frame
+    /// Returns from the current stack frame, restoring the caller's registers and
+    /// copying the return register (r15) back into the caller's r15
     Return,
 
     /// Sets the value of the specified variable to the value stored at the register specified.
@@ -130,10 +131,10 @@ pub enum Instruction {
     JumpUnconditional(String),
 
     /// Jumps to a label conditionally if the stored value of
-    /// the specified register has its first byte as one
+    /// the specified register is the boolean `true`
     JumpConditional(usize, String),
 
-    /// Calls the specified label
+    /// Calls the specified label. The callee gets its own register window
     /// 
     /// Function label
     Call(String),
@@ -165,4 +166,279 @@ pub enum Instruction {
 
     /// Reads a line from the Stdin and stores it on the specified register
     BufferedStdinRead(usize),
-}
\ No newline at end of file
+
+    // ########### Error handling
+    /// Installs an error handler on the current stack frame. When an error is thrown
+    /// before the matching `EndTry`, execution resumes at the label and the error's
+    /// name and message are stored as strings in the specified registers
+    ///
+    /// LABEL - NAME DST - MESSAGE DST
+    Try(String, usize, usize),
+
+    /// Removes the innermost error handler installed on the current stack frame
+    EndTry,
+
+    // ########### Array operations
+    /// Stores a new empty array in the specified register
+    ///
+    /// DST
+    ArrayNew(usize),
+
+    /// Appends the value stored at the second register to the array stored at the first one
+    ///
+    /// ARRAY - VALUE
+    ArrayPush(usize, usize),
+
+    /// Removes the last element of the array and stores it in the last specified register
+    ///
+    /// ARRAY - DST
+    ArrayPop(usize, usize),
+
+    /// Stores the element of the array at the specified index in the last specified register
+    ///
+    /// ARRAY - INDEX - DST
+    ArrayGet(usize, usize, usize),
+
+    /// Replaces the element of the array at the specified index with the specified value
+    ///
+    /// ARRAY - INDEX - VALUE
+    ArraySet(usize, usize, usize),
+
+    /// Stores the length of the array in the last specified register
+    ///
+    /// ARRAY - DST
+    ArrayLen(usize, usize),
+
+    /// Stores a copy of the elements of the array between the start (inclusive)
+    /// and the end (exclusive) indexes in the last specified register
+    ///
+    /// ARRAY - START - END - DST
+    ArraySlice(usize, usize, usize, usize),
+
+    // ########### Map operations
+    /// Stores a new empty map in the specified register
+    ///
+    /// DST
+    MapNew(usize),
+
+    /// Inserts the value with the specified key in the map, replacing the previous value of the key
+    ///
+    /// MAP - KEY - VALUE
+    MapInsert(usize, usize, usize),
+
+    /// Stores the value of the specified key in the last specified register.
+    /// Throws a `KeyError` if the key is not present.
+    ///
+    /// MAP - KEY - DST
+    MapGet(usize, usize, usize),
+
+    /// Removes the specified key from the map and stores its value in the last specified register.
+    /// Throws a `KeyError` if the key is not present.
+    ///
+    /// MAP - KEY - DST
+    MapRemove(usize, usize, usize),
+
+    /// Stores whether the map contains the specified key in the last specified register
+    ///
+    /// MAP - KEY - DST
+    MapContains(usize, usize, usize),
+
+    /// Stores an array with the keys of the map in the last specified register
+    ///
+    /// MAP - DST
+    MapKeys(usize, usize),
+
+    /// Stores the number of entries of the map in the last specified register
+    ///
+    /// MAP - DST
+    MapLen(usize, usize),
+
+    // ########### Class operations
+    /// Starts the definition of a class blueprint, which ends at `EndClass`.
+    /// Functions defined inside of it are its methods.
+    ///
+    /// Class name
+    DefineClass(String),
+
+    /// Declares a field of the class being defined
+    ///
+    /// Field name - Field type
+    ClassField(String, MiType),
+
+    /// Ends the definition of a class blueprint
+    EndClass,
+
+    /// Instantiates the specified class and stores the instance in the specified register.
+    /// All the fields of the instance start as `None`.
+    ///
+    /// Class name - DST
+    New(String, usize),
+
+    /// Stores the value of a field of the instance in the last specified register
+    ///
+    /// OBJECT - Field name - DST
+    GetField(usize, String, usize),
+
+    /// Sets the value of a field of the instance stored at the first specified register
+    ///
+    /// OBJECT - Field name - SRC
+    SetField(usize, String, usize),
+
+    /// Calls a method of the instance stored at the specified register. The instance is
+    /// bound to the `self` argument, and the fields the method sets on it are written back
+    /// to the register when the method returns
+    ///
+    /// OBJECT - Method name
+    CallMethod(usize, String),
+
+    // ########### Type operations
+    /// Converts the value stored at the first register to the specified type and stores
+    /// the result in the last specified register. Throws a `ConversionError` if the value
+    /// cannot be converted.
+    ///
+    /// SRC - TYPE - DST
+    Cast(usize, MiType, usize),
+
+    /// Stores the name of the type of the value stored at the first register as a string
+    /// in the last specified register
+    ///
+    /// SRC - DST
+    TypeOf(usize, usize),
+}
+impl Instruction {
+    /// Returns the registers the instruction reads or writes, in operand order
+    pub fn registers(&self) -> Vec<usize> {
+        match self {
+            Instruction::Move(reg, _)
+            | Instruction::MoveArgument(_, reg)
+            | Instruction::MoveAsArgument(reg)
+            | Instruction::SetVariable(reg, _)
+            | Instruction::MovFromVariable(_, reg)
+            | Instruction::JumpConditional(reg, _)
+            | Instruction::StdoutWrite(reg)
+            | Instruction::StdoutWriteDebugged(reg)
+            | Instruction::StderrWrite(reg)
+            | Instruction::StderrWriteDebugged(reg)
+            | Instruction::BufferedStdinRead(reg)
+            | Instruction::ArrayNew(reg)
+            | Instruction::MapNew(reg)
+            | Instruction::New(_, reg)
+            | Instruction::CallMethod(reg, _) => vec![*reg],
+            Instruction::MoveBetween(op1, op2)
+            | Instruction::Not(op1, op2)
+            | Instruction::ThrowFrom(op1, op2)
+            | Instruction::Try(_, op1, op2)
+            | Instruction::ArrayPush(op1, op2)
+            | Instruction::ArrayPop(op1, op2)
+            | Instruction::ArrayLen(op1, op2)
+            | Instruction::MapKeys(op1, op2)
+            | Instruction::MapLen(op1, op2)
+            | Instruction::GetField(op1, _, op2)
+            | Instruction::SetField(op1, _, op2)
+            | Instruction::Cast(op1, _, op2)
+            | Instruction::TypeOf(op1, op2) => vec![*op1, *op2],
+            Instruction::Add(op1, op2, dst)
+            | Instruction::Sub(op1, op2, dst)
+            | Instruction::Mul(op1, op2, dst)
+            | Instruction::Div(op1, op2, dst)
+            | Instruction::Rem(op1, op2, dst)
+            | Instruction::Pow(op1, op2, dst)
+            | Instruction::Or(op1, op2, dst)
+            | Instruction::Xor(op1, op2, dst)
+            | Instruction::And(op1, op2, dst)
+            | Instruction::Lt(op1, op2, dst)
+            | Instruction::Le(op1, op2, dst)
+            | Instruction::Gt(op1, op2, dst)
+            | Instruction::Ge(op1, op2, dst)
+            | Instruction::Eq(op1, op2, dst)
+            | Instruction::Ne(op1, op2, dst)
+            | Instruction::ArrayGet(op1, op2, dst)
+            | Instruction::ArraySet(op1, op2, dst)
+            | Instruction::MapInsert(op1, op2, dst)
+            | Instruction::MapGet(op1, op2, dst)
+            | Instruction::MapRemove(op1, op2, dst)
+            | Instruction::MapContains(op1, op2, dst) => vec![*op1, *op2, *dst],
+            Instruction::ArraySlice(arr, start, end, dst) => vec![*arr, *start, *end, *dst],
+            Instruction::Return
+            | Instruction::DefineLabel(_)
+            | Instruction::JumpUnconditional(_)
+            | Instruction::Call(_)
+            | Instruction::DefineFnLabel(..)
+            | Instruction::EndFunction
+            | Instruction::StdoutFlush
+            | Instruction::StderrFlush
+            | Instruction::EndTry
+            | Instruction::DefineClass(_)
+            | Instruction::ClassField(..)
+            | Instruction::EndClass => vec![],
+        }
+    }
+
+    /// Returns the `.masm` keyword of the instruction
+    pub fn opcode(&self) -> &'static str {
+        match self {
+            Instruction::Move(..) => "move",
+            Instruction::MoveBetween(..) => "movebetween",
+            Instruction::MoveArgument(..) => "moveargument",
+            Instruction::MoveAsArgument(_) => "moveasargument",
+            Instruction::Add(..) => "add",
+            Instruction::Sub(..) => "sub",
+            Instruction::Mul(..) => "mul",
+            Instruction::Div(..) => "div",
+            Instruction::Rem(..) => "rem",
+            Instruction::Pow(..) => "pow",
+            Instruction::Or(..) => "or",
+            Instruction::Xor(..) => "xor",
+            Instruction::And(..) => "and",
+            Instruction::Not(..) => "not",
+            Instruction::Lt(..) => "lt",
+            Instruction::Le(..) => "le",
+            Instruction::Gt(..) => "gt",
+            Instruction::Ge(..) => "ge",
+            Instruction::Return => "return",
+            Instruction::SetVariable(..) => "setvariable",
+            Instruction::MovFromVariable(..) => "movfromvariable",
+            Instruction::ThrowFrom(..) => "throwfrom",
+            Instruction::Eq(..) => "eq",
+            Instruction::Ne(..) => "ne",
+            Instruction::DefineLabel(_) => "definelabel",
+            Instruction::JumpUnconditional(_) => "jumpunc",
+            Instruction::JumpConditional(..) => "jumpc",
+            Instruction::Call(_) => "call",
+            Instruction::DefineFnLabel(..) => "definefnlabel",
+            Instruction::EndFunction => "endfunction",
+            Instruction::StdoutWrite(_) => "stdoutwrite",
+            Instruction::StdoutWriteDebugged(_) => "stdoutwritedebugged",
+            Instruction::StdoutFlush => "stdoutflush",
+            Instruction::StderrWrite(_) => "stderrwrite",
+            Instruction::StderrWriteDebugged(_) => "stderrwritedebugged",
+            Instruction::StderrFlush => "stderrflush",
+            Instruction::BufferedStdinRead(_) => "bufferedstdinread",
+            Instruction::Try(..) => "try",
+            Instruction::EndTry => "endtry",
+            Instruction::ArrayNew(_) => "arraynew",
+            Instruction::ArrayPush(..) => "arraypush",
+            Instruction::ArrayPop(..) => "arraypop",
+            Instruction::ArrayGet(..) => "arrayget",
+            Instruction::ArraySet(..) => "arrayset",
+            Instruction::ArrayLen(..) => "arraylen",
+            Instruction::ArraySlice(..) => "arrayslice",
+            Instruction::MapNew(_) => "mapnew",
+            Instruction::MapInsert(..) => "mapinsert",
+            Instruction::MapGet(..) => "mapget",
+            Instruction::MapRemove(..) => "mapremove",
+            Instruction::MapContains(..) => "mapcontains",
+            Instruction::MapKeys(..) => "mapkeys",
+            Instruction::MapLen(..) => "maplen",
+            Instruction::DefineClass(_) => "defineclass",
+            Instruction::ClassField(..) => "field",
+            Instruction::EndClass => "endclass",
+            Instruction::New(..) => "new",
+            Instruction::GetField(..) => "getfield",
+            Instruction::SetField(..) => "setfield",
+            Instruction::CallMethod(..) => "callmethod",
+            Instruction::Cast(..) => "cast",
+            Instruction::TypeOf(..) => "typeof",
+        }
+    }
+}
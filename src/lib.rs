@@ -0,0 +1,52 @@
+pub mod stack;
+pub mod value;
+pub mod class;
+pub mod function;
+pub mod result;
+pub mod args;
+pub mod instructions;
+pub mod limits;
+pub mod runtime;
+pub mod meta;
+pub mod builtins;
+pub mod debug_info;
+pub mod debugger;
+pub mod operations;
+pub mod profiler;
+pub mod registers;
+pub mod tracer;
+pub mod verifier;
+pub mod assembly;
+
+pub const MIRAGE_VERSION: &str = "1.2.1";
+
+#[macro_export]
+macro_rules! error_println {
+    ($($args:expr),*) => {
+        eprintln!("{} {}", ansi_term::Color::Red.bold().paint("Error:"), format_args!($($args),*))
+    };
+}
+
+#[macro_export]
+macro_rules! note_println {
+    ($($args:expr),*) => {
+        eprintln!("{} {}", ansi_term::Color::White.bold().paint("Note:"), format_args!($($args),*))
+    };
+}
+
+#[macro_export]
+macro_rules! example_println {
+    ($($args:expr),*) => {
+        let input = format!($($args),*);
+        let lines: Vec<String> = input.lines().map(|line| format!("+ {}", ansi_term::Color::Green.paint(line))).collect();
+        let output = lines.join("\n");
+        eprintln!("{}", output);
+    };
+}
+
+#[macro_export]
+macro_rules! warning_println {
+    ($($args:expr),*) => {
+        eprintln!("{} {}", ansi_term::Color::Yellow.bold().paint("Warning:"), format_args!($($args),*))
+    };
+}
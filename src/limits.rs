@@ -0,0 +1,114 @@
+use std::mem::size_of;
+use std::rc::Rc;
+
+use fxhash::FxHashSet;
+
+use crate::value::{MapKey, MiValue};
+
+/// How many instructions are executed between two checks of `max_value_bytes`
+pub const MEMORY_CHECK_INTERVAL: u64 = 1024;
+
+/// Caps on the resources a program may use, checked while it runs.
+///
+/// Exceeding `max_instructions` or `max_value_bytes` raises a fatal error that error
+/// handlers cannot catch, while exceeding `max_call_depth` or `max_argument_stack`
+/// throws a `StackOverflow` or `ArgumentStackOverflow` the program can catch.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct RuntimeLimits {
+    /// Maximum number of instructions executed by the runtime
+    pub max_instructions: Option<u64>,
+    /// Maximum number of stack frames, the main one included
+    pub max_call_depth: usize,
+    /// Maximum number of values waiting on the argument stack
+    pub max_argument_stack: Option<usize>,
+    /// Maximum number of bytes taken by the values reachable from the registers,
+    /// the stack frames and the argument stack. Checked every `MEMORY_CHECK_INTERVAL`
+    /// instructions, and earlier when an instruction operates on a large value.
+    pub max_value_bytes: Option<usize>,
+}
+
+impl Default for RuntimeLimits {
+    fn default() -> Self {
+        Self {
+            max_instructions: None,
+            max_call_depth: 4000,
+            max_argument_stack: None,
+            max_value_bytes: None,
+        }
+    }
+}
+
+/// Returns the bytes taken by the value and its direct contents, without following
+/// nested values. Cheap enough to call on every instruction.
+pub fn shallow_bytes(value: &MiValue) -> usize {
+    size_of::<MiValue>() + match value {
+        MiValue::Str(string) => string.len(),
+        MiValue::Array(array) => array.len() * size_of::<MiValue>(),
+        MiValue::Map(map) => map.len() * (size_of::<MapKey>() + size_of::<MiValue>()),
+        MiValue::Class(class) => class.properties.len() * size_of::<MiValue>(),
+        MiValue::Int(_) | MiValue::Float(_) | MiValue::Bool(_) | MiValue::Function(_) | MiValue::None => 0,
+    }
+}
+
+/// Adds up the bytes taken by values, counting the contents shared between
+/// values only once
+#[derive(Default)]
+pub struct ByteCounter {
+    seen: FxHashSet<*const ()>,
+    total: usize,
+}
+
+impl ByteCounter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the bytes counted so far
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    /// Counts the value and the contents it references
+    pub fn count(&mut self, value: &MiValue) {
+        self.total += size_of::<MiValue>();
+        match value {
+            MiValue::Str(string) => {
+                if self.first_seen(Rc::as_ptr(string) as *const ()) {
+                    self.total += string.len();
+                }
+            }
+            MiValue::Array(array) => {
+                if self.first_seen(Rc::as_ptr(array) as *const ()) {
+                    for element in array.iter() {
+                        self.count(element);
+                    }
+                }
+            }
+            MiValue::Map(map) => {
+                if self.first_seen(Rc::as_ptr(map) as *const ()) {
+                    for (key, value) in map.iter() {
+                        self.total += size_of::<MapKey>();
+                        if let MapKey::String(key) = key {
+                            self.total += key.len();
+                        }
+                        self.count(value);
+                    }
+                }
+            }
+            MiValue::Class(class) => {
+                if self.first_seen(Rc::as_ptr(class) as *const ()) {
+                    self.total += class.name.len();
+                    for (name, value) in &class.properties {
+                        self.total += name.len();
+                        self.count(value);
+                    }
+                }
+            }
+            MiValue::Int(_) | MiValue::Float(_) | MiValue::Bool(_) | MiValue::Function(_) | MiValue::None => {}
+        }
+    }
+
+    fn first_seen(&mut self, pointer: *const ()) -> bool {
+        self.seen.insert(pointer)
+    }
+}
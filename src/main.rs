@@ -1,68 +1,27 @@
-pub mod stack;
-pub mod value;
-pub mod class;
-pub mod function;
-pub mod result;
-pub mod args;
-pub mod instructions;
-pub mod runtime;
-pub mod meta;
-pub mod builtins;
-pub mod registers;
-pub mod assembly;
-
-use std::{fs::File, io::{Write, stdout, stderr, Read}, time::SystemTime, process::ExitCode};
-use instructions::Instruction;
-use meta::{Metadata, Manifest};
-use result::MiResult;
-use runtime::MirageRuntime;
-use value::IntoValue;
+use std::{fs::File, io::{Write, BufWriter, stdout, stderr, stdin, Read}, time::SystemTime, process::ExitCode, str::FromStr};
+use mirage::meta::{Metadata, Manifest};
+use mirage::debugger::Debugger;
+use mirage::limits::RuntimeLimits;
+use mirage::tracer::TraceFormat;
+use mirage::runtime::MirageRuntime;
+use mirage::verifier::verify;
+use mirage::{assembly, error_println, note_println, MIRAGE_VERSION};
 use ansi_term::Color;
-use std::process::exit;
-use std::time::Instant;
 use std::env::args;
 
-const MIRAGE_VERSION: &'static str = "1.2.1";
-
-use crate::value::{MiValue, MiType};
-
-#[macro_export]
-macro_rules! error_println {
-    ($($args:expr),*) => {
-        eprintln!("{} {}", Color::Red.bold().paint("Error:"), format_args!($($args),*))
-    };
-}
-
-#[macro_export]
-macro_rules! note_println {
-    ($($args:expr),*) => {
-        eprintln!("{} {}", Color::White.bold().paint("Note:"), format_args!($($args),*))
-    };
-}
-
-#[macro_export]
-macro_rules! example_println {
-    ($($args:expr),*) => {
-        let input = format!($($args),*);
-        let lines: Vec<String> = input.lines().map(|line| format!("+ {}", ansi_term::Color::Green.paint(line))).collect();
-        let output = lines.join("\n");
-        eprintln!("{}", output);
-    };
-}
-
-#[macro_export]
-macro_rules! warning_println {
-    ($($args:expr),*) => {
-        eprintln!("{} {}", ansi_term::Color::Yellow.bold().paint("Warning:"), format_args!($($args),*))
-    };
-}
-
 fn main() -> ExitCode {
-    let mut instant: Instant = Instant::now();
     let mut option = String::new();
     let mut input = String::new();
     let mut output = String::new();
     let mut asm = false;
+    let mut debug = false;
+    let mut embed_source = false;
+    let mut profile = false;
+    let mut profile_output: Option<String> = None;
+    let mut limits = RuntimeLimits::default();
+    let mut trace = false;
+    let mut trace_output: Option<String> = None;
+    let mut trace_format = TraceFormat::Human;
 
     let mut args = args().skip(1);
 
@@ -77,6 +36,62 @@ fn main() -> ExitCode {
                 "--asm" => {
                     asm = true;
                 }
+                "--debug" => {
+                    debug = true;
+                }
+                "--embed-source" => {
+                    debug = true;
+                    embed_source = true;
+                }
+                "--max-instructions" => match limit_argument(&arg, args.next()) {
+                    Ok(max) => limits.max_instructions = Some(max),
+                    Err(code) => return code,
+                }
+                "--max-call-depth" => match limit_argument(&arg, args.next()) {
+                    Ok(0) => {
+                        error_println!("{arg} must be at least 1");
+                        note_println!("the main frame counts towards the call depth");
+                        return ExitCode::FAILURE
+                    }
+                    Ok(max) => limits.max_call_depth = max,
+                    Err(code) => return code,
+                }
+                "--max-argument-stack" => match limit_argument(&arg, args.next()) {
+                    Ok(max) => limits.max_argument_stack = Some(max),
+                    Err(code) => return code,
+                }
+                "--max-value-bytes" => match limit_argument(&arg, args.next()) {
+                    Ok(max) => limits.max_value_bytes = Some(max),
+                    Err(code) => return code,
+                }
+                "--trace" => {
+                    trace = true;
+                }
+                _ if arg.starts_with("--trace=") => {
+                    trace = true;
+                    trace_output = Some(arg["--trace=".len()..].to_string());
+                }
+                "--trace-format" => match args.next().as_deref() {
+                    Some("human") => trace_format = TraceFormat::Human,
+                    Some("json") => trace_format = TraceFormat::Json,
+                    Some(format) => {
+                        error_println!("Unknown trace format `{format}`");
+                        note_println!("the trace format is either `human` or `json`");
+                        return ExitCode::FAILURE
+                    }
+                    None => {
+                        error_println!("--trace-format requires an argument");
+                        note_println!("provide an argument like --trace-format json");
+                        return ExitCode::FAILURE
+                    }
+                }
+                "--profile" => {
+                    profile = true;
+                }
+                _ if arg.starts_with("--profile=") => {
+                    profile = true;
+                    profile_output = Some(arg["--profile=".len()..].to_string());
+                }
                 "-i" => match args.next() {
                     Some(arg) => {
                         if input.as_str() != "" {
@@ -114,6 +129,20 @@ fn main() -> ExitCode {
                     }
                     option = arg;
                 }
+                "debug" => {
+                    if option != String::new() {
+                        error_println!("The main option can only be used once");
+                        return ExitCode::FAILURE
+                    }
+                    option = arg;
+                }
+                "disasm" => {
+                    if option != String::new() {
+                        error_println!("The main option can only be used once");
+                        return ExitCode::FAILURE
+                    }
+                    option = arg;
+                }
                 "build" => {
                     if option != String::new() {
                         error_println!("The main option can only be used once");
@@ -158,61 +187,62 @@ fn main() -> ExitCode {
                                         let mut main_file_string = String::new();
                                         match file.read_to_string(&mut main_file_string) {
                                             Ok(_) => {
-                                                let tokens = assembly::tokens::tokenize(&main_file_string, &manifest.main_file);
-                                                match tokens {
-                                                    Ok(tokens) => {
-                                                        let mut parser = assembly::parser::Parser::new(tokens);
-                                                        match parser.parse() {
-                                                            Ok(instructions) => {
-                                                                let length = instructions.len();
-                                                                let metadata = Metadata {
-                                                                    package: manifest.package,
-                                                                    version: manifest.version,
-                                                                    timestamp: SystemTime::now(),
-                                                                    description: manifest.description.unwrap_or(String::new()),
-                                                                    author: manifest.author,
-                                                                    debug: false,
-                                                                    instructions,
-                                                                    source_code: None,
-                                                                    license: Some(manifest.license),
-                                                                    total_instructions: length,
-                                                                    compiled_version: MIRAGE_VERSION.to_string(),
-                                                                };
-                                                                match File::create(&output) {
-                                                                    Ok(mut file) => {
-                                                                        let converted = bincode::serialize(&metadata);
-                                                                        match converted {
-                                                                            Ok(converted) => {
-                                                                                match file.write_all(&converted) {
-                                                                                    Ok(_) => {
-                                                                                        return ExitCode::SUCCESS
-                                                                                    }
-                                                                                    Err(err) => {
-                                                                                        error_println!("Failed to write bytes to file: {err}");
-                                                                                        return ExitCode::FAILURE
-                                                                                    }
-                                                                                }
+                                                let mut assembler = assembly::Assembler::new();
+                                                let assembled = assembler.assemble(&main_file_string, &manifest.main_file)
+                                                    .map(|(instructions, mut debug_info)| {
+                                                        if !embed_source {
+                                                            debug_info.files.iter_mut().for_each(|file| file.source = None);
+                                                        }
+                                                        (instructions, if debug { Some(debug_info) } else { None })
+                                                    });
+                                                match assembled {
+                                                    Ok((instructions, debug_info)) => {
+                                                        let length = instructions.len();
+                                                        let metadata = Metadata {
+                                                            package: manifest.package,
+                                                            version: manifest.version,
+                                                            timestamp: SystemTime::now(),
+                                                            description: manifest.description.unwrap_or(String::new()),
+                                                            author: manifest.author,
+                                                            instructions,
+                                                            debug_info,
+                                                            license: Some(manifest.license),
+                                                            total_instructions: length,
+                                                            compiled_version: MIRAGE_VERSION.to_string(),
+                                                        };
+                                                        match File::create(&output) {
+                                                            Ok(mut file) => {
+                                                                let converted = metadata.to_bytes();
+                                                                match converted {
+                                                                    Ok(converted) => {
+                                                                        match file.write_all(&converted) {
+                                                                            Ok(_) => {
+                                                                                return ExitCode::SUCCESS
                                                                             }
                                                                             Err(err) => {
-                                                                                error_println!("Failed to serialize file metadata: {err}");
+                                                                                error_println!("Failed to write bytes to file: {err}");
                                                                                 return ExitCode::FAILURE
                                                                             }
                                                                         }
                                                                     }
                                                                     Err(err) => {
-                                                                        error_println!("Failed to create output file: {err}");
+                                                                        error_println!("Failed to serialize file metadata: {err}");
                                                                         return ExitCode::FAILURE
                                                                     }
                                                                 }
                                                             }
                                                             Err(err) => {
-                                                                error_println!("Error parsing: {err}");
+                                                                error_println!("Failed to create output file: {err}");
                                                                 return ExitCode::FAILURE
                                                             }
                                                         }
                                                     }
-                                                    Err(err) => {
-                                                        error_println!("{err}");
+                                                    Err(diagnostics) => {
+                                                        eprint!("{}", assembler.render_diagnostics(&diagnostics, true));
+                                                        error_println!(
+                                                            "could not assemble `{}` due to {} previous {}",
+                                                            &manifest.main_file, diagnostics.len(), if diagnostics.len() == 1 { "error" } else { "errors" }
+                                                        );
                                                         return ExitCode::FAILURE
                                                     }
                                                 }
@@ -247,50 +277,178 @@ fn main() -> ExitCode {
             }
         }
     } else if &option == "run" {
-        match File::open(input) {
-            Ok(mut file) => {
-                let mut input_contents = Vec::new();
-                match file.read_to_end(&mut input_contents) {
-                    Ok(_) => {
-                        let metadata = bincode::deserialize::<Metadata>(&input_contents);
-                        match metadata {
-                            Ok(metadata) => {
-                                let mut runtime = MirageRuntime::new(metadata.instructions);
-                                runtime.setup();
-                                match runtime.run() {
-                                    Ok(_) => {
-                                        print!("\n");
-                                        return ExitCode::SUCCESS;
-                                    }
-                                    Err(error) => {
-                                        stdout().flush().unwrap();
-                                        stderr().flush().unwrap();
-                                        eprintln!("\n{} {}", Color::Red.bold().paint("Error:"), error.name);
-                                        eprintln!("{} {}", Color::Green.bold().paint("Message:"), error.message);
-                                        eprintln!("Stack Backtrace:");
-                                        eprintln!("{}", error.backtrace);
-                                    }
-                                }
-                            }
-                            Err(err) => {
-                                error_println!("Failed to decode the binary file metadata (invalid format)");
-                            }
-                        }
-                        return ExitCode::SUCCESS
-                    }
+        let mut runtime = match load_runtime(&input) {
+            Ok(runtime) => runtime,
+            Err(code) => return code,
+        };
+        runtime.set_limits(limits);
+        if profile {
+            runtime.enable_profiling();
+        }
+        if trace {
+            match &trace_output {
+                Some(path) => match File::create(path) {
+                    Ok(file) => runtime.enable_tracing(BufWriter::new(file), trace_format),
                     Err(err) => {
-                        error_println!("Failed to read from input file: {err}");
+                        error_println!("Failed to create the trace file `{path}`: {err}");
                         return ExitCode::FAILURE
                     }
                 }
+                None => runtime.enable_tracing(stderr(), trace_format),
+            }
+        }
+        let result = runtime.run();
+        if let Err(err) = runtime.flush_trace() {
+            error_println!("Failed to write the trace: {err}");
+        }
+        if let Some(profiler) = runtime.profiler() {
+            stdout().flush().unwrap();
+            match &profile_output {
+                Some(path) => {
+                    if let Err(err) = std::fs::write(path, profiler.collapsed_stacks()) {
+                        error_println!("Failed to write the profile to `{path}`: {err}");
+                        return ExitCode::FAILURE
+                    }
+                }
+                None => eprintln!("\n{}", profiler.report()),
+            }
+        }
+        match result {
+            Ok(_) => {
+                print!("\n");
+                return ExitCode::SUCCESS;
+            }
+            Err(error) => {
+                stdout().flush().unwrap();
+                stderr().flush().unwrap();
+                eprintln!("\n{} {}", Color::Red.bold().paint("Error:"), error.name);
+                eprintln!("{} {}", Color::Green.bold().paint("Message:"), error.message);
+                eprintln!("Stack Backtrace:");
+                eprintln!("{}", error.backtrace);
+            }
+        }
+        return ExitCode::SUCCESS
+    } else if &option == "debug" {
+        let mut runtime = match load_runtime(&input) {
+            Ok(runtime) => runtime,
+            Err(code) => return code,
+        };
+        runtime.set_limits(limits);
+        let mut debugger = match Debugger::new(&mut runtime) {
+            Ok(debugger) => debugger,
+            Err(error) => {
+                error_println!("{}", error.message);
+                return ExitCode::FAILURE
+            }
+        };
+        match debugger.run(stdin().lock(), stdout()) {
+            Ok(_) => {
+                return ExitCode::SUCCESS
+            }
+            Err(err) => {
+                error_println!("Debugger input/output failed: {err}");
+                return ExitCode::FAILURE
+            }
+        }
+    } else if &option == "disasm" {
+        let mut input_contents = Vec::new();
+        match File::open(&input) {
+            Ok(mut file) => {
+                if let Err(err) = file.read_to_end(&mut input_contents) {
+                    error_println!("Failed to read from input file: {err}");
+                    return ExitCode::FAILURE
+                }
             }
             Err(err) => {
                 error_println!("Failed to open input file: {err}");
                 return ExitCode::FAILURE
             }
         }
+        let metadata = match Metadata::from_bytes(&input_contents) {
+            Ok(metadata) => metadata,
+            Err(err) => {
+                error_println!("Failed to decode the binary file: {err}");
+                return ExitCode::FAILURE
+            }
+        };
+        let source = match assembly::disasm::disassemble_metadata(&metadata) {
+            Ok(source) => source,
+            Err(err) => {
+                error_println!("Failed to disassemble: {err}");
+                return ExitCode::FAILURE
+            }
+        };
+        if output.is_empty() {
+            print!("{source}");
+        } else if let Err(err) = std::fs::write(&output, source) {
+            error_println!("Failed to write the output file: {err}");
+            return ExitCode::FAILURE
+        }
+        ExitCode::SUCCESS
     } else {
         error_println!("Unknown option: {}", option);
         return ExitCode::FAILURE
     }
 }
+
+/// Reads, decodes, checks, verifies and links the binary at the specified path,
+/// printing why when it cannot be run
+fn load_runtime(input: &str) -> Result<MirageRuntime<'static>, ExitCode> {
+    let mut input_contents = Vec::new();
+    match File::open(input) {
+        Ok(mut file) => {
+            if let Err(err) = file.read_to_end(&mut input_contents) {
+                error_println!("Failed to read from input file: {err}");
+                return Err(ExitCode::FAILURE)
+            }
+        }
+        Err(err) => {
+            error_println!("Failed to open input file: {err}");
+            return Err(ExitCode::FAILURE)
+        }
+    }
+    let metadata = match Metadata::from_bytes(&input_contents) {
+        Ok(metadata) => metadata,
+        Err(err) => {
+            error_println!("Failed to decode the binary file: {err}");
+            return Err(ExitCode::FAILURE)
+        }
+    };
+    if let Err(err) = metadata.check_compatibility() {
+        error_println!("{err}");
+        note_println!("rebuild the program with this version of mirage");
+        return Err(ExitCode::FAILURE)
+    }
+    if let Err(errors) = verify(&metadata.instructions) {
+        for error in errors {
+            error_println!("{error}");
+        }
+        return Err(ExitCode::FAILURE)
+    }
+    let mut runtime = MirageRuntime::from_metadata(metadata);
+    if let Err(error) = runtime.link() {
+        for line in error.message.lines() {
+            error_println!("{line}");
+        }
+        return Err(ExitCode::FAILURE)
+    }
+    Ok(runtime)
+}
+
+/// Parses the value of a limit option, printing why when it is missing or invalid
+fn limit_argument<T: FromStr>(option: &str, value: Option<String>) -> Result<T, ExitCode> {
+    match value {
+        Some(value) => match value.parse::<T>() {
+            Ok(max) => Ok(max),
+            Err(_) => {
+                error_println!("{option} requires a non-negative integer, found `{value}`");
+                Err(ExitCode::FAILURE)
+            }
+        }
+        None => {
+            error_println!("{option} requires an argument");
+            note_println!("provide an argument like {option} 1000");
+            Err(ExitCode::FAILURE)
+        }
+    }
+}
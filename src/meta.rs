@@ -2,7 +2,23 @@ use std::time::SystemTime;
 
 use serde_derive::{Serialize, Deserialize};
 
+use crate::debug_info::{DebugInfo, SourceFile, SourceLocation};
 use crate::instructions::Instruction;
+use crate::value::{IntoValue, MiType, MiValue};
+use crate::MIRAGE_VERSION;
+
+/// The bytes every `.mirage` binary starts with
+pub const MAGIC: [u8; 4] = *b"MIRG";
+
+/// The version of the layout of the encoded `Metadata`. It must be increased on every
+/// change to `Metadata` or the types it contains, adding a migration from the previous layout.
+///
+/// Version 1 is the layout written before binaries had a header, version 2 the one
+/// before debug info held register aliases.
+pub const FORMAT_VERSION: u16 = 3;
+
+/// Magic bytes, format version, payload length and payload checksum
+const HEADER_LEN: usize = 4 + 2 + 8 + 4;
 
 #[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
 pub struct Metadata {
@@ -10,15 +26,297 @@ pub struct Metadata {
     pub version: Option<String>,
     pub timestamp: SystemTime,
     pub author: Option<String>,
-    pub debug: bool,
     pub instructions: Vec<Instruction>,
-    pub source_code: Option<String>,
+    /// Line table and optionally the sources, present in builds made with `--debug`
+    pub debug_info: Option<DebugInfo>,
     pub description: String,
     pub license: Option<String>,
     pub total_instructions: usize,
     pub compiled_version: String,
 }
 
+/// The metadata as encoded by format version 1
+#[derive(Deserialize)]
+struct MetadataV1 {
+    package: String,
+    version: Option<String>,
+    timestamp: SystemTime,
+    author: Option<String>,
+    _debug: bool,
+    instructions: Vec<InstructionV1>,
+    _source_code: Option<String>,
+    description: String,
+    license: Option<String>,
+    total_instructions: usize,
+    compiled_version: String,
+}
+
+/// The instructions as encoded by format version 1, before the error handling, collection
+/// and class instructions were added and before values were a tagged enum
+#[derive(Deserialize)]
+enum InstructionV1 {
+    Move(usize, MiValueV1),
+    MoveBetween(usize, usize),
+    MoveArgument(String, usize),
+    MoveAsArgument(usize),
+    Add(usize, usize, usize),
+    Sub(usize, usize, usize),
+    Mul(usize, usize, usize),
+    Div(usize, usize, usize),
+    Rem(usize, usize, usize),
+    Pow(usize, usize, usize),
+    Or(usize, usize, usize),
+    Xor(usize, usize, usize),
+    And(usize, usize, usize),
+    Not(usize, usize),
+    Lt(usize, usize, usize),
+    Le(usize, usize, usize),
+    Gt(usize, usize, usize),
+    Ge(usize, usize, usize),
+    Return,
+    SetVariable(usize, String),
+    MovFromVariable(String, usize),
+    ThrowFrom(usize, usize),
+    Eq(usize, usize, usize),
+    Ne(usize, usize, usize),
+    DefineLabel(String),
+    JumpUnconditional(String),
+    JumpConditional(usize, String),
+    Call(String),
+    DefineFnLabel(String, Vec<String>, MiType),
+    EndFunction,
+    StdoutWrite(usize),
+    StdoutWriteDebugged(usize),
+    StdoutFlush,
+    StderrWrite(usize),
+    StderrWriteDebugged(usize),
+    StderrFlush,
+    BufferedStdinRead(usize),
+}
+
+impl InstructionV1 {
+    fn upgrade(self) -> Result<Instruction, String> {
+        Ok(match self {
+            InstructionV1::Move(reg, value) => Instruction::Move(reg, value.upgrade()?),
+            InstructionV1::MoveBetween(src, dst) => Instruction::MoveBetween(src, dst),
+            InstructionV1::MoveArgument(arg, dst) => Instruction::MoveArgument(arg, dst),
+            InstructionV1::MoveAsArgument(src) => Instruction::MoveAsArgument(src),
+            InstructionV1::Add(op1, op2, dst) => Instruction::Add(op1, op2, dst),
+            InstructionV1::Sub(op1, op2, dst) => Instruction::Sub(op1, op2, dst),
+            InstructionV1::Mul(op1, op2, dst) => Instruction::Mul(op1, op2, dst),
+            InstructionV1::Div(op1, op2, dst) => Instruction::Div(op1, op2, dst),
+            InstructionV1::Rem(op1, op2, dst) => Instruction::Rem(op1, op2, dst),
+            InstructionV1::Pow(op1, op2, dst) => Instruction::Pow(op1, op2, dst),
+            InstructionV1::Or(op1, op2, dst) => Instruction::Or(op1, op2, dst),
+            InstructionV1::Xor(op1, op2, dst) => Instruction::Xor(op1, op2, dst),
+            InstructionV1::And(op1, op2, dst) => Instruction::And(op1, op2, dst),
+            InstructionV1::Not(src, dst) => Instruction::Not(src, dst),
+            InstructionV1::Lt(op1, op2, dst) => Instruction::Lt(op1, op2, dst),
+            InstructionV1::Le(op1, op2, dst) => Instruction::Le(op1, op2, dst),
+            InstructionV1::Gt(op1, op2, dst) => Instruction::Gt(op1, op2, dst),
+            InstructionV1::Ge(op1, op2, dst) => Instruction::Ge(op1, op2, dst),
+            InstructionV1::Return => Instruction::Return,
+            InstructionV1::SetVariable(src, name) => Instruction::SetVariable(src, name),
+            InstructionV1::MovFromVariable(name, dst) => Instruction::MovFromVariable(name, dst),
+            InstructionV1::ThrowFrom(name, message) => Instruction::ThrowFrom(name, message),
+            InstructionV1::Eq(op1, op2, dst) => Instruction::Eq(op1, op2, dst),
+            InstructionV1::Ne(op1, op2, dst) => Instruction::Ne(op1, op2, dst),
+            InstructionV1::DefineLabel(label) => Instruction::DefineLabel(label),
+            InstructionV1::JumpUnconditional(label) => Instruction::JumpUnconditional(label),
+            InstructionV1::JumpConditional(reg, label) => Instruction::JumpConditional(reg, label),
+            InstructionV1::Call(name) => Instruction::Call(name),
+            InstructionV1::DefineFnLabel(name, args, returns) => Instruction::DefineFnLabel(name, args, returns),
+            InstructionV1::EndFunction => Instruction::EndFunction,
+            InstructionV1::StdoutWrite(src) => Instruction::StdoutWrite(src),
+            InstructionV1::StdoutWriteDebugged(src) => Instruction::StdoutWriteDebugged(src),
+            InstructionV1::StdoutFlush => Instruction::StdoutFlush,
+            InstructionV1::StderrWrite(src) => Instruction::StderrWrite(src),
+            InstructionV1::StderrWriteDebugged(src) => Instruction::StderrWriteDebugged(src),
+            InstructionV1::StderrFlush => Instruction::StderrFlush,
+            InstructionV1::BufferedStdinRead(dst) => Instruction::BufferedStdinRead(dst),
+        })
+    }
+}
+
+/// A value as encoded by format version 1: its bytes, tagged with its type. Ints took 4
+/// bytes, strings were encoded with bincode and booleans took a single byte.
+#[derive(Deserialize)]
+struct MiValueV1 {
+    bytes: Vec<u8>,
+    variant: MiType,
+}
+
+impl MiValueV1 {
+    fn upgrade(self) -> Result<MiValue, String> {
+        let value = match self.variant {
+            MiType::Int => self.bytes.as_slice().try_into().ok().map(|bytes| MiValue::Int(i32::from_le_bytes(bytes) as i64)),
+            MiType::Float => self.bytes.as_slice().try_into().ok().map(|bytes| MiValue::Float(f64::from_le_bytes(bytes))),
+            MiType::String => bincode::deserialize::<String>(&self.bytes).ok().map(|string| string.into_value()),
+            MiType::Bool => match self.bytes.as_slice() {
+                [byte] => Some(MiValue::Bool(*byte == 1)),
+                _ => None,
+            },
+            MiType::None => Some(MiValue::None),
+            // the assembler could not write values of the other types
+            _ => None,
+        };
+        value.ok_or_else(|| format!("The value of type `{:?}` encoded as {:?} is invalid", self.variant, self.bytes))
+    }
+}
+
+/// The metadata as encoded by format version 2
+#[derive(Deserialize)]
+struct MetadataV2 {
+    package: String,
+    version: Option<String>,
+    timestamp: SystemTime,
+    author: Option<String>,
+    instructions: Vec<Instruction>,
+    debug_info: Option<DebugInfoV2>,
+    description: String,
+    license: Option<String>,
+    total_instructions: usize,
+    compiled_version: String,
+}
+
+/// The debug info as encoded by format version 2
+#[derive(Deserialize)]
+struct DebugInfoV2 {
+    files: Vec<SourceFile>,
+    locations: Vec<SourceLocation>,
+}
+
+impl Metadata {
+    /// Decodes the metadata stored in a `.mirage` binary, checking its header and
+    /// upgrading binaries written with older format versions
+    pub fn from_bytes(bytes: &[u8]) -> Result<Metadata, String> {
+        if !bytes.starts_with(&MAGIC) {
+            // binaries written before the header was introduced
+            return Self::migrate(1, bytes).map_err(|err| format!(
+                "The file is not a mirage binary: it does not start with the expected magic bytes, \
+                and cannot be read as a binary written before they were introduced: {err}"
+            ));
+        }
+        if bytes.len() < HEADER_LEN {
+            return Err("The binary is truncated: its header is incomplete".to_string());
+        }
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        let mut length = [0; 8];
+        length.copy_from_slice(&bytes[6..14]);
+        let length = u64::from_le_bytes(length);
+        let mut checksum = [0; 4];
+        checksum.copy_from_slice(&bytes[14..18]);
+        let checksum = u32::from_le_bytes(checksum);
+
+        let payload = &bytes[HEADER_LEN..];
+        if payload.len() as u64 != length {
+            return Err(format!("The binary is truncated or corrupted: expected {length} bytes of content, found {}", payload.len()));
+        }
+        if crc32(payload) != checksum {
+            return Err("The binary is corrupted: its checksum does not match its content".to_string());
+        }
+        if version > FORMAT_VERSION {
+            return Err(format!(
+                "The binary uses format version {version}, this runtime only reads versions up to {FORMAT_VERSION}: it was built by a newer version of mirage"
+            ));
+        }
+        Self::migrate(version, payload)
+    }
+
+    /// Decodes a payload written with the specified format version, upgrading it to the current layout
+    fn migrate(version: u16, payload: &[u8]) -> Result<Metadata, String> {
+        let invalid = |err: bincode::Error| format!("The binary content is invalid for format version {version}: {err}");
+        match version {
+            1 => {
+                let old = bincode::deserialize::<MetadataV1>(payload).map_err(invalid)?;
+                let instructions = old.instructions
+                    .into_iter()
+                    .map(InstructionV1::upgrade)
+                    .collect::<Result<Vec<Instruction>, String>>()
+                    .map_err(|err| format!("The binary content is invalid for format version {version}: {err}"))?;
+                Ok(Metadata {
+                    package: old.package,
+                    version: old.version,
+                    timestamp: old.timestamp,
+                    author: old.author,
+                    instructions,
+                    debug_info: None,
+                    description: old.description,
+                    license: old.license,
+                    total_instructions: old.total_instructions,
+                    compiled_version: old.compiled_version,
+                })
+            }
+            2 => {
+                let old = bincode::deserialize::<MetadataV2>(payload).map_err(invalid)?;
+                Ok(Metadata {
+                    package: old.package,
+                    version: old.version,
+                    timestamp: old.timestamp,
+                    author: old.author,
+                    instructions: old.instructions,
+                    debug_info: old.debug_info.map(|debug_info| DebugInfo {
+                        files: debug_info.files,
+                        locations: debug_info.locations,
+                        aliases: Vec::new(),
+                    }),
+                    description: old.description,
+                    license: old.license,
+                    total_instructions: old.total_instructions,
+                    compiled_version: old.compiled_version,
+                })
+            }
+            FORMAT_VERSION => bincode::deserialize::<Metadata>(payload).map_err(invalid),
+            _ => Err(format!("Unknown format version {version}")),
+        }
+    }
+
+    /// Encodes the metadata into the `.mirage` binary format, with the current format version
+    pub fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        let payload = bincode::serialize(self).map_err(|err| err.to_string())?;
+        let mut bytes = Vec::with_capacity(HEADER_LEN + payload.len());
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(&crc32(&payload).to_le_bytes());
+        bytes.extend_from_slice(&payload);
+        Ok(bytes)
+    }
+
+    /// Checks that the binary was built by a version of mirage this runtime can run.
+    /// Versions with a different major version or a newer minor version are refused.
+    pub fn check_compatibility(&self) -> Result<(), String> {
+        let parse = |version: &str| -> Option<(u64, u64)> {
+            let mut parts = version.split('.');
+            Some((parts.next()?.parse().ok()?, parts.next()?.parse().ok()?))
+        };
+        let Some((major, minor)) = parse(&self.compiled_version) else {
+            return Err(format!("The binary was built by an unknown version of mirage `{}`", self.compiled_version));
+        };
+        let (current_major, current_minor) = parse(MIRAGE_VERSION).unwrap();
+        if major != current_major || minor > current_minor {
+            return Err(format!(
+                "The binary was built by mirage {}, which is incompatible with this runtime ({MIRAGE_VERSION})",
+                self.compiled_version
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Computes the CRC-32 (IEEE) checksum of the bytes
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
 #[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
 pub struct Manifest {
     pub package: String,
@@ -27,4 +325,58 @@ pub struct Manifest {
     pub main_file: String,
     pub description: Option<String>,
     pub license: String,
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::MirageRuntime;
+
+    /// Built by mirage 1.2.1 from `tests/fixtures/baseline.masm`, before binaries had a header
+    const BASELINE: &[u8] = include_bytes!("../tests/fixtures/baseline.mirage");
+
+    #[test]
+    fn binaries_without_a_header_are_migrated() {
+        let metadata = Metadata::from_bytes(BASELINE).unwrap();
+        assert_eq!(metadata.package, "fixture");
+        assert_eq!(metadata.compiled_version, "1.2.1");
+        assert_eq!(metadata.debug_info, None);
+        assert_eq!(metadata.instructions, vec![
+            Instruction::DefineFnLabel("greet".to_string(), Vec::new(), MiType::None),
+            Instruction::Move(0, "hello".into_value()),
+            Instruction::StdoutWrite(0),
+            Instruction::Return,
+            Instruction::EndFunction,
+            Instruction::Move(1, 33.into_value()),
+            Instruction::Move(4, MiValue::None),
+            Instruction::Call("greet".to_string()),
+            Instruction::Add(1, 1, 15),
+        ]);
+
+        let mut output = Vec::new();
+        let mut runtime = MirageRuntime::from_binary(BASELINE).unwrap();
+        runtime.set_stdout(&mut output);
+        assert_eq!(runtime.run(), Ok(Some(66.into_value())));
+        drop(runtime);
+        assert_eq!(output, b"hello");
+    }
+
+    #[test]
+    fn binaries_round_trip_with_the_current_format() {
+        let metadata = Metadata::from_bytes(BASELINE).unwrap();
+        let bytes = metadata.to_bytes().unwrap();
+        assert!(bytes.starts_with(&MAGIC));
+        assert_eq!(Metadata::from_bytes(&bytes), Ok(metadata));
+    }
+
+    #[test]
+    fn invalid_files_report_why_they_cannot_be_decoded() {
+        let error = Metadata::from_bytes(&BASELINE[..BASELINE.len() / 2]).unwrap_err();
+        assert!(error.starts_with("The file is not a mirage binary"), "{error}");
+        assert!(error.contains("invalid for format version 1: "), "{error}");
+
+        let mut corrupted = Metadata::from_bytes(BASELINE).unwrap().to_bytes().unwrap();
+        *corrupted.last_mut().unwrap() ^= 1;
+        assert_eq!(Metadata::from_bytes(&corrupted), Err("The binary is corrupted: its checksum does not match its content".to_string()));
+    }
+}
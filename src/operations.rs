@@ -0,0 +1,225 @@
+use crate::result::{MiResult, MiError};
+use crate::value::MiValue;
+
+/// The operands of a numeric instruction, both of the same type
+enum Numbers {
+    Ints(i64, i64),
+    Floats(f64, f64),
+}
+
+/// A function implementing a binary instruction over the values of two registers
+pub type BinaryOperation = fn(&MiValue, &MiValue) -> MiResult;
+
+/// A function implementing a unary instruction over the value of a register
+pub type UnaryOperation = fn(&MiValue) -> MiResult;
+
+pub fn add(op1: &MiValue, op2: &MiValue) -> MiResult {
+    match numbers(op1, op2, "add") {
+        Ok(Numbers::Ints(val1, val2)) => checked(val1.checked_add(val2), format_args!("{val1} + {val2}")),
+        Ok(Numbers::Floats(val1, val2)) => MiResult::Ok(MiValue::Float(val1 + val2)),
+        Err(err) => MiResult::Err(err),
+    }
+}
+
+pub fn sub(op1: &MiValue, op2: &MiValue) -> MiResult {
+    match numbers(op1, op2, "subtract") {
+        Ok(Numbers::Ints(val1, val2)) => checked(val1.checked_sub(val2), format_args!("{val1} - {val2}")),
+        Ok(Numbers::Floats(val1, val2)) => MiResult::Ok(MiValue::Float(val1 - val2)),
+        Err(err) => MiResult::Err(err),
+    }
+}
+
+pub fn mul(op1: &MiValue, op2: &MiValue) -> MiResult {
+    match numbers(op1, op2, "multiply") {
+        Ok(Numbers::Ints(val1, val2)) => checked(val1.checked_mul(val2), format_args!("{val1} * {val2}")),
+        Ok(Numbers::Floats(val1, val2)) => MiResult::Ok(MiValue::Float(val1 * val2)),
+        Err(err) => MiResult::Err(err),
+    }
+}
+
+pub fn div(op1: &MiValue, op2: &MiValue) -> MiResult {
+    match numbers(op1, op2, "divide") {
+        Ok(Numbers::Ints(val1, 0)) => error("DivisionByZero", format!("Cannot divide `{val1}` by zero")),
+        Ok(Numbers::Ints(val1, val2)) => checked(val1.checked_div(val2), format_args!("{val1} / {val2}")),
+        Ok(Numbers::Floats(val1, val2)) => MiResult::Ok(MiValue::Float(val1 / val2)),
+        Err(err) => MiResult::Err(err),
+    }
+}
+
+pub fn rem(op1: &MiValue, op2: &MiValue) -> MiResult {
+    match numbers(op1, op2, "rem") {
+        Ok(Numbers::Ints(val1, 0)) => error("DivisionByZero", format!("Cannot take the remainder of `{val1}` by zero")),
+        Ok(Numbers::Ints(val1, val2)) => checked(val1.checked_rem(val2), format_args!("{val1} % {val2}")),
+        Ok(Numbers::Floats(val1, val2)) => MiResult::Ok(MiValue::Float(val1 % val2)),
+        Err(err) => MiResult::Err(err),
+    }
+}
+
+pub fn pow(op1: &MiValue, op2: &MiValue) -> MiResult {
+    match numbers(op1, op2, "power") {
+        Ok(Numbers::Ints(_, val2)) if val2 < 0 => {
+            error("MathError", format!("The exponent `{val2}` is not valid as it needs to be positive"))
+        }
+        Ok(Numbers::Ints(val1, val2)) => {
+            let result = u32::try_from(val2).ok().and_then(|exp| val1.checked_pow(exp));
+            checked(result, format_args!("{val1} ** {val2}"))
+        }
+        Ok(Numbers::Floats(val1, val2)) => MiResult::Ok(MiValue::Float(val1.powf(val2))),
+        Err(err) => MiResult::Err(err),
+    }
+}
+
+pub fn lt(op1: &MiValue, op2: &MiValue) -> MiResult {
+    match numbers(op1, op2, "LT") {
+        Ok(Numbers::Ints(val1, val2)) => MiResult::Ok(MiValue::Bool(val1 < val2)),
+        Ok(Numbers::Floats(val1, val2)) => MiResult::Ok(MiValue::Bool(val1 < val2)),
+        Err(err) => MiResult::Err(err),
+    }
+}
+
+pub fn le(op1: &MiValue, op2: &MiValue) -> MiResult {
+    match numbers(op1, op2, "LE") {
+        Ok(Numbers::Ints(val1, val2)) => MiResult::Ok(MiValue::Bool(val1 <= val2)),
+        Ok(Numbers::Floats(val1, val2)) => MiResult::Ok(MiValue::Bool(val1 <= val2)),
+        Err(err) => MiResult::Err(err),
+    }
+}
+
+pub fn gt(op1: &MiValue, op2: &MiValue) -> MiResult {
+    match numbers(op1, op2, "GT") {
+        Ok(Numbers::Ints(val1, val2)) => MiResult::Ok(MiValue::Bool(val1 > val2)),
+        Ok(Numbers::Floats(val1, val2)) => MiResult::Ok(MiValue::Bool(val1 > val2)),
+        Err(err) => MiResult::Err(err),
+    }
+}
+
+pub fn ge(op1: &MiValue, op2: &MiValue) -> MiResult {
+    match numbers(op1, op2, "GE") {
+        Ok(Numbers::Ints(val1, val2)) => MiResult::Ok(MiValue::Bool(val1 >= val2)),
+        Ok(Numbers::Floats(val1, val2)) => MiResult::Ok(MiValue::Bool(val1 >= val2)),
+        Err(err) => MiResult::Err(err),
+    }
+}
+
+pub fn eq(op1: &MiValue, op2: &MiValue) -> MiResult {
+    MiResult::Ok(MiValue::Bool(op1 == op2))
+}
+
+pub fn ne(op1: &MiValue, op2: &MiValue) -> MiResult {
+    MiResult::Ok(MiValue::Bool(op1 != op2))
+}
+
+pub fn or(op1: &MiValue, op2: &MiValue) -> MiResult {
+    match (boolean(op1), boolean(op2)) {
+        (Ok(b1), Ok(b2)) => MiResult::Ok(MiValue::Bool(b1 || b2)),
+        (Err(err), _) | (_, Err(err)) => MiResult::Err(err),
+    }
+}
+
+pub fn xor(op1: &MiValue, op2: &MiValue) -> MiResult {
+    match (boolean(op1), boolean(op2)) {
+        (Ok(b1), Ok(b2)) => MiResult::Ok(MiValue::Bool(b1 ^ b2)),
+        (Err(err), _) | (_, Err(err)) => MiResult::Err(err),
+    }
+}
+
+pub fn and(op1: &MiValue, op2: &MiValue) -> MiResult {
+    match (boolean(op1), boolean(op2)) {
+        (Ok(b1), Ok(b2)) => MiResult::Ok(MiValue::Bool(b1 && b2)),
+        (Err(err), _) | (_, Err(err)) => MiResult::Err(err),
+    }
+}
+
+pub fn not(op: &MiValue) -> MiResult {
+    match boolean(op) {
+        Ok(b) => MiResult::Ok(MiValue::Bool(!b)),
+        Err(err) => MiResult::Err(err),
+    }
+}
+
+/// Checks that both operands are numbers of the same type
+fn numbers(op1: &MiValue, op2: &MiValue, verb: &str) -> Result<Numbers, MiError> {
+    match (op1, op2) {
+        (MiValue::Int(val1), MiValue::Int(val2)) => Ok(Numbers::Ints(*val1, *val2)),
+        (MiValue::Float(val1), MiValue::Float(val2)) => Ok(Numbers::Floats(*val1, *val2)),
+        _ => {
+            for op in [op1, op2] {
+                if !op.variant().is_numeric() {
+                    return Err(mi_error("InvalidType", format!("The type `{:?}` is not numeric", op.variant())));
+                }
+            }
+            Err(mi_error(
+                "InvalidType",
+                format!("Cannot {verb} two different types: `{:?}` and `{:?}`", op1.variant(), op2.variant())
+            ))
+        }
+    }
+}
+
+fn boolean(op: &MiValue) -> Result<bool, MiError> {
+    match op {
+        MiValue::Bool(b) => Ok(*b),
+        _ => Err(mi_error("InvalidType", format!("The type `{:?}` is not boolean", op.variant()))),
+    }
+}
+
+/// Wraps the result of a checked int operation, throwing `IntegerOverflow` if it overflowed
+fn checked(result: Option<i64>, operation: std::fmt::Arguments) -> MiResult {
+    match result {
+        Some(num) => MiResult::Ok(MiValue::Int(num)),
+        None => error("IntegerOverflow", format!("The operation `{operation}` overflows an int")),
+    }
+}
+
+fn mi_error<T: ToString>(name: &str, message: T) -> MiError {
+    MiError {
+        name: name.to_string(),
+        message: message.to_string(),
+        backtrace: String::new(),
+    }
+}
+
+fn error<T: ToString>(name: &str, message: T) -> MiResult {
+    MiResult::Err(mi_error(name, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the name of the error of the result, or its value
+    fn outcome(result: MiResult) -> Result<MiValue, String> {
+        match result {
+            MiResult::Ok(value) => Ok(value),
+            MiResult::Err(err) => Err(err.name),
+        }
+    }
+
+    fn int(value: i64) -> MiValue {
+        MiValue::Int(value)
+    }
+
+    #[test]
+    fn int_overflow_is_an_error() {
+        let overflow = Err("IntegerOverflow".to_string());
+        assert_eq!(outcome(add(&int(i64::MAX), &int(1))), overflow);
+        assert_eq!(outcome(sub(&int(i64::MIN), &int(1))), overflow);
+        assert_eq!(outcome(mul(&int(i64::MAX), &int(2))), overflow);
+        assert_eq!(outcome(div(&int(i64::MIN), &int(-1))), overflow);
+        assert_eq!(outcome(rem(&int(i64::MIN), &int(-1))), overflow);
+        assert_eq!(outcome(pow(&int(2), &int(63))), overflow);
+        assert_eq!(outcome(pow(&int(2), &int(u32::MAX as i64 + 1))), overflow);
+        assert_eq!(outcome(add(&int(i64::MAX - 1), &int(1))), Ok(int(i64::MAX)));
+        assert_eq!(outcome(pow(&int(2), &int(62))), Ok(int(1 << 62)));
+    }
+
+    #[test]
+    fn int_division_by_zero_is_an_error() {
+        let division_by_zero = Err("DivisionByZero".to_string());
+        assert_eq!(outcome(div(&int(1), &int(0))), division_by_zero);
+        assert_eq!(outcome(rem(&int(1), &int(0))), division_by_zero);
+        assert_eq!(outcome(pow(&int(2), &int(-1))), Err("MathError".to_string()));
+        // floats follow IEEE 754
+        assert_eq!(outcome(div(&MiValue::Float(1.0), &MiValue::Float(0.0))), Ok(MiValue::Float(f64::INFINITY)));
+    }
+}
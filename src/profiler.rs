@@ -0,0 +1,200 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::Duration;
+
+use fxhash::FxHashMap;
+
+use crate::stack::StackFrame;
+
+/// The statistics of a single function
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct FunctionProfile {
+    /// Number of times the function was entered
+    pub calls: u64,
+    /// Number of instructions executed by the function itself
+    pub instructions: u64,
+    /// Time spent in the function and the functions it called
+    pub inclusive: Duration,
+    /// Time spent in the function itself
+    pub exclusive: Duration,
+}
+
+/// Counts the instructions executed per opcode and per function and measures the
+/// time spent in every function, attributing each instruction to the frame executing it
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Profiler {
+    opcodes: BTreeMap<&'static str, u64>,
+    functions: FxHashMap<String, FunctionProfile>,
+    /// Exclusive time per call stack, keyed by the frame names joined with `;`
+    stacks: FxHashMap<String, Duration>,
+    /// Names of the frames of the call stack as last seen, the innermost being the last
+    path: Vec<String>,
+    /// `path` joined with `;`
+    stack_key: String,
+    /// `path` without repeated names, so recursive calls count once towards inclusive time
+    distinct: Vec<String>,
+    total_instructions: u64,
+    total_time: Duration,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records an instruction executed by the innermost frame seen by the last `sync`
+    pub fn record(&mut self, opcode: &'static str, elapsed: Duration) {
+        *self.opcodes.entry(opcode).or_insert(0) += 1;
+        self.total_instructions += 1;
+        self.total_time += elapsed;
+
+        match self.stacks.get_mut(&self.stack_key) {
+            Some(time) => *time += elapsed,
+            None => {
+                self.stacks.insert(self.stack_key.clone(), elapsed);
+            }
+        }
+        for name in &self.distinct {
+            let function = self.functions.entry(name.clone()).or_default();
+            function.inclusive += elapsed;
+        }
+        if let Some(name) = self.path.last() {
+            let function = self.functions.entry(name.clone()).or_default();
+            function.instructions += 1;
+            function.exclusive += elapsed;
+        }
+    }
+
+    /// Updates the recorded path when the call stack changed since the last instruction.
+    /// Called with the frames of the runtime before executing each instruction.
+    pub fn sync(&mut self, frames: &[StackFrame]) {
+        let unchanged = frames.len() == self.path.len()
+            && frames.iter().zip(&self.path).all(|(frame, name)| &frame.name == name);
+        if unchanged {
+            return;
+        }
+        // frames are pushed one at a time, so a longer stack means a single call
+        if frames.len() > self.path.len() {
+            if let Some(frame) = frames.last() {
+                self.functions.entry(frame.name.clone()).or_default().calls += 1;
+            }
+        }
+        self.path = frames.iter().map(|frame| frame.name.clone()).collect();
+        self.stack_key = self.path.join(";");
+        self.distinct.clear();
+        for name in &self.path {
+            if !self.distinct.contains(name) {
+                self.distinct.push(name.clone());
+            }
+        }
+    }
+
+    /// Returns the number of executed instructions per opcode
+    pub fn opcodes(&self) -> &BTreeMap<&'static str, u64> {
+        &self.opcodes
+    }
+
+    /// Returns the statistics of every function that executed an instruction
+    pub fn functions(&self) -> &FxHashMap<String, FunctionProfile> {
+        &self.functions
+    }
+
+    /// Formats the statistics, functions sorted by exclusive time and opcodes by count
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+        let _ = writeln!(report, "Executed {} instructions in {:.3?}", self.total_instructions, self.total_time);
+
+        let mut functions: Vec<_> = self.functions.iter().collect();
+        functions.sort_by(|(a_name, a), (b_name, b)| b.exclusive.cmp(&a.exclusive).then_with(|| a_name.cmp(b_name)));
+        let width = functions.iter().map(|(name, _)| name.len()).max().unwrap_or(0).max("Function".len());
+        let _ = writeln!(report, "\n{:<width$} {:>10} {:>14} {:>14} {:>14}", "Function", "Calls", "Instructions", "Inclusive", "Exclusive");
+        for (name, function) in functions {
+            let _ = writeln!(
+                report,
+                "{:<width$} {:>10} {:>14} {:>14} {:>14}",
+                name, function.calls, function.instructions,
+                format!("{:.3?}", function.inclusive), format!("{:.3?}", function.exclusive),
+            );
+        }
+
+        let mut opcodes: Vec<_> = self.opcodes.iter().collect();
+        opcodes.sort_by(|(a_name, a), (b_name, b)| b.cmp(a).then_with(|| a_name.cmp(b_name)));
+        let _ = writeln!(report, "\n{:<20} {:>14} {:>8}", "Opcode", "Count", "%");
+        for (opcode, count) in opcodes {
+            let percent = *count as f64 * 100.0 / self.total_instructions as f64;
+            let _ = writeln!(report, "{:<20} {:>14} {:>7.2}%", opcode, count, percent);
+        }
+        report
+    }
+
+    /// Formats the exclusive time of every call stack in the collapsed format read by
+    /// flamegraph tools, one `Main;caller;callee <nanoseconds>` line per stack
+    pub fn collapsed_stacks(&self) -> String {
+        let mut stacks: Vec<_> = self.stacks.iter().collect();
+        stacks.sort();
+        let mut collapsed = String::new();
+        for (stack, time) in stacks {
+            let _ = writeln!(collapsed, "{} {}", stack, time.as_nanos());
+        }
+        collapsed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::MirageRuntime;
+
+    fn frames(names: &[&str]) -> Vec<StackFrame> {
+        names.iter().map(|name| StackFrame::new(name.to_string(), FxHashMap::default(), None)).collect()
+    }
+
+    #[test]
+    fn instructions_are_counted_per_opcode_and_function() {
+        let source = "definefnlabel f 0 int\nmove r0 int 1\nreturn\nendfunction\ncall f\ncall f\nmove r15 int 2";
+        let mut runtime = MirageRuntime::from_source(source, "test.masm").unwrap();
+        runtime.enable_profiling();
+        runtime.run().unwrap();
+        let profiler = runtime.profiler().unwrap();
+        assert_eq!(profiler.opcodes().get("call"), Some(&2));
+        assert_eq!(profiler.opcodes().get("return"), Some(&2));
+        assert_eq!(profiler.opcodes().get("move"), Some(&3));
+        let f = &profiler.functions()["f"];
+        assert_eq!((f.calls, f.instructions), (2, 4));
+        assert_eq!(profiler.functions()["Main"].calls, 1);
+    }
+
+    #[test]
+    fn time_is_attributed_to_the_executing_frame() {
+        let mut profiler = Profiler::new();
+        profiler.sync(&frames(&["Main"]));
+        profiler.record("call", Duration::from_nanos(10));
+        profiler.sync(&frames(&["Main", "f"]));
+        profiler.record("move", Duration::from_nanos(100));
+        profiler.sync(&frames(&["Main", "f", "f"]));
+        profiler.record("return", Duration::from_nanos(1000));
+        profiler.sync(&frames(&["Main"]));
+        profiler.record("move", Duration::from_nanos(5));
+
+        let main = &profiler.functions()["Main"];
+        assert_eq!((main.exclusive, main.inclusive), (Duration::from_nanos(15), Duration::from_nanos(1115)));
+        let f = &profiler.functions()["f"];
+        assert_eq!(f.calls, 2);
+        // the recursive call counts once towards the inclusive time
+        assert_eq!((f.exclusive, f.inclusive), (Duration::from_nanos(1100), Duration::from_nanos(1100)));
+    }
+
+    #[test]
+    fn collapsed_stacks_hold_the_exclusive_time_of_every_stack() {
+        let mut profiler = Profiler::new();
+        profiler.sync(&frames(&["Main"]));
+        profiler.record("call", Duration::from_nanos(10));
+        profiler.sync(&frames(&["Main", "f"]));
+        profiler.record("call", Duration::from_nanos(20));
+        profiler.sync(&frames(&["Main", "f", "g"]));
+        profiler.record("return", Duration::from_nanos(30));
+        profiler.sync(&frames(&["Main", "f"]));
+        profiler.record("return", Duration::from_nanos(40));
+        assert_eq!(profiler.collapsed_stacks(), "Main 10\nMain;f 60\nMain;f;g 30\n");
+    }
+}
@@ -1,8 +1,17 @@
 use crate::{value::MiValue, result::MiError};
 
+/// The register functions leave their return value in. It is copied back into the
+/// caller's register window on `return`.
+pub const RETURN_REGISTER: usize = 15;
+
+/// The number of registers of a register window
+pub const REGISTER_COUNT: usize = 16;
+
+/// A register window. Every stack frame gets its own window, so a call never
+/// clobbers the registers of its caller.
 #[derive(Clone, PartialEq, Debug)]
 pub struct Registers {
-    registers: [Option<MiValue>; 16],
+    registers: [Option<MiValue>; REGISTER_COUNT],
 }
 
 impl Registers {
@@ -14,6 +23,15 @@ impl Registers {
         self.registers.get(index).and_then(|v| v.as_ref())
     }
 
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut MiValue> {
+        self.registers.get_mut(index).and_then(|v| v.as_mut())
+    }
+
+    /// Returns the values of the registers that have been set
+    pub fn values(&self) -> impl Iterator<Item = &MiValue> {
+        self.registers.iter().flatten()
+    }
+
     pub fn set(&mut self, index: usize, value: MiValue) -> Result<(), MiError> {
         if let Some(register) = self.registers.get_mut(index) {
             *register = Some(value);
@@ -1,24 +1,75 @@
-use std::io::{stdout, Write, stderr, stdin, StdoutLock, StderrLock};
+use std::collections::BTreeMap;
+use std::io::{stdout, Write, stderr, stdin, BufRead, BufReader};
+use std::rc::Rc;
+use std::time::Instant;
 
 use fxhash::FxHashMap;
 
-use crate::registers::Registers;
+use crate::assembly::Assembler;
+use crate::builtins::Builtins;
+use crate::class::{Class, ClassBlueprint};
+use crate::debug_info::DebugInfo;
+use crate::limits::{shallow_bytes, ByteCounter, RuntimeLimits, MEMORY_CHECK_INTERVAL};
+use crate::meta::Metadata;
+use crate::operations::{self, BinaryOperation, UnaryOperation};
+use crate::profiler::Profiler;
+use crate::tracer::{changed_registers, TraceFormat, Tracer};
+use crate::registers::{Registers, RETURN_REGISTER};
 use crate::instructions::Instruction;
-use crate::value::{MiType, MiValue, ToStringDebugged, IntoValue};
-use crate::result::MiError;
-use crate::stack::{CallStack, StackFrame};
+use crate::value::{MiType, MiValue, MapKey, ToStringDebugged, IntoValue};
+use crate::result::{MiError, MiResult};
+use crate::stack::{CallStack, StackFrame, ErrorHandler};
+use crate::verifier::verify;
+
+/// The target of an instruction, resolved by `MirageRuntime::link` before running
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Link {
+    /// The instruction has no target
+    None,
+    /// The index of a label, or of the end of the function or class being skipped
+    Label(i32),
+    /// The index of the `DefineFnLabel` instruction of a defined function
+    Function(i32),
+    /// The index of a builtin function
+    Builtin(u32),
+}
+
+/// The state of the runtime after executing an instruction
+#[derive(Clone, PartialEq, Debug)]
+pub enum Step {
+    /// There are instructions left to execute
+    Running,
+    /// A frame without a return address returned, holding the value of its return register
+    Returned(Option<MiValue>),
+    /// The end of the program was reached, holding the value of the return register
+    Finished(Option<MiValue>),
+}
 
 /// Represents the Mirage runtime (virtual machine)
 pub struct MirageRuntime<'rtm> {
     pub registers: Registers,
     stack: CallStack,
     program_counter: i32,
-    instructions: Vec<Instruction>,
+    instructions: Rc<[Instruction]>,
+    links: Vec<Link>,
     labels: FxHashMap<String, i32>,
     argument_stack: Vec<MiValue>,
+    /// Depths of the call stack when the host called into the program, errors are not
+    /// unwound past them
+    call_boundaries: Vec<usize>,
     function_addr_table: FxHashMap<String, (Vec<String>, MiType, i32)>,
-    stdout_lock: StdoutLock<'rtm>,
-    stderr_lock: StderrLock<'rtm>,
+    builtins: Builtins,
+    classes: FxHashMap<String, ClassBlueprint>,
+    debug_info: Option<DebugInfo>,
+    profiler: Option<Profiler>,
+    tracer: Option<Tracer<'rtm>>,
+    limits: RuntimeLimits,
+    executed_instructions: u64,
+    /// Bytes taken by the values of the program when the memory limit was last checked
+    value_bytes: usize,
+    stdout: Box<dyn Write + 'rtm>,
+    stderr: Box<dyn Write + 'rtm>,
+    stdin: Box<dyn BufRead + 'rtm>,
 }
 
 impl<'rtm> MirageRuntime<'rtm> {
@@ -28,1442 +79,993 @@ impl<'rtm> MirageRuntime<'rtm> {
             registers: Registers::new(),
             stack: CallStack::new(),
             program_counter: -1,
-            instructions,
+            instructions: instructions.into(),
+            links: Vec::new(),
             labels: FxHashMap::default(),
             argument_stack: Vec::new(),
+            call_boundaries: Vec::new(),
             function_addr_table: FxHashMap::default(),
-            stdout_lock: stdout().lock(),
-            stderr_lock: stderr().lock(),
+            builtins: Builtins::with_defaults(),
+            classes: FxHashMap::default(),
+            debug_info: None,
+            profiler: None,
+            tracer: None,
+            limits: RuntimeLimits::default(),
+            executed_instructions: 0,
+            value_bytes: 0,
+            stdout: Box::new(stdout()),
+            stderr: Box::new(stderr()),
+            stdin: Box::new(BufReader::new(stdin())),
         }
     }
 
-    /// Prechecks the runtime's labels before running
+    /// Creates a runtime from the bytes of a `.mirage` binary, ready to run.
+    /// The program is verified first, as binaries may come from untrusted sources.
+    pub fn from_binary(bytes: &[u8]) -> Result<MirageRuntime<'rtm>, String> {
+        let metadata = Metadata::from_bytes(bytes)?;
+        metadata.check_compatibility()?;
+        if let Err(errors) = verify(&metadata.instructions) {
+            return Err(errors.iter().map(|error| error.to_string()).collect::<Vec<String>>().join("\n"));
+        }
+        Ok(Self::from_metadata(metadata))
+    }
+
+    /// Creates a runtime from decoded metadata, ready to run
+    pub fn from_metadata(metadata: Metadata) -> MirageRuntime<'rtm> {
+        let mut runtime = Self::new(metadata.instructions);
+        runtime.debug_info = metadata.debug_info;
+        runtime.setup();
+        runtime
+    }
+
+    /// Assembles the `.masm` source and creates a runtime from it, ready to run.
+    /// Errors point back to the source, as its debug info is kept.
+    pub fn from_source(source: &str, filename: &str) -> Result<MirageRuntime<'rtm>, String> {
+        let mut assembler = Assembler::new();
+        let (instructions, debug_info) = assembler
+            .assemble(source, filename)
+            .map_err(|diagnostics| assembler.render_diagnostics(&diagnostics, false))?;
+        let mut runtime = Self::new(instructions);
+        runtime.debug_info = Some(debug_info);
+        runtime.setup();
+        Ok(runtime)
+    }
+
+    /// Sets the writer that `stdoutwrite` instructions write to
+    pub fn set_stdout<W: Write + 'rtm>(&mut self, writer: W) {
+        self.stdout = Box::new(writer);
+    }
+
+    /// Sets the writer that `stderrwrite` instructions write to
+    pub fn set_stderr<W: Write + 'rtm>(&mut self, writer: W) {
+        self.stderr = Box::new(writer);
+    }
+
+    /// Sets the reader that `bufferedstdinread` instructions read lines from
+    pub fn set_stdin<R: BufRead + 'rtm>(&mut self, reader: R) {
+        self.stdin = Box::new(reader);
+    }
+
+    /// Registers a host function callable from the program through `call`.
+    /// Returns the index of the function in the builtin table.
+    pub fn register_function<T, F>(&mut self, name: T, arguments: Vec<MiType>, returns: MiType, function: F) -> u32
+    where
+        T: ToString,
+        F: Fn(&[MiValue]) -> MiResult + 'static,
+    {
+        // calls may resolve to the new function, so the program is linked again
+        self.links.clear();
+        self.builtins.register(name, arguments, returns, function)
+    }
+
+    /// Returns the builtin function table of the runtime
+    pub fn builtins(&self) -> &Builtins {
+        &self.builtins
+    }
+
+    /// Prechecks the runtime's labels and class blueprints before running.
+    /// Methods are registered as functions named `Class.method`.
     pub fn setup(&mut self) {
+        let mut current_class: Option<ClassBlueprint> = None;
         for (pos, instruction) in self.instructions.iter().enumerate() {
             match instruction {
                 Instruction::DefineLabel(label) => {
                     self.labels.insert(label.clone(), pos as i32);
                 }
                 Instruction::DefineFnLabel(name, args, returns) => {
-                    self.function_addr_table.insert(name.clone(), (args.clone(), returns.clone(), pos as i32));
+                    match current_class.as_mut() {
+                        Some(class) => {
+                            self.function_addr_table.insert(format!("{}.{}", class.name, name), (args.clone(), returns.clone(), pos as i32));
+                        }
+                        None => {
+                            self.function_addr_table.insert(name.clone(), (args.clone(), returns.clone(), pos as i32));
+                        }
+                    }
+                }
+                Instruction::DefineClass(name) => {
+                    current_class = Some(ClassBlueprint {
+                        name: name.clone(),
+                        variables: FxHashMap::default(),
+                    });
+                }
+                Instruction::ClassField(name, variant) => {
+                    if let Some(class) = current_class.as_mut() {
+                        class.variables.insert(name.clone(), variant.clone());
+                    }
+                }
+                Instruction::EndClass => {
+                    if let Some(class) = current_class.take() {
+                        self.classes.insert(class.name.clone(), class);
+                    }
                 }
                 _ => continue,
             }
         }
     }
 
+    /// Resolves the labels and function names used by the instructions to instruction
+    /// indices, so they are not looked up while running. Every undefined label and
+    /// function is reported at once, before execution starts.
+    pub fn link(&mut self) -> Result<(), MiError> {
+        let mut links = Vec::with_capacity(self.instructions.len());
+        let mut undefined = Vec::new();
+        for (pos, instruction) in self.instructions.iter().enumerate() {
+            let link = match instruction {
+                Instruction::JumpUnconditional(label)
+                | Instruction::JumpConditional(_, label)
+                | Instruction::Try(label, _, _) => match self.labels.get(label) {
+                    Some(label_pos) => Link::Label(*label_pos),
+                    None => {
+                        undefined.push(format!("instruction {pos}: the label `{label}` is not defined"));
+                        Link::None
+                    }
+                }
+                Instruction::Call(name) => match self.function_addr_table.get(name) {
+                    Some((_, _, addr)) => Link::Function(*addr),
+                    None => match self.builtins.index_of(name) {
+                        Some(index) => Link::Builtin(index),
+                        None => {
+                            undefined.push(format!("instruction {pos}: the function `{name}` is not defined"));
+                            Link::None
+                        }
+                    }
+                }
+                // definitions are skipped when they are reached, they only run when called
+                Instruction::DefineFnLabel(..) => Link::Label(self.find_end(pos, &Instruction::EndFunction)),
+                Instruction::DefineClass(_) => Link::Label(self.find_end(pos, &Instruction::EndClass)),
+                _ => Link::None,
+            };
+            links.push(link);
+        }
+        if !undefined.is_empty() {
+            return Err(MiError {
+                name: "LinkError".to_string(),
+                message: undefined.join("\n"),
+                backtrace: String::new(),
+            })
+        }
+        self.links = links;
+        Ok(())
+    }
+
+    /// Returns the index of the first instruction equal to `end` after the specified
+    /// position, or the index of the last instruction if there is none
+    fn find_end(&self, pos: usize, end: &Instruction) -> i32 {
+        let offset = self.instructions[pos..].iter().position(|instruction| instruction == end);
+        match offset {
+            Some(offset) => (pos + offset) as i32,
+            None => self.instructions.len() as i32 - 1,
+        }
+    }
+
+    /// Links the program if it has not been linked since the last change to the builtins
+    fn ensure_linked(&mut self) -> Result<(), MiError> {
+        if self.links.len() != self.instructions.len() {
+            self.link()?;
+        }
+        Ok(())
+    }
+
     /// Runs the virtual machine to its end
     pub fn run(&mut self) -> Result<Option<MiValue>, MiError> {
-        self.stack.push_frame(StackFrame::new(
-            String::from("Main"),
-            FxHashMap::default(),
-            None,
-            false,
-            0,
-        )).unwrap();
+        self.start()?;
+        self.execute()
+    }
 
-        loop {
-            self.program_counter += 1;
-            let ins = self.get_current();
-            // eprintln!("{:?}", self.get_current());
-            match ins {
-                Some(instruction) => {
-                    match instruction {
-                        Instruction::Move(reg, value) => {
-                            self.registers.set(reg, value.clone())?;
-                        }
-                        Instruction::MoveBetween(src, dst) => {
-                            match self.registers.get(src) {
-                                Some(value) => {
-                                    self.registers.set(dst, value.clone())?;
-                                }
-                                None => {
-                                    self.program_counter = self.throw(
-                                        "UnsetRegister",
-                                        format!("The register `{src} has not been set yet.")
-                                    )?;
-                                    continue;
-                                },
-                            }
-                        }
-                        Instruction::MoveArgument(arg, reg) => {
-                            match self.stack.last_frame_mut().unwrap().args.get(&arg) {
-                                Some(value) => {
-                                    self.registers.set(reg, value.clone())?;
-                                }
-                                None => {
-                                    self.program_counter = self.throw(
-                                        "UndefinedArgument",
-                                        format!("The argument `{}` has not been defined yet.", arg)
-                                    )?;
-                                    continue;
-                                }
-                            }
-                        }
-                        Instruction::MoveAsArgument(reg) => {
-                            match self.registers.get(reg) {
-                                Some(value) => {
-                                    self.argument_stack.push(value.clone());
-                                }
-                                None => {
-                                    self.program_counter = self.throw(
-                                        "UnsetRegister",
-                                        format!("The register `{reg}` has not been set yet.")
-                                    )?;
-                                    continue;
-                                }
-                            }
-                        }
-                        Instruction::Add(op1, op2, dst) => {
-                            match self.registers.get(op1).cloned() {
-                                Some(op1) => {
-                                    if !op1.variant.is_numeric() {
-                                        self.program_counter = self.throw(
-                                            "InvalidType",
-                                            format!("The type `{:?}` is not numeric", op1.variant)
-                                        )?;
-                                        continue;
-                                    }
-                                    match self.registers.get(op2).cloned() {
-                                        Some(op2) => {
-                                            if !op2.variant.is_numeric() {
-                                                self.program_counter = self.throw(
-                                                    "InvalidType",
-                                                    format!("The type `{:?}` is not numeric", op2.variant)
-                                                )?;
-                                                continue;
-                                            }
-
-                                            // Addition implementation here
-                                            match op1.variant {
-                                                MiType::Int => {
-                                                    match op2.variant {
-                                                        MiType::Int => {
-                                                            let val1 = i32::from_le_bytes(op1.bytes.try_into().unwrap());
-                                                            let val2 = i32::from_le_bytes(op2.bytes.try_into().unwrap());
-                                                            self.registers.set(dst, MiValue {
-                                                                bytes: (val1 + val2).to_le_bytes().to_vec(),
-                                                                variant: MiType::Int,
-                                                            })?;
-                                                        }
-                                                        _ => {
-                                                            self.program_counter = self.throw(
-                                                                "InvalidType",
-                                                                format!("Cannot add two different types: `{:?}` and  `{:?}`", op1.variant, op2.variant)
-                                                            )?;
-                                                            continue;
-                                                        }
-                                                    }
-                                                }
-                                                MiType::Float => {
-                                                    match op2.variant {
-                                                        MiType::Float => {
-                                                            let val1 = f64::from_le_bytes(op1.bytes.try_into().unwrap());
-                                                            let val2 = f64::from_le_bytes(op2.bytes.try_into().unwrap());
-                                                            self.registers.set(dst, MiValue {
-                                                                bytes: (val1 + val2).to_le_bytes().to_vec(),
-                                                                variant: MiType::Float,
-                                                            })?;
-                                                        }
-                                                        _ => {
-                                                            self.program_counter = self.throw(
-                                                                "InvalidType",
-                                                                format!("Cannot add two different types: `{:?}` and  `{:?}`", op1.variant, op2.variant)
-                                                            )?;
-                                                            continue;
-                                                        }
-                                                    }
-                                                }
-                                                _ => unreachable!()
-                                            }
-                                        }
-                                        None => {
-                                            self.program_counter = self.throw(
-                                                "UnsetRegister",
-                                                format!("The register `{op2}` has not been set yet.")
-                                            )?;
-                                            continue;
-                                        },
-                                    }
-                                }
-                                None => {
-                                    self.program_counter = self.throw(
-                                        "UnsetRegister",
-                                        format!("The register `{op1}` has not been set yet.")
-                                    )?;
-                                    continue;
-                                },
-                            }
-                        }
-                        Instruction::Sub(op1, op2, dst) => {
-                            match self.registers.get(op1).cloned() {
-                                Some(op1) => {
-                                    if !op1.variant.is_numeric() {
-                                        self.program_counter = self.throw(
-                                            "InvalidType",
-                                            format!("The type `{:?}` is not numeric", op1.variant)
-                                        )?;
-                                        continue;
-                                    }
-                                    match self.registers.get(op2).cloned() {
-                                        Some(op2) => {
-                                            if !op2.variant.is_numeric() {
-                                                self.program_counter = self.throw(
-                                                    "InvalidType",
-                                                    format!("The type `{:?}` is not numeric", op2.variant)
-                                                )?;
-                                                continue;
-                                            }
-
-                                            // Subtraction implementation here
-                                            match op1.variant {
-                                                MiType::Int => {
-                                                    match op2.variant {
-                                                        MiType::Int => {
-                                                            let val1 = i32::from_le_bytes(op1.bytes.try_into().unwrap());
-                                                            let val2 = i32::from_le_bytes(op2.bytes.try_into().unwrap());
-                                                            self.registers.set(dst, MiValue {
-                                                                bytes: (val1 - val2).to_le_bytes().to_vec(),
-                                                                variant: MiType::Int,
-                                                            })?;
-                                                        }
-                                                        _ => {
-                                                            self.program_counter = self.throw(
-                                                                "InvalidType",
-                                                                format!("Cannot subtract two different types: `{:?}` and  `{:?}`", op1.variant, op2.variant)
-                                                            )?;
-                                                            continue;
-                                                        }
-                                                    }
-                                                }
-                                                MiType::Float => {
-                                                    match op2.variant {
-                                                        MiType::Float => {
-                                                            let val1 = f64::from_le_bytes(op1.bytes.try_into().unwrap());
-                                                            let val2 = f64::from_le_bytes(op2.bytes.try_into().unwrap());
-                                                            self.registers.set(dst, MiValue {
-                                                                bytes: (val1 - val2).to_le_bytes().to_vec(),
-                                                                variant: MiType::Float,
-                                                            })?;
-                                                        }
-                                                        _ => {
-                                                            self.program_counter = self.throw(
-                                                                "InvalidType",
-                                                                format!("Cannot subtract two different types: `{:?}` and  `{:?}`", op1.variant, op2.variant)
-                                                            )?;
-                                                            continue;
-                                                        }
-                                                    }
-                                                }
-                                                _ => unreachable!()
-                                            }
-                                        }
-                                        None => {
-                                            self.program_counter = self.throw(
-                                                "UnsetRegister",
-                                                format!("The register `{op2}` has not been set yet.")
-                                            )?;
-                                            continue;
-                                        },
-                                    }
-                                }
-                                None => {
-                                    self.program_counter = self.throw(
-                                        "UnsetRegister",
-                                        format!("The register `{op1}` has not been set yet.")
-                                    )?;
-                                    continue;
-                                },
-                            }
-                        }
-                        Instruction::Mul(op1, op2, dst) => {
-                            match self.registers.get(op1).cloned() {
-                                Some(op1) => {
-                                    if !op1.variant.is_numeric() {
-                                        self.program_counter = self.throw(
-                                            "InvalidType",
-                                            format!("The type `{:?}` is not numeric", op1.variant)
-                                        )?;
-                                        continue;
-                                    }
-                                    match self.registers.get(op2).cloned() {
-                                        Some(op2) => {
-                                            if !op2.variant.is_numeric() {
-                                                self.program_counter = self.throw(
-                                                    "InvalidType",
-                                                    format!("The type `{:?}` is not numeric", op2.variant)
-                                                )?;
-                                                continue;
-                                            }
-
-                                            // Multiplication implementation here
-                                            match op1.variant {
-                                                MiType::Int => {
-                                                    match op2.variant {
-                                                        MiType::Int => {
-                                                            let val1 = i32::from_le_bytes(op1.bytes.try_into().unwrap());
-                                                            let val2 = i32::from_le_bytes(op2.bytes.try_into().unwrap());
-                                                            self.registers.set(dst, MiValue {
-                                                                bytes: (val1 * val2).to_le_bytes().to_vec(),
-                                                                variant: MiType::Int,
-                                                            })?;
-                                                        }
-                                                        _ => {
-                                                            self.program_counter = self.throw(
-                                                                "InvalidType",
-                                                                format!("Cannot multiply two different types: `{:?}` and  `{:?}`", op1.variant, op2.variant)
-                                                            )?;
-                                                            continue;
-                                                        }
-                                                    }
-                                                }
-                                                MiType::Float => {
-                                                    match op2.variant {
-                                                        MiType::Float => {
-                                                            let val1 = f64::from_le_bytes(op1.bytes.try_into().unwrap());
-                                                            let val2 = f64::from_le_bytes(op2.bytes.try_into().unwrap());
-                                                            self.registers.set(dst, MiValue {
-                                                                bytes: (val1 * val2).to_le_bytes().to_vec(),
-                                                                variant: MiType::Float,
-                                                            })?;
-                                                        }
-                                                        _ => {
-                                                            self.program_counter = self.throw(
-                                                                "InvalidType",
-                                                                format!("Cannot multiply two different types: `{:?}` and  `{:?}`", op1.variant, op2.variant)
-                                                            )?;
-                                                            continue;
-                                                        }
-                                                    }
-                                                }
-                                                _ => unreachable!()
-                                            }
-                                        }
-                                        None => {
-                                            eprintln!("Error is in mul reg2 handler");
-                                            self.program_counter = self.throw(
-                                                "UnsetRegister",
-                                                format!("The register `{op2}` has not been set yet.")
-                                            )?;
-                                            continue;
-                                        },
-                                    }
-                                }
-                                None => {
-                                    eprintln!("Error is in mul reg1 handler");
-                                    self.program_counter = self.throw(
-                                        "UnsetRegister",
-                                        format!("The register `{op1}` has not been set yet.")
-                                    )?;
-                                    continue;
-                                },
-                            }
-                        }
-                        Instruction::Div(op1, op2, dst) => {
-                            match self.registers.get(op1).cloned() {
-                                Some(op1) => {
-                                    if !op1.variant.is_numeric() {
-                                        self.program_counter = self.throw(
-                                            "InvalidType",
-                                            format!("The type `{:?}` is not numeric", op1.variant)
-                                        )?;
-                                        continue;
-                                    }
-                                    match self.registers.get(op2).cloned() {
-                                        Some(op2) => {
-                                            if !op2.variant.is_numeric() {
-                                                self.program_counter = self.throw(
-                                                    "InvalidType",
-                                                    format!("The type `{:?}` is not numeric", op2.variant)
-                                                )?;
-                                                continue;
-                                            }
-
-                                            // Division implementation here
-                                            match op1.variant {
-                                                MiType::Int => {
-                                                    match op2.variant {
-                                                        MiType::Int => {
-                                                            let val1 = i32::from_le_bytes(op1.bytes.try_into().unwrap());
-                                                            let val2 = i32::from_le_bytes(op2.bytes.try_into().unwrap());
-                                                            self.registers.set(dst, MiValue {
-                                                                bytes: (val1 / val2).to_le_bytes().to_vec(),
-                                                                variant: MiType::Int,
-                                                            })?;
-                                                        }
-                                                        _ => {
-                                                            self.program_counter = self.throw(
-                                                                "InvalidType",
-                                                                format!("Cannot divide two different types: `{:?}` and  `{:?}`", op1.variant, op2.variant)
-                                                            )?;
-                                                            continue;
-                                                        }
-                                                    }
-                                                }
-                                                MiType::Float => {
-                                                    match op2.variant {
-                                                        MiType::Float => {
-                                                            let val1 = f64::from_le_bytes(op1.bytes.try_into().unwrap());
-                                                            let val2 = f64::from_le_bytes(op2.bytes.try_into().unwrap());
-                                                            self.registers.set(dst, MiValue {
-                                                                bytes: (val1 / val2).to_le_bytes().to_vec(),
-                                                                variant: MiType::Float,
-                                                            })?;
-                                                        }
-                                                        _ => {
-                                                            self.program_counter = self.throw(
-                                                                "InvalidType",
-                                                                format!("Cannot divide two different types: `{:?}` and  `{:?}`", op1.variant, op2.variant)
-                                                            )?;
-                                                            continue;
-                                                        }
-                                                    }
-                                                }
-                                                _ => unreachable!()
-                                            }
-                                        }
-                                        None => {
-                                            self.program_counter = self.throw(
-                                                "UnsetRegister",
-                                                format!("The register `{op2}` has not been set yet.")
-                                            )?;
-                                            continue;
-                                        },
-                                    }
-                                }
-                                None => {
-                                    self.program_counter = self.throw(
-                                        "UnsetRegister",
-                                        format!("The register `{op1}` has not been set yet.")
-                                    )?;
-                                    continue;
-                                },
-                            }
-                        }
-                        Instruction::Rem(op1, op2, dst) => {
-                            match self.registers.get(op1).cloned() {
-                                Some(op1) => {
-                                    if !op1.variant.is_numeric() {
-                                        self.program_counter = self.throw(
-                                            "InvalidType",
-                                            format!("The type `{:?}` is not numeric", op1.variant)
-                                        )?;
-                                        continue;
-                                    }
-                                    match self.registers.get(op2).cloned() {
-                                        Some(op2) => {
-                                            if !op2.variant.is_numeric() {
-                                                self.program_counter = self.throw(
-                                                    "InvalidType",
-                                                    format!("The type `{:?}` is not numeric", op2.variant)
-                                                )?;
-                                                continue;
-                                            }
-
-                                            // Remainder implementation here
-                                            match op1.variant {
-                                                MiType::Int => {
-                                                    match op2.variant {
-                                                        MiType::Int => {
-                                                            let val1 = i32::from_le_bytes(op1.bytes.try_into().unwrap());
-                                                            let val2 = i32::from_le_bytes(op2.bytes.try_into().unwrap());
-                                                            self.registers.set(dst, MiValue {
-                                                                bytes: (val1 % val2).to_le_bytes().to_vec(),
-                                                                variant: MiType::Int,
-                                                            })?;
-                                                        }
-                                                        _ => {
-                                                            self.program_counter = self.throw(
-                                                                "InvalidType",
-                                                                format!("Cannot rem two different types: `{:?}` and  `{:?}`", op1.variant, op2.variant)
-                                                            )?;
-                                                            continue;
-                                                        }
-                                                    }
-                                                }
-                                                MiType::Float => {
-                                                    match op2.variant {
-                                                        MiType::Float => {
-                                                            let val1 = f64::from_le_bytes(op1.bytes.try_into().unwrap());
-                                                            let val2 = f64::from_le_bytes(op2.bytes.try_into().unwrap());
-                                                            self.registers.set(dst, MiValue {
-                                                                bytes: (val1 % val2).to_le_bytes().to_vec(),
-                                                                variant: MiType::Float,
-                                                            })?;
-                                                        }
-                                                        _ => {
-                                                            self.program_counter = self.throw(
-                                                                "InvalidType",
-                                                                format!("Cannot rem two different types: `{:?}` and  `{:?}`", op1.variant, op2.variant)
-                                                            )?;
-                                                            continue;
-                                                        }
-                                                    }
-                                                }
-                                                _ => unreachable!()
-                                            }
-                                        }
-                                        None => {
-                                            self.program_counter = self.throw(
-                                                "UnsetRegister",
-                                                format!("The register `{op2}` has not been set yet.")
-                                            )?;
-                                            continue;
-                                        },
-                                    }
-                                }
-                                None => {
-                                    self.program_counter = self.throw(
-                                        "UnsetRegister",
-                                        format!("The register `{op1}` has not been set yet.")
-                                    )?;
-                                    continue;
-                                },
-                            }
-                        }
-                        Instruction::Pow(op1, op2, dst) => {
-                            match self.registers.get(op1).cloned() {
-                                Some(op1) => {
-                                    if !op1.variant.is_numeric() {
-                                        self.program_counter = self.throw(
-                                            "InvalidType",
-                                            format!("The type `{:?}` is not numeric", op1.variant)
-                                        )?;
-                                        continue;
-                                    }
-                                    match self.registers.get(op2).cloned() {
-                                        Some(op2) => {
-                                            if !op2.variant.is_numeric() {
-                                                self.program_counter = self.throw(
-                                                    "InvalidType",
-                                                    format!("The type `{:?}` is not numeric", op2.variant)
-                                                )?;
-                                                continue;
-                                            }
-
-                                            // Power implementation here
-                                            match op1.variant {
-                                                MiType::Int => {
-                                                    match op2.variant {
-                                                        MiType::Int => {
-                                                            let val1 = i32::from_le_bytes(op1.bytes.try_into().unwrap());
-                                                            let val2 = i32::from_le_bytes(op2.bytes.try_into().unwrap());
-                                                            if val2 < 0 {
-                                                                self.program_counter = self.throw(
-                                                                    "MathError",
-                                                                    format!("The exponent `{val2}` is not valid as it needs to be positive")
-                                                                )?;
-                                                                continue;
-                                                            }
-                                                            self.registers.set(dst, MiValue {
-                                                                bytes: (val1.pow(val2 as u32)).to_le_bytes().to_vec(),
-                                                                variant: MiType::Int,
-                                                            })?;
-                                                        }
-                                                        _ => {
-                                                            self.program_counter = self.throw(
-                                                                "InvalidType",
-                                                                format!("Cannot power two different types: `{:?}` and  `{:?}`", op1.variant, op2.variant)
-                                                            )?;
-                                                            continue;
-                                                        }
-                                                    }
-                                                }
-                                                MiType::Float => {
-                                                    match op2.variant {
-                                                        MiType::Float => {
-                                                            let val1 = f64::from_le_bytes(op1.bytes.try_into().unwrap());
-                                                            let val2 = f64::from_le_bytes(op2.bytes.try_into().unwrap());
-                                                            self.registers.set(dst, MiValue {
-                                                                bytes: (val1.powf(val2)).to_le_bytes().to_vec(),
-                                                                variant: MiType::Float,
-                                                            })?;
-                                                        }
-                                                        _ => {
-                                                            self.program_counter = self.throw(
-                                                                "InvalidType",
-                                                                format!("Cannot add two different types: `{:?}` and  `{:?}`", op1.variant, op2.variant)
-                                                            )?;
-                                                            continue;
-                                                        }
-                                                    }
-                                                }
-                                                _ => unreachable!()
-                                            }
-                                        }
-                                        None => {
-                                            self.program_counter = self.throw(
-                                                "UnsetRegister",
-                                                format!("The register `{op2}` has not been set yet.")
-                                            )?;
-                                            continue;
-                                        },
-                                    }
-                                }
-                                None => {
-                                    self.program_counter = self.throw(
-                                        "UnsetRegister",
-                                        format!("The register `{op1}` has not been set yet.")
-                                    )?;
-                                    continue;
-                                },
-                            }
+    /// Links the program and pushes the `Main` frame, leaving the runtime ready
+    /// to execute the program one instruction at a time with `step`
+    pub fn start(&mut self) -> Result<(), MiError> {
+        self.ensure_linked()?;
+        let main = StackFrame::new(String::from("Main"), FxHashMap::default(), None);
+        // a call depth limit of 0 leaves no room for the main frame
+        self.stack.push_frame(main).map_err(|err| MiError {
+            name: "StackOverflow".to_string(),
+            message: err,
+            backtrace: self.get_backtrace(),
+        })
+    }
+
+    /// Calls a function defined by the program or a builtin from the host, with the arguments
+    /// given in declaration order, and returns the value left in the return register.
+    /// The registers of the runtime are left untouched by the call. Errors thrown by the
+    /// function are only caught by its own handlers, the others are returned.
+    pub fn call_function(&mut self, name: &str, args: Vec<MiValue>) -> Result<Option<MiValue>, MiError> {
+        self.ensure_linked()?;
+        match self.function_addr_table.get(name).cloned() {
+            Some((args_names, _, real_label)) => {
+                if args.len() != args_names.len() {
+                    return Err(MiError {
+                        name: "NotEnoughArguments".to_string(),
+                        message: format!("The function `{}` takes {} arguments, {} were given", name, args_names.len(), args.len()),
+                        backtrace: self.get_backtrace(),
+                    })
+                }
+                let args_hash = args_names.into_iter().zip(args).collect();
+                let depth = self.stack.frames().len();
+                if let Err(err) = self.stack.push_frame(StackFrame::new(name.to_string(), args_hash, None)) {
+                    return Err(MiError {
+                        name: "StackOverflow".to_string(),
+                        message: err,
+                        backtrace: self.get_backtrace(),
+                    })
+                }
+                // the frame doesn't hold the registers of the host, so returning doesn't write to them
+                let caller_registers = std::mem::replace(&mut self.registers, Registers::new());
+                let previous_counter = self.program_counter;
+                self.program_counter = real_label;
+                self.call_boundaries.push(depth);
+                let result = self.execute();
+                self.call_boundaries.pop();
+                // fatal errors leave the frames of the call on the stack
+                while self.stack.frames().len() > depth {
+                    self.stack.pop_frame();
+                }
+                self.registers = caller_registers;
+                self.program_counter = previous_counter;
+                result
+            }
+            None => match self.builtins.index_of(name) {
+                Some(index) => {
+                    if let Some(max_argument_stack) = self.limits.max_argument_stack {
+                        if self.argument_stack.len() + args.len() > max_argument_stack {
+                            return Err(self.argument_stack_overflow(max_argument_stack))
                         }
-                        Instruction::Or(op1, op2, dst) => {
-                            match self.registers.get(op1).cloned() {
-                                Some(op1) => {
-                                    if op1.variant != MiType::Bool {
-                                        self.program_counter = self.throw(
-                                            "InvalidType",
-                                            format!("The type `{:?}` is not boolean", op1.variant)
-                                        )?;
-                                        continue;
-                                    }
-                                    match self.registers.get(op2).cloned() {
-                                        Some(op2) => {
-                                            if op1.variant != MiType::Bool {
-                                                self.program_counter = self.throw(
-                                                    "InvalidType",
-                                                    format!("The type `{:?}` is not boolean", op2.variant)
-                                                )?;
-                                                continue;
-                                            }
-
-                                            // Or implementation here
-                                            let b1: bool = op1.bytes[0] != 0;
-                                            let b2: bool = op2.bytes[0] != 0;
-                                            self.registers.set(dst, MiValue {
-                                                bytes: if b1 || b2 { [1].to_vec() } else { [0].to_vec() },
-                                                variant: MiType::Bool,
-                                            })?;
-                                        }
-                                        None => {
-                                            self.program_counter = self.throw(
-                                                "UnsetRegister",
-                                                format!("The register `{op2}` has not been set yet.")
-                                            )?;
-                                            continue;
-                                        },
-                                    }
-                                }
-                                None => {
-                                    self.program_counter = self.throw(
-                                        "UnsetRegister",
-                                        format!("The register `{op1}` has not been set yet.")
-                                    )?;
-                                    continue;
-                                },
-                            }
+                    }
+                    // builtins take their first argument from the top of the argument stack
+                    self.argument_stack.extend(args.into_iter().rev());
+                    self.call_builtin(index).map(Some)
+                }
+                None => Err(MiError {
+                    name: "UndefinedFunction".to_string(),
+                    message: format!("Cannot call undefined function `{name}`"),
+                    backtrace: self.get_backtrace(),
+                }),
+            }
+        }
+    }
+
+    /// Executes instructions from the current program counter until the current frame returns
+    /// or the program ends
+    fn execute(&mut self) -> Result<Option<MiValue>, MiError> {
+        loop {
+            let step = if self.profiler.is_some() || self.tracer.is_some() { self.instrumented_step() } else { self.step() };
+            match step? {
+                Step::Running => continue,
+                Step::Returned(value) | Step::Finished(value) => return Ok(value),
+            }
+        }
+    }
+
+    /// Executes the instruction after the program counter, recording it in the profiler
+    /// and the tracer
+    fn instrumented_step(&mut self) -> Result<Step, MiError> {
+        let pc = self.next_index();
+        let instructions = Rc::clone(&self.instructions);
+        let Some(instruction) = instructions.get(pc) else { return self.step() };
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.sync(self.stack.frames());
+        }
+        let depth = self.stack.frames().len();
+        let traced = match self.tracer {
+            Some(_) => {
+                let frame = self.stack.last_frame().map(|frame| frame.name.clone()).unwrap_or_default();
+                // a return restores the window of the caller, so changes are relative to it
+                let before = match (instruction, self.stack.last_frame()) {
+                    (Instruction::Return, Some(StackFrame { caller_registers: Some(caller_registers), .. })) => caller_registers.clone(),
+                    _ => self.registers.clone(),
+                };
+                Some((frame, before))
+            }
+            None => None,
+        };
+
+        let started = Instant::now();
+        let step = self.step();
+        let elapsed = started.elapsed();
+
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.record(instruction.opcode(), elapsed);
+        }
+        if let (Some(tracer), Some((frame, before))) = (self.tracer.as_mut(), traced) {
+            // a call starts with an empty window, which has no changes to report
+            let changes = if self.stack.frames().len() > depth { Vec::new() } else { changed_registers(&before, &self.registers) };
+            if let Err(err) = tracer.trace(pc, instruction, &frame, &changes) {
+                return Err(self.new_error("IOError", format!("Error writing the trace: {err}")))
+            }
+        }
+        step
+    }
+
+    /// Executes the instruction after the program counter. Thrown errors move the program
+    /// counter to their handler, an `Err` is only returned when no handler caught them.
+    /// The program is linked first if it was not.
+    pub fn step(&mut self) -> Result<Step, MiError> {
+        self.ensure_linked()?;
+        // the instructions are shared so they can be borrowed while the runtime changes
+        let instructions = Rc::clone(&self.instructions);
+        let pc = (self.program_counter + 1) as usize;
+        let Some(instruction) = instructions.get(pc) else {
+            return Ok(Step::Finished(self.registers.get(RETURN_REGISTER).cloned()))
+        };
+        self.program_counter += 1;
+        self.executed_instructions += 1;
+        // exceeding the instruction or memory limits is fatal, so handlers cannot keep the program running
+        if let Some(max_instructions) = self.limits.max_instructions {
+            if self.executed_instructions > max_instructions {
+                return Err(self.new_error(
+                    "InstructionLimitExceeded",
+                    format!("The program exceeded the limit of {max_instructions} executed instructions")
+                ))
+            }
+        }
+        if let Some(max_value_bytes) = self.limits.max_value_bytes {
+            // a large operand may have been produced since the last check, so the values
+            // are counted early instead of waiting for the next interval
+            let largest = instruction.registers().into_iter()
+                .chain([RETURN_REGISTER])
+                .filter_map(|reg| self.registers.get(reg))
+                .map(shallow_bytes)
+                .max()
+                .unwrap_or(0);
+            if self.executed_instructions.is_multiple_of(MEMORY_CHECK_INTERVAL)
+                || self.value_bytes.saturating_add(largest) > max_value_bytes {
+                self.check_memory(max_value_bytes)?;
+            }
+        }
+        match *instruction {
+
+            Instruction::Move(reg, ref value) => {
+                self.registers.set(reg, value.clone())?;
+            }
+            Instruction::MoveBetween(src, dst) => {
+                match self.registers.get(src) {
+                    Some(value) => {
+                        self.registers.set(dst, value.clone())?;
+                    }
+                    None => {
+                        self.program_counter = self.throw(
+                            "UnsetRegister",
+                            format!("The register `{src} has not been set yet.")
+                        )?;
+                        return Ok(Step::Running);
+                    },
+                }
+            }
+            Instruction::MoveArgument(ref arg, reg) => {
+                match self.stack.last_frame().and_then(|frame| frame.args.get(arg)) {
+                    Some(value) => {
+                        self.registers.set(reg, value.clone())?;
+                    }
+                    None => {
+                        self.program_counter = self.throw(
+                            "UndefinedArgument",
+                            format!("The argument `{}` has not been defined yet.", arg)
+                        )?;
+                        return Ok(Step::Running);
+                    }
+                }
+            }
+            Instruction::MoveAsArgument(reg) => {
+                match self.registers.get(reg) {
+                    Some(value) => {
+                        let value = value.clone();
+                        if !self.check_argument_stack(1)? {
+                            return Ok(Step::Running);
                         }
-                        Instruction::Xor(op1, op2, dst) => {
-                            match self.registers.get(op1).cloned() {
-                                Some(op1) => {
-                                    if op1.variant != MiType::Bool {
-                                        self.program_counter = self.throw(
-                                            "InvalidType",
-                                            format!("The type `{:?}` is not boolean", op1.variant)
-                                        )?;
-                                        continue;
-                                    }
-                                    match self.registers.get(op2).cloned() {
-                                        Some(op2) => {
-                                            if op1.variant != MiType::Bool {
-                                                self.program_counter = self.throw(
-                                                    "InvalidType",
-                                                    format!("The type `{:?}` is not boolean", op2.variant)
-                                                )?;
-                                                continue;
-                                            }
-
-                                            // Or implementation here
-                                            let b1: bool = op1.bytes[0] != 0;
-                                            let b2: bool = op2.bytes[0] != 0;
-                                            self.registers.set(dst, MiValue {
-                                                bytes: if b1 ^ b2 { [1].to_vec() } else { [0].to_vec() },
-                                                variant: MiType::Bool,
-                                            })?;
-                                        }
-                                        None => {
-                                            self.program_counter = self.throw(
-                                                "UnsetRegister",
-                                                format!("The register `{op2}` has not been set yet.")
-                                            )?;
-                                            continue;
-                                        },
-                                    }
+                        self.argument_stack.push(value);
+                    }
+                    None => {
+                        self.program_counter = self.throw(
+                            "UnsetRegister",
+                            format!("The register `{reg}` has not been set yet.")
+                        )?;
+                        return Ok(Step::Running);
+                    }
+                }
+            }
+            Instruction::Add(op1, op2, dst) => {
+                self.binary_op(op1, op2, dst, operations::add)?;
+            }
+            Instruction::Sub(op1, op2, dst) => {
+                self.binary_op(op1, op2, dst, operations::sub)?;
+            }
+            Instruction::Mul(op1, op2, dst) => {
+                self.binary_op(op1, op2, dst, operations::mul)?;
+            }
+            Instruction::Div(op1, op2, dst) => {
+                self.binary_op(op1, op2, dst, operations::div)?;
+            }
+            Instruction::Rem(op1, op2, dst) => {
+                self.binary_op(op1, op2, dst, operations::rem)?;
+            }
+            Instruction::Pow(op1, op2, dst) => {
+                self.binary_op(op1, op2, dst, operations::pow)?;
+            }
+            Instruction::Or(op1, op2, dst) => {
+                self.binary_op(op1, op2, dst, operations::or)?;
+            }
+            Instruction::Xor(op1, op2, dst) => {
+                self.binary_op(op1, op2, dst, operations::xor)?;
+            }
+            Instruction::And(op1, op2, dst) => {
+                self.binary_op(op1, op2, dst, operations::and)?;
+            }
+            Instruction::Not(src, dst) => {
+                self.unary_op(src, dst, operations::not)?;
+            }
+            Instruction::Lt(op1, op2, dst) => {
+                self.binary_op(op1, op2, dst, operations::lt)?;
+            }
+            Instruction::Le(op1, op2, dst) => {
+                self.binary_op(op1, op2, dst, operations::le)?;
+            }
+            Instruction::Gt(op1, op2, dst) => {
+                self.binary_op(op1, op2, dst, operations::gt)?;
+            }
+            Instruction::Ge(op1, op2, dst) => {
+                self.binary_op(op1, op2, dst, operations::ge)?;
+            }
+            Instruction::Return => {
+                match self.stack.pop_frame() {
+                    Some(frame) => {
+                        let returned = self.registers.get(RETURN_REGISTER).cloned();
+                        if let Some(caller_registers) = frame.caller_registers {
+                            self.registers = caller_registers;
+                            // the fields set by a method are kept by the instance it was called on
+                            if let (Some(reg), Some(instance)) = (frame.receiver, frame.args.get("self")) {
+                                let previous = match self.registers.get(reg) {
+                                    Some(MiValue::Class(previous)) => Some(Rc::as_ptr(previous)),
+                                    _ => None,
+                                };
+                                self.registers.set(reg, instance.clone())?;
+                                // the caller may itself be a method called on the same instance
+                                if let Some(previous) = previous {
+                                    self.update_receiver(previous, instance);
                                 }
-                                None => {
-                                    self.program_counter = self.throw(
-                                        "UnsetRegister",
-                                        format!("The register `{op1}` has not been set yet.")
-                                    )?;
-                                    continue;
-                                },
                             }
-                        }
-                        Instruction::And(op1, op2, dst) => {
-                            match self.registers.get(op1).cloned() {
-                                Some(op1) => {
-                                    if op1.variant != MiType::Bool {
-                                        self.program_counter = self.throw(
-                                            "InvalidType",
-                                            format!("The type `{:?}` is not boolean", op1.variant)
-                                        )?;
-                                        continue;
-                                    }
-                                    match self.registers.get(op2).cloned() {
-                                        Some(op2) => {
-                                            if op1.variant != MiType::Bool {
-                                                self.program_counter = self.throw(
-                                                    "InvalidType",
-                                                    format!("The type `{:?}` is not boolean", op2.variant)
-                                                )?;
-                                                continue;
-                                            }
-
-                                            // Or implementation here
-                                            let b1: bool = op1.bytes[0] != 0;
-                                            let b2: bool = op2.bytes[0] != 0;
-                                            self.registers.set(dst, MiValue {
-                                                bytes: if b1 && b2 { [1].to_vec() } else { [0].to_vec() },
-                                                variant: MiType::Bool,
-                                            })?;
-                                        }
-                                        None => {
-                                            self.program_counter = self.throw(
-                                                "UnsetRegister",
-                                                format!("The register `{op2}` has not been set yet.")
-                                            )?;
-                                            continue;
-                                        },
-                                    }
-                                }
-                                None => {
-                                    self.program_counter = self.throw(
-                                        "UnsetRegister",
-                                        format!("The register `{op1}` has not been set yet.")
-                                    )?;
-                                    continue;
-                                },
+                            if let Some(value) = returned.clone() {
+                                self.registers.set(RETURN_REGISTER, value)?;
                             }
                         }
-                        Instruction::Not(src, dst) => {
-                            match self.registers.get(src).cloned() {
-                                Some(op1) => {
-                                    if op1.variant != MiType::Bool {
-                                        self.program_counter = self.throw(
-                                            "InvalidType",
-                                            format!("The type `{:?}` is not boolean", op1.variant)
-                                        )?;
-                                        continue;
-                                    }
-                                    let b1: bool = op1.bytes[0] != 0;
-                                    self.registers.set(dst, MiValue {
-                                        bytes: if !b1 { [1].to_vec() } else { [0].to_vec() },
-                                        variant: MiType::Bool,
-                                    })?;
-                                }
-                                None => {
-                                    self.program_counter = self.throw(
-                                        "UnsetRegister",
-                                        format!("The register `{src} has not been set yet.")
-                                    )?;
-                                    continue;
-                                },
+                        match frame.return_addr {
+                            Some(addr) => {
+                                self.program_counter = addr as i32;
                             }
-                        }
-                        Instruction::Lt(op1, op2, dst) => {
-                            match self.registers.get(op1).cloned() {
-                                Some(op1) => {
-                                    if !op1.variant.is_numeric() {
-                                        self.program_counter = self.throw(
-                                            "InvalidType",
-                                            format!("The type `{:?}` is not numeric", op1.variant)
-                                        )?;
-                                        continue;
-                                    }
-                                    match self.registers.get(op2).cloned() {
-                                        Some(op2) => {
-                                            if !op2.variant.is_numeric() {
-                                                self.program_counter = self.throw(
-                                                    "InvalidType",
-                                                    format!("The type `{:?}` is not numeric", op2.variant)
-                                                )?;
-                                                continue;
-                                            }
-
-                                            // Power implementation here
-                                            match op1.variant {
-                                                MiType::Int => {
-                                                    match op2.variant {
-                                                        MiType::Int => {
-                                                            let val1 = i32::from_le_bytes(op1.bytes.try_into().unwrap());
-                                                            let val2 = i32::from_le_bytes(op2.bytes.try_into().unwrap());
-                                                            self.registers.set(dst, MiValue {
-                                                                bytes: vec![(val1 < val2) as u8],
-                                                                variant: MiType::Bool,
-                                                            })?;
-                                                        }
-                                                        _ => {
-                                                            self.program_counter = self.throw(
-                                                                "InvalidType",
-                                                                format!("Cannot LT two different types: `{:?}` and  `{:?}`", op1.variant, op2.variant)
-                                                            )?;
-                                                            continue;
-                                                        }
-                                                    }
-                                                }
-                                                MiType::Float => {
-                                                    match op2.variant {
-                                                        MiType::Float => {
-                                                            let val1 = f64::from_le_bytes(op1.bytes.try_into().unwrap());
-                                                            let val2 = f64::from_le_bytes(op2.bytes.try_into().unwrap());
-                                                            self.registers.set(dst, MiValue {
-                                                                bytes: vec![(val1 < val2) as u8],
-                                                                variant: MiType::Bool,
-                                                            })?;
-                                                        }
-                                                        _ => {
-                                                            self.program_counter = self.throw(
-                                                                "InvalidType",
-                                                                format!("Cannot LT two different types: `{:?}` and  `{:?}`", op1.variant, op2.variant)
-                                                            )?;
-                                                            continue;
-                                                        }
-                                                    }
-                                                }
-                                                _ => unreachable!()
-                                            }
-                                        }
-                                        None => {
-                                            self.program_counter = self.throw(
-                                                "UnsetRegister",
-                                                format!("The register `{op2}` has not been set yet.")
-                                            )?;
-                                            continue;
-                                        },
-                                    }
-                                }
-                                None => {
-                                    self.program_counter = self.throw(
-                                        "UnsetRegister",
-                                        format!("The register `{op1}` has not been set yet.")
-                                    )?;
-                                    continue;
-                                },
+                            None => {
+                                return Ok(Step::Returned(returned));
                             }
                         }
-                        Instruction::Le(op1, op2, dst) => {
-                            match self.registers.get(op1).cloned() {
-                                Some(op1) => {
-                                    if !op1.variant.is_numeric() {
-                                        self.program_counter = self.throw(
-                                            "InvalidType",
-                                            format!("The type `{:?}` is not numeric", op1.variant)
-                                        )?;
-                                        continue;
-                                    }
-                                    match self.registers.get(op2).cloned() {
-                                        Some(op2) => {
-                                            if !op2.variant.is_numeric() {
-                                                self.program_counter = self.throw(
-                                                    "InvalidType",
-                                                    format!("The type `{:?}` is not numeric", op2.variant)
-                                                )?;
-                                                continue;
-                                            }
-
-                                            // Power implementation here
-                                            match op1.variant {
-                                                MiType::Int => {
-                                                    match op2.variant {
-                                                        MiType::Int => {
-                                                            let val1 = i32::from_le_bytes(op1.bytes.try_into().unwrap());
-                                                            let val2 = i32::from_le_bytes(op2.bytes.try_into().unwrap());
-                                                            self.registers.set(dst, MiValue {
-                                                                bytes: vec![(val1 <= val2) as u8],
-                                                                variant: MiType::Bool,
-                                                            })?;
-                                                        }
-                                                        _ => {
-                                                            self.program_counter = self.throw(
-                                                                "InvalidType",
-                                                                format!("Cannot LE two different types: `{:?}` and  `{:?}`", op1.variant, op2.variant)
-                                                            )?;
-                                                            continue;
-                                                        }
-                                                    }
-                                                }
-                                                MiType::Float => {
-                                                    match op2.variant {
-                                                        MiType::Float => {
-                                                            let val1 = f64::from_le_bytes(op1.bytes.try_into().unwrap());
-                                                            let val2 = f64::from_le_bytes(op2.bytes.try_into().unwrap());
-                                                            self.registers.set(dst, MiValue {
-                                                                bytes: vec![(val1 <= val2) as u8],
-                                                                variant: MiType::Bool,
-                                                            })?;
-                                                        }
-                                                        _ => {
-                                                            self.program_counter = self.throw(
-                                                                "InvalidType",
-                                                                format!("Cannot LE two different types: `{:?}` and  `{:?}`", op1.variant, op2.variant)
-                                                            )?;
-                                                            continue;
-                                                        }
-                                                    }
-                                                }
-                                                _ => unreachable!()
-                                            }
-                                        }
-                                        None => {
-                                            self.program_counter = self.throw(
-                                                "UnsetRegister",
-                                                format!("The register `{op2}` has not been set yet.")
-                                            )?;
-                                            continue;
-                                        },
-                                    }
-                                }
-                                None => {
-                                    self.program_counter = self.throw(
-                                        "UnsetRegister",
-                                        format!("The register `{op1}` has not been set yet.")
-                                    )?;
-                                    continue;
-                                },
+                    }
+                    None => {
+                        self.program_counter = self.throw("InvalidFrame", "There is no frame to return from")?;
+                        return Ok(Step::Running);
+                    }
+                }
+            }
+            Instruction::SetVariable(reg, ref name) => {
+                match self.registers.get(reg) {
+                    Some(value) => {
+                        let frame = self.stack.last_frame_mut();
+                        match frame {
+                            Some(frame) => {
+                                frame.local_variables.insert(name.clone(), value.clone());
                             }
-                        }
-                        Instruction::Gt(op1, op2, dst) => {
-                            match self.registers.get(op1).cloned() {
-                                Some(op1) => {
-                                    if !op1.variant.is_numeric() {
-                                        self.program_counter = self.throw(
-                                            "InvalidType",
-                                            format!("The type `{:?}` is not numeric", op1.variant)
-                                        )?;
-                                        continue;
-                                    }
-                                    match self.registers.get(op2).cloned() {
-                                        Some(op2) => {
-                                            if !op2.variant.is_numeric() {
-                                                self.program_counter = self.throw(
-                                                    "InvalidType",
-                                                    format!("The type `{:?}` is not numeric", op2.variant)
-                                                )?;
-                                                continue;
-                                            }
-
-                                            // Power implementation here
-                                            match op1.variant {
-                                                MiType::Int => {
-                                                    match op2.variant {
-                                                        MiType::Int => {
-                                                            let val1 = i32::from_le_bytes(op1.bytes.try_into().unwrap());
-                                                            let val2 = i32::from_le_bytes(op2.bytes.try_into().unwrap());
-                                                            self.registers.set(dst, MiValue {
-                                                                bytes: vec![(val1 > val2) as u8],
-                                                                variant: MiType::Bool,
-                                                            })?;
-                                                        }
-                                                        _ => {
-                                                            self.program_counter = self.throw(
-                                                                "InvalidType",
-                                                                format!("Cannot GT two different types: `{:?}` and  `{:?}`", op1.variant, op2.variant)
-                                                            )?;
-                                                            continue;
-                                                        }
-                                                    }
-                                                }
-                                                MiType::Float => {
-                                                    match op2.variant {
-                                                        MiType::Float => {
-                                                            let val1 = f64::from_le_bytes(op1.bytes.try_into().unwrap());
-                                                            let val2 = f64::from_le_bytes(op2.bytes.try_into().unwrap());
-                                                            self.registers.set(dst, MiValue {
-                                                                bytes: vec![(val1 > val2) as u8],
-                                                                variant: MiType::Bool,
-                                                            })?;
-                                                        }
-                                                        _ => {
-                                                            self.program_counter = self.throw(
-                                                                "InvalidType",
-                                                                format!("Cannot FT two different types: `{:?}` and  `{:?}`", op1.variant, op2.variant)
-                                                            )?;
-                                                            continue;
-                                                        }
-                                                    }
-                                                }
-                                                _ => unreachable!()
-                                            }
-                                        }
-                                        None => {
-                                            self.program_counter = self.throw(
-                                                "UnsetRegister",
-                                                format!("The register `{op2}` has not been set yet.")
-                                            )?;
-                                            continue;
-                                        },
-                                    }
-                                }
-                                None => {
-                                    self.program_counter = self.throw(
-                                        "UnsetRegister",
-                                        format!("The register `{op1}` has not been set yet.")
-                                    )?;
-                                    continue;
-                                },
+                            None => {
+                                self.program_counter = self.throw(
+                                    "InvalidFrame",
+                                    format!("There is no frame to set the variable `{name}` in")
+                                )?;
+                                return Ok(Step::Running);
                             }
                         }
-                        Instruction::Ge(op1, op2, dst) => {
-                            match self.registers.get(op1).cloned() {
-                                Some(op1) => {
-                                    if !op1.variant.is_numeric() {
-                                        self.program_counter = self.throw(
-                                            "InvalidType",
-                                            format!("The type `{:?}` is not numeric", op1.variant)
-                                        )?;
-                                        continue;
-                                    }
-                                    match self.registers.get(op2).cloned() {
-                                        Some(op2) => {
-                                            if !op2.variant.is_numeric() {
-                                                self.program_counter = self.throw(
-                                                    "InvalidType",
-                                                    format!("The type `{:?}` is not numeric", op2.variant)
-                                                )?;
-                                                continue;
-                                            }
-
-                                            // Power implementation here
-                                            match op1.variant {
-                                                MiType::Int => {
-                                                    match op2.variant {
-                                                        MiType::Int => {
-                                                            let val1 = i32::from_le_bytes(op1.bytes.try_into().unwrap());
-                                                            let val2 = i32::from_le_bytes(op2.bytes.try_into().unwrap());
-                                                            self.registers.set(dst, MiValue {
-                                                                bytes: vec![(val1 >= val2) as u8],
-                                                                variant: MiType::Bool,
-                                                            })?;
-                                                        }
-                                                        _ => {
-                                                            self.program_counter = self.throw(
-                                                                "InvalidType",
-                                                                format!("Cannot GE two different types: `{:?}` and  `{:?}`", op1.variant, op2.variant)
-                                                            )?;
-                                                            continue;
-                                                        }
-                                                    }
-                                                }
-                                                MiType::Float => {
-                                                    match op2.variant {
-                                                        MiType::Float => {
-                                                            let val1 = f64::from_le_bytes(op1.bytes.try_into().unwrap());
-                                                            let val2 = f64::from_le_bytes(op2.bytes.try_into().unwrap());
-                                                            self.registers.set(dst, MiValue {
-                                                                bytes: vec![(val1 >= val2) as u8],
-                                                                variant: MiType::Bool,
-                                                            })?;
-                                                        }
-                                                        _ => {
-                                                            self.program_counter = self.throw(
-                                                                "InvalidType",
-                                                                format!("Cannot GE two different types: `{:?}` and  `{:?}`", op1.variant, op2.variant)
-                                                            )?;
-                                                            continue;
-                                                        }
-                                                    }
-                                                }
-                                                _ => unreachable!()
-                                            }
-                                        }
-                                        None => {
-                                            self.program_counter = self.throw(
-                                                "UnsetRegister",
-                                                format!("The register `{op2}` has not been set yet.")
-                                            )?;
-                                            continue;
-                                        },
-                                    }
-                                }
-                                None => {
-                                    self.program_counter = self.throw(
-                                        "UnsetRegister",
-                                        format!("The register `{op1}` has not been set yet.")
-                                    )?;
-                                    continue;
-                                },
+                    }
+                    None => {
+                        self.program_counter = self.throw(
+                            "UnsetRegister",
+                            format!("The register `{}` is not valid.", reg)
+                        )?;
+                        return Ok(Step::Running);
+                    }
+                }
+            }
+            Instruction::MovFromVariable(ref name, reg) => {
+                let frame = self.stack.last_frame_mut();
+                match frame {
+                    Some(frame) => {
+                        let var = frame.local_variables.get(name);
+                        match var {
+                            Some(value) => {
+                                self.registers.set(reg, value.clone())?;
                             }
-                        }
-                        Instruction::Return => {
-                            match self.stack.pop_frame() {
-                                Some(frame) => match frame.return_addr {
-                                    Some(addr) => {
-                                        self.program_counter = addr as i32;
-                                    }
-                                    None => {
-                                        return Ok(self.registers.get(15).cloned());
-                                    }
-                                }
-                                None => panic!("No frame to return to")
+                            None => {
+                                self.program_counter = self.throw(
+                                    "UndefinedVariable",
+                                    format!("Cannot move value of variable `{}` to register `{}` because `{}` is not defined.", &name, reg, name)
+                                )?;
+                                return Ok(Step::Running);
                             }
                         }
-                        Instruction::SetVariable(reg, name) => {
-                            match self.registers.get(reg) {
-                                Some(value) => {
-                                    let frame = self.stack.last_frame_mut();
-                                    match frame {
-                                        Some(frame) => {
-                                            frame.local_variables.insert(name, value.clone());
-                                        }
-                                        None => {
-                                            panic!("Current frame is not valid")
-                                        }
-                                    }
-                                }
-                                None => {
-                                    self.program_counter = self.throw(
-                                        "UnsetRegister",
-                                        format!("The register `{}` is not valid.", reg)
-                                    )?;
-                                    continue;
-                                }
+                    }
+                    None => {
+                        self.program_counter = self.throw(
+                            "InvalidFrame",
+                            format!("There is no frame to read the variable `{name}` from")
+                        )?;
+                        return Ok(Step::Running);
+                    }
+                }
+            }
+            Instruction::ThrowFrom(reason_reg, msg_reg) => {
+                match self.registers.get(reason_reg) {
+                    Some(value) => {
+                        let reason = value.to_string();
+                        match self.registers.get(msg_reg) {
+                            Some(value) => {
+                                let msg = value.to_string();
+                                self.program_counter = self.throw(reason, msg)?;
+                                return Ok(Step::Running);
                             }
-                        }
-                        Instruction::MovFromVariable(name, reg) => {
-                            let frame = self.stack.last_frame_mut();
-                            match frame {
-                                Some(frame) => {
-                                    let var = frame.local_variables.get(&name);
-                                    match var {
-                                        Some(value) => {
-                                            self.registers.set(reg, value.clone())?;
-                                        }
-                                        None => {
-                                            self.program_counter = self.throw(
-                                                "UndefinedVariable",
-                                                format!("Cannot move value of variable `{}` to register `{}` because `{}` is not defined.", &name, reg, name)
-                                            )?;
-                                            continue;
-                                        }
-                                    }
-                                }
-                                None => {
-                                    panic!("Current frame is not valid")
-                                }
+                            None => {
+                                self.program_counter = self.throw(
+                                    "UnsetRegister",
+                                    format!("The register `{msg_reg}` has not been set yet.")
+                                )?;
+                                return Ok(Step::Running);
                             }
                         }
-                        Instruction::ThrowFrom(reason_reg, msg_reg) => {
-                            match self.registers.get(reason_reg) {
-                                Some(value) => {
-                                    let reason = String::from_utf8_lossy(&value.bytes).to_string();
-                                    match self.registers.get(msg_reg) {
-                                        Some(value) => {
-                                            let msg = String::from_utf8_lossy(&value.bytes).to_string();
-                                            self.program_counter = self.throw(reason, msg)?;
-                                            continue;
-                                        }
-                                        None => {
-                                            self.program_counter = self.throw(
-                                                "UnsetRegister",
-                                                format!("The register `{msg_reg}` has not been set yet.")
-                                            )?;
-                                            continue;
-                                        }
-                                    }
-                                }
-                                None => {
-                                    self.program_counter = self.throw(
-                                        "UnsetRegister",
-                                        format!("The register `{reason_reg}` has not been set yet.")
-                                    )?;
-                                    continue;
-                                }
-                            }
+                    }
+                    None => {
+                        self.program_counter = self.throw(
+                            "UnsetRegister",
+                            format!("The register `{reason_reg}` has not been set yet.")
+                        )?;
+                        return Ok(Step::Running);
+                    }
+                }
+            }
+            Instruction::Eq(op1, op2, dst) => {
+                self.binary_op(op1, op2, dst, operations::eq)?;
+            }
+            Instruction::Ne(op1, op2, dst) => {
+                self.binary_op(op1, op2, dst, operations::ne)?;
+            }
+            Instruction::DefineLabel(_) => {
+                return Ok(Step::Running);
+            }
+            Instruction::JumpUnconditional(_) => {
+                self.program_counter = self.label_target(pc);
+            }
+            Instruction::JumpConditional(reg, _) => {
+                let Some(value) = self.read_typed(reg, MiType::Bool)? else { return Ok(Step::Running) };
+                if value == MiValue::Bool(true) {
+                    self.program_counter = self.label_target(pc);
+                }
+            }
+            Instruction::Call(ref name) => {
+                match self.links[pc] {
+                    Link::Function(real_label) => {
+                        let Instruction::DefineFnLabel(_, ref args_names, _) = instructions[real_label as usize] else {
+                            unreachable!("functions are linked to their definition")
+                        };
+                        self.enter_function(name, args_names, real_label, None)?;
+                        return Ok(Step::Running);
+                    }
+                    Link::Builtin(index) => match self.call_builtin(index) {
+                        Ok(value) => {
+                            self.registers.set(RETURN_REGISTER, value)?;
                         }
-                        Instruction::Eq(op1, op2, dst) => {
-                            match self.registers.get(op1) {
-                                Some(op1) => {
-                                    match self.registers.get(op2) {
-                                        Some(op2) => {
-                                            self.registers.set(dst, MiValue {
-                                                bytes: vec![(op1 == op2) as u8],
-                                                variant: MiType::Bool,
-                                            })?;
-                                        }
-                                        None => {
-                                            self.program_counter = self.throw(
-                                                "UnsetRegister",
-                                                format!("The register `{op2}` has not been set yet.")
-                                            )?;
-                                            continue;
-                                        }
-                                    }
-                                }
-                                None => {
-                                    self.program_counter = self.throw(
-                                        "UnsetRegister",
-                                        format!("The register `{op1}` has not been set yet.")
-                                    )?;
-                                    continue;
-                                }
-                            }
+                        Err(error) => {
+                            self.program_counter = self.throw(error.name, error.message)?;
+                            return Ok(Step::Running);
                         }
-                        Instruction::Ne(op1, op2, dst) => {
-                            match self.registers.get(op1) {
-                                Some(op1) => {
-                                    match self.registers.get(op2) {
-                                        Some(op2) => {
-                                            self.registers.set(dst, MiValue {
-                                                bytes: vec![(op1 != op2) as u8],
-                                                variant: MiType::Bool,
-                                            })?;
-                                        }
-                                        None => {
-                                            self.program_counter = self.throw(
-                                                "UnsetRegister",
-                                                format!("The register `{op2}` has not been set yet.")
-                                            )?;
-                                            continue;
-                                        }
-                                    }
-                                }
-                                None => {
-                                    self.program_counter = self.throw(
-                                        "UnsetRegister",
-                                        format!("The register `{op1}` has not been set yet.")
-                                    )?;
-                                    continue;
-                                }
+                    }
+                    _ => unreachable!("calls are linked before running"),
+                }
+            }
+            Instruction::DefineFnLabel(..) => {
+                self.program_counter = self.label_target(pc);
+            }
+            Instruction::StdoutWrite(reg) => {
+                match self.registers.get(reg) {
+                    Some(value) => {
+                        let res = write!(self.stdout, "{}", value);
+                        match res {
+                            Ok(_) => {
+                                return Ok(Step::Running);
                             }
-                        }
-                        Instruction::DefineLabel(name) => {
-                            continue;
-                        }
-                        Instruction::JumpUnconditional(name) => {
-                            if let Some(label_pos) = self.labels.get(&name) {
-                                self.program_counter = label_pos + 1;
-                            } else {
+                            Err(err) => {
                                 self.program_counter = self.throw(
-                                    "UnsetLabel",
-                                    format!("The label `{name}` is currently not defined.")
+                                    "IOError",
+                                    format!("Error writing to stdout: {}", err)
                                 )?;
-                                continue;
+                                return Ok(Step::Running);
                             }
                         }
-                        Instruction::JumpConditional(reg, name) => {
-                            if let Some(value) = self.registers.get(reg) {
-                                if value.bytes[0] == 1u8 {
-                                    if let Some(label_pos) = self.labels.get(&name) {
-                                        self.program_counter = label_pos + 1;
-                                    } else {
-                                        self.program_counter = self.throw(
-                                            "UnsetLabel",
-                                            format!("The label `{name}` is currently not defined.")
-                                        )?;
-                                        continue;
-                                    }
-                                }
-                            } else {
+                    }
+                    None => {
+                        self.program_counter = self.throw(
+                            "UnsetRegister",
+                            format!("The register `{reg}` has not been set yet.")
+                        )?;
+                        return Ok(Step::Running);
+                    }
+                }
+            }
+            Instruction::StdoutWriteDebugged(reg) => {
+                match self.registers.get(reg) {
+                    Some(value) => {
+                        let res = write!(self.stdout, "{}", value.to_string_debugged());
+                        match res {
+                            Ok(_) => {
+                                return Ok(Step::Running);
+                            }
+                            Err(err) => {
                                 self.program_counter = self.throw(
-                                    "UnsetRegister",
-                                    format!("The register `{reg}` has not been set yet.")
+                                    "IOError",
+                                    format!("Error writing to stdout: {}", err)
                                 )?;
-                                continue;
+                                return Ok(Step::Running);
                             }
                         }
-                        Instruction::Call(name) => {
-                            let funname = name.clone();
-                            let fun = self.function_addr_table.get(&name).cloned();
-                            match fun {
-                                Some((args_names, _, real_label)) => {
-                                    let mut args_hash = FxHashMap::default();
-                                    let mut reversed_names = args_names.iter().rev().collect::<Vec<_>>();
-                                    while let Some(name) = reversed_names.pop() {
-                                        let value = self.argument_stack.pop();
-                                        if let Some(value) = value {
-                                            args_hash.insert(name.clone(), value);
-                                        } else {
-                                            self.program_counter = self.throw(
-                                                "NotEnoughArguments",
-                                                format!("Cannot satisfy the arguments size for the function `{}`: {}", &funname, args_names.len())
-                                            )?;
-                                            continue;
-                                        }
-                                    }
-                                    let has_overflowed: Result<(), String> = self.stack.push_frame(StackFrame {
-                                        name: name.clone(),
-                                        args: args_hash,
-                                        local_variables: FxHashMap::default(),
-                                        return_addr: Some((self.program_counter + 1) as usize),
-                                        handles_error: false,
-                                        error_handling_addr: 0
-                                    });
-                                    self.program_counter = real_label;
-                                    if let Err(err) = has_overflowed {
-                                        self.program_counter = self.throw(
-                                            "StackOverflow",
-                                            err,
-                                        )?;
-                                    }
-                                
-                                    continue;
-                                }
-                                None => {
-                                    self.program_counter = self.throw(
-                                        "UndefinedFunction",
-                                        format!("Cannot call undefined function `{name}`")
-                                    )?;
-                                    continue;
-                                }
-                            }
-                        }
-                        Instruction::DefineFnLabel(name, args, returns) => {
-                            while let Some(instruction) = self.instructions.get((self.program_counter + 1) as usize) {
-                                self.program_counter += 1;
-                                match instruction {
-                                    Instruction::EndFunction => break,
-                                    _ => continue,
-                                }
-                            }
-                        }
-                        Instruction::StdoutWrite(reg) => {
-                            match self.registers.get(reg) {
-                                Some(value) => {
-                                    let res = write!(self.stdout_lock, "{}", value.to_string());
-                                    match res {
-                                        Ok(_) => {
-                                            continue;
-                                        }
-                                        Err(err) => {
-                                            self.program_counter = self.throw(
-                                                "IOError",
-                                                format!("Error writing to stderr: {}", err)
-                                            )?;
-                                            continue;
-                                        }
-                                    }
-                                }
-                                None => {
-                                    self.program_counter = self.throw(
-                                        "UnsetRegister",
-                                        format!("The register `{reg}` has not been set yet.")
-                                    )?;
-                                    continue;
-                                }
+                    }
+                    None => {
+                        self.program_counter = self.throw(
+                            "UnsetRegister",
+                            format!("The register `{reg}` has not been set yet.")
+                        )?;
+                        return Ok(Step::Running);
+                    }
+                }
+            }
+            Instruction::StdoutFlush => {
+                if let Err(err) = self.stdout.flush() {
+                    self.program_counter = self.throw(
+                        "IOError",
+                        format!("Error flushing stdout: {}", err)
+                    )?;
+                    return Ok(Step::Running);
+                }
+            }
+            Instruction::StderrWrite(reg) => {
+                match self.registers.get(reg) {
+                    Some(value) => {
+                        let res = write!(self.stderr, "{}", value);
+                        match res {
+                            Ok(_) => {
+                                return Ok(Step::Running);
                             }
-                        }
-                        Instruction::StdoutWriteDebugged(reg) => {
-                            match self.registers.get(reg) {
-                                Some(value) => {
-                                    let res = write!(self.stdout_lock, "{}", value.to_string_debugged());
-                                    match res {
-                                        Ok(_) => {
-                                            continue;
-                                        }
-                                        Err(err) => {
-                                            self.program_counter = self.throw(
-                                                "IOError",
-                                                format!("Error writing to stderr: {}", err)
-                                            )?;
-                                            continue;
-                                        }
-                                    }
-                                }
-                                None => {
-                                    self.program_counter = self.throw(
-                                        "UnsetRegister",
-                                        format!("The register `{reg}` has not been set yet.")
-                                    )?;
-                                    continue;
-                                }
+                            Err(err) => {
+                                self.program_counter = self.throw(
+                                    "IOError",
+                                    format!("Error writing to stderr: {}", err)
+                                )?;
+                                return Ok(Step::Running);
                             }
                         }
-                        Instruction::StdoutFlush => {
-                            stdout().flush().unwrap();
-                        }
-                        Instruction::StderrWrite(reg) => {
-                            match self.registers.get(reg) {
-                                Some(value) => {
-                                    let res = write!(self.stderr_lock, "{}", value.to_string());
-                                    match res {
-                                        Ok(_) => {
-                                            continue;
-                                        }
-                                        Err(err) => {
-                                            self.program_counter = self.throw(
-                                                "IOError",
-                                                format!("Error writing to stderr: {}", err)
-                                            )?;
-                                            continue;
-                                        }
-                                    }
-                                }
-                                None => {
-                                    self.program_counter = self.throw(
-                                        "UnsetRegister",
-                                        format!("The register `{reg}` has not been set yet.")
-                                    )?;
-                                    continue;
-                                }
+                    }
+                    None => {
+                        self.program_counter = self.throw(
+                            "UnsetRegister",
+                            format!("The register `{reg}` has not been set yet.")
+                        )?;
+                        return Ok(Step::Running);
+                    }
+                }
+            }
+            Instruction::StderrWriteDebugged(reg) => {
+                match self.registers.get(reg) {
+                    Some(value) => {
+                        let res = write!(self.stderr, "{}", value.to_string_debugged());
+                        match res {
+                            Ok(_) => {
+                                return Ok(Step::Running);
                             }
-                        }
-                        Instruction::StderrWriteDebugged(reg) => {
-                            match self.registers.get(reg) {
-                                Some(value) => {
-                                    let res = write!(self.stderr_lock, "{}", value.to_string_debugged());
-                                    match res {
-                                        Ok(_) => {
-                                            continue;
-                                        }
-                                        Err(err) => {
-                                            self.program_counter = self.throw(
-                                                "IOError",
-                                                format!("Error writing to stderr: {}", err)
-                                            )?;
-                                            continue;
-                                        }
-                                    }
-                                }
-                                None => {
-                                    self.program_counter = self.throw(
-                                        "UnsetRegister",
-                                        format!("The register `{reg}` has not been set yet.")
-                                    )?;
-                                    continue;
-                                }
+                            Err(err) => {
+                                self.program_counter = self.throw(
+                                    "IOError",
+                                    format!("Error writing to stderr: {}", err)
+                                )?;
+                                return Ok(Step::Running);
                             }
                         }
-                        Instruction::StderrFlush => {
-                            stderr().flush().unwrap();
-                        }
-                        Instruction::BufferedStdinRead(reg) => {
-                            let mut buf = String::new();
-                            let line = stdin().read_line(&mut buf);
-                            match line {
-                                Ok(_) => {
-                                    self.registers.set(reg, buf.into_value())?;
-                                }
-                                Err(err) => {
-                                    self.program_counter = self.throw(
-                                        "IOError",
-                                        format!("Unable to read a line from stdin: {}", err)
-                                    )?;
-                                    continue;
-                                }
-                            }
+                    }
+                    None => {
+                        self.program_counter = self.throw(
+                            "UnsetRegister",
+                            format!("The register `{reg}` has not been set yet.")
+                        )?;
+                        return Ok(Step::Running);
+                    }
+                }
+            }
+            Instruction::StderrFlush => {
+                if let Err(err) = self.stderr.flush() {
+                    self.program_counter = self.throw(
+                        "IOError",
+                        format!("Error flushing stderr: {}", err)
+                    )?;
+                    return Ok(Step::Running);
+                }
+            }
+            Instruction::BufferedStdinRead(reg) => {
+                let mut buf = String::new();
+                let line = self.stdin.read_line(&mut buf);
+                match line {
+                    Ok(_) => {
+                        self.registers.set(reg, buf.into_value())?;
+                    }
+                    Err(err) => {
+                        self.program_counter = self.throw(
+                            "IOError",
+                            format!("Unable to read a line from stdin: {}", err)
+                        )?;
+                        return Ok(Step::Running);
+                    }
+                }
+            }
+            Instruction::EndFunction => {
+                return Ok(Step::Running);
+            }
+            Instruction::Try(_, name_reg, message_reg) => {
+                let handler = ErrorHandler {
+                    addr: self.label_target(pc) as usize,
+                    name_reg,
+                    message_reg,
+                };
+                match self.stack.last_frame_mut() {
+                    Some(frame) => frame.error_handlers.push(handler),
+                    None => {
+                        self.program_counter = self.throw("InvalidFrame", "There is no frame to install the error handler in")?;
+                        return Ok(Step::Running);
+                    }
+                }
+            }
+            Instruction::EndTry => {
+                if let Some(frame) = self.stack.last_frame_mut() {
+                    frame.error_handlers.pop();
+                }
+            }
+            Instruction::ArrayNew(dst) => {
+                self.registers.set(dst, Vec::<MiValue>::new().into_value())?;
+            }
+            Instruction::ArrayPush(arr, val) => {
+                let Some(value) = self.read_register(val)? else { return Ok(Step::Running) };
+                let Some(array) = self.array_mut(arr)? else { return Ok(Step::Running) };
+                array.push(value);
+            }
+            Instruction::ArrayPop(arr, dst) => {
+                let Some(array) = self.array_mut(arr)? else { return Ok(Step::Running) };
+                match array.pop() {
+                    Some(value) => {
+                        self.registers.set(dst, value)?;
+                    }
+                    None => {
+                        self.program_counter = self.throw(
+                            "IndexOutOfBounds",
+                            "Cannot pop an element from an empty array"
+                        )?;
+                        return Ok(Step::Running);
+                    }
+                }
+            }
+            Instruction::ArrayGet(arr, idx, dst) => {
+                let Some(array) = self.read_array(arr)? else { return Ok(Step::Running) };
+                let Some(index) = self.read_int(idx)? else { return Ok(Step::Running) };
+                let Some(index) = self.check_index(index, array.len())? else { return Ok(Step::Running) };
+                self.registers.set(dst, array[index].clone())?;
+            }
+            Instruction::ArraySet(arr, idx, val) => {
+                let Some(len) = self.read_array(arr)?.map(|array| array.len()) else { return Ok(Step::Running) };
+                let Some(index) = self.read_int(idx)? else { return Ok(Step::Running) };
+                let Some(index) = self.check_index(index, len)? else { return Ok(Step::Running) };
+                let Some(value) = self.read_register(val)? else { return Ok(Step::Running) };
+                let Some(array) = self.array_mut(arr)? else { return Ok(Step::Running) };
+                array[index] = value;
+            }
+            Instruction::ArrayLen(arr, dst) => {
+                let Some(array) = self.read_array(arr)? else { return Ok(Step::Running) };
+                self.registers.set(dst, (array.len() as i64).into_value())?;
+            }
+            Instruction::ArraySlice(arr, start, end, dst) => {
+                let Some(array) = self.read_array(arr)? else { return Ok(Step::Running) };
+                let Some(start) = self.read_int(start)? else { return Ok(Step::Running) };
+                let Some(end) = self.read_int(end)? else { return Ok(Step::Running) };
+                if start < 0 || end < start || end as usize > array.len() {
+                    self.program_counter = self.throw(
+                        "IndexOutOfBounds",
+                        format!("The range `{start}..{end}` is out of bounds for an array of length {}", array.len())
+                    )?;
+                    return Ok(Step::Running);
+                }
+                self.registers.set(dst, array[start as usize..end as usize].to_vec().into_value())?;
+            }
+            Instruction::MapNew(dst) => {
+                self.registers.set(dst, BTreeMap::<MapKey, MiValue>::new().into_value())?;
+            }
+            Instruction::MapInsert(map_reg, key, val) => {
+                let Some(key) = self.read_key(key)? else { return Ok(Step::Running) };
+                let Some(value) = self.read_register(val)? else { return Ok(Step::Running) };
+                let Some(map) = self.map_mut(map_reg)? else { return Ok(Step::Running) };
+                map.insert(key, value);
+            }
+            Instruction::MapGet(map_reg, key, dst) => {
+                let Some(map) = self.read_map(map_reg)? else { return Ok(Step::Running) };
+                let Some(key) = self.read_key(key)? else { return Ok(Step::Running) };
+                match map.get(&key) {
+                    Some(value) => {
+                        self.registers.set(dst, value.clone())?;
+                    }
+                    None => {
+                        self.program_counter = self.throw(
+                            "KeyError",
+                            format!("The key `{}` is not present in the map", key.into_value())
+                        )?;
+                        return Ok(Step::Running);
+                    }
+                }
+            }
+            Instruction::MapRemove(map_reg, key, dst) => {
+                let Some(key) = self.read_key(key)? else { return Ok(Step::Running) };
+                let Some(map) = self.map_mut(map_reg)? else { return Ok(Step::Running) };
+                match map.remove(&key) {
+                    Some(value) => {
+                        self.registers.set(dst, value)?;
+                    }
+                    None => {
+                        self.program_counter = self.throw(
+                            "KeyError",
+                            format!("The key `{}` is not present in the map", key.into_value())
+                        )?;
+                        return Ok(Step::Running);
+                    }
+                }
+            }
+            Instruction::MapContains(map_reg, key, dst) => {
+                let Some(map) = self.read_map(map_reg)? else { return Ok(Step::Running) };
+                let Some(key) = self.read_key(key)? else { return Ok(Step::Running) };
+                self.registers.set(dst, map.contains_key(&key).into_value())?;
+            }
+            Instruction::MapKeys(map_reg, dst) => {
+                let Some(map) = self.read_map(map_reg)? else { return Ok(Step::Running) };
+                let keys = map.keys().map(|key| key.into_value()).collect::<Vec<MiValue>>();
+                self.registers.set(dst, keys.into_value())?;
+            }
+            Instruction::MapLen(map_reg, dst) => {
+                let Some(map) = self.read_map(map_reg)? else { return Ok(Step::Running) };
+                self.registers.set(dst, (map.len() as i64).into_value())?;
+            }
+            Instruction::DefineClass(_) => {
+                self.program_counter = self.label_target(pc);
+            }
+            Instruction::ClassField(..) | Instruction::EndClass => {
+                return Ok(Step::Running);
+            }
+            Instruction::New(ref name, dst) => {
+                match self.classes.get(name) {
+                    Some(blueprint) => {
+                        let class = Class {
+                            name: name.clone(),
+                            properties: blueprint.variables
+                                .keys()
+                                .map(|field| (field.clone(), MiValue::None))
+                                .collect(),
+                        };
+                        self.registers.set(dst, class.into_value())?;
+                    }
+                    None => {
+                        self.program_counter = self.throw(
+                            "UndefinedClass",
+                            format!("Cannot instantiate undefined class `{name}`")
+                        )?;
+                        return Ok(Step::Running);
+                    }
+                }
+            }
+            Instruction::GetField(obj, ref field, dst) => {
+                let Some(class) = self.read_class(obj)? else { return Ok(Step::Running) };
+                match class.properties.get(field) {
+                    Some(value) => {
+                        self.registers.set(dst, value.clone())?;
+                    }
+                    None => {
+                        self.program_counter = self.throw(
+                            "UndefinedField",
+                            format!("The class `{}` has no field `{field}`", class.name)
+                        )?;
+                        return Ok(Step::Running);
+                    }
+                }
+            }
+            Instruction::SetField(obj, ref field, src) => {
+                let Some(class) = self.read_class(obj)? else { return Ok(Step::Running) };
+                let Some(value) = self.read_register(src)? else { return Ok(Step::Running) };
+                let declared = self.classes.get(&class.name).and_then(|blueprint| blueprint.variables.get(field));
+                match declared {
+                    Some(variant) => {
+                        if *variant != value.variant() && value != MiValue::None {
+                            self.program_counter = self.throw(
+                                "InvalidType",
+                                format!("The field `{}.{field}` is of type `{:?}`, found `{:?}`", class.name, variant, value.variant())
+                            )?;
+                            return Ok(Step::Running);
                         }
-                        Instruction::EndFunction => {
-                            continue;
+                        // release the instance so it is only copied when another register shares it
+                        drop(class);
+                        if let Some(MiValue::Class(class)) = self.registers.get_mut(obj) {
+                            let previous = Rc::as_ptr(class);
+                            Rc::make_mut(class).properties.insert(field.clone(), value);
+                            let updated = MiValue::Class(class.clone());
+                            self.update_receiver(previous, &updated);
                         }
                     }
+                    None => {
+                        self.program_counter = self.throw(
+                            "UndefinedField",
+                            format!("The class `{}` has no field `{field}`", class.name)
+                        )?;
+                        return Ok(Step::Running);
+                    }
+                }
+            }
+            Instruction::Cast(src, ref variant, dst) => {
+                let Some(value) = self.read_register(src)? else { return Ok(Step::Running) };
+                match value.cast(variant) {
+                    Ok(value) => {
+                        self.registers.set(dst, value)?;
+                    }
+                    Err(message) => {
+                        self.program_counter = self.throw("ConversionError", message)?;
+                        return Ok(Step::Running);
+                    }
+                }
+            }
+            Instruction::TypeOf(src, dst) => {
+                let Some(value) = self.read_register(src)? else { return Ok(Step::Running) };
+                self.registers.set(dst, value.variant().name().into_value())?;
+            }
+            Instruction::CallMethod(obj, ref method) => {
+                let Some(receiver) = self.read_register(obj)? else { return Ok(Step::Running) };
+                let Some(class) = self.read_class(obj)? else { return Ok(Step::Running) };
+                let qualified_name = format!("{}.{}", class.name, method);
+                match self.function_addr_table.get(&qualified_name).map(|(_, _, addr)| *addr) {
+                    Some(real_label) => {
+                        let Instruction::DefineFnLabel(_, ref args_names, _) = instructions[real_label as usize] else {
+                            unreachable!("methods are registered at their definition")
+                        };
+                        self.enter_function(&qualified_name, args_names, real_label, Some((obj, receiver)))?;
+                        return Ok(Step::Running);
+                    }
+                    None => {
+                        self.program_counter = self.throw(
+                            "UndefinedMethod",
+                            format!("The class `{}` has no method `{method}`", class.name)
+                        )?;
+                        return Ok(Step::Running);
+                    }
                 }
-                None => break,
             }
         }
-
-        return Ok(self.registers.get(15).cloned())
+        Ok(Step::Running)
     }
 
     /// Returns an `Option<Instruction>` representing the current instruction according to the current program counter.
@@ -1475,19 +1077,406 @@ impl<'rtm> MirageRuntime<'rtm> {
         }
     }
 
-    /// Unwinds the stack frames looking for an error handler
+    /// Returns the index of the instruction `step` executes next
+    pub fn next_index(&self) -> usize {
+        (self.program_counter + 1) as usize
+    }
+
+    /// Returns the debug info of the program, if it was built with it
+    pub fn debug_info(&self) -> Option<&DebugInfo> {
+        self.debug_info.as_ref()
+    }
+
+    /// Sets the debug info used to map instructions back to the source
+    pub fn set_debug_info(&mut self, debug_info: DebugInfo) {
+        self.debug_info = Some(debug_info);
+    }
+
+    /// Starts profiling the instructions executed by `run` and `call_function`
+    pub fn enable_profiling(&mut self) {
+        self.profiler = Some(Profiler::new());
+    }
+
+    /// Returns the profiler, if profiling was enabled
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    /// Starts writing a line for every instruction executed by `run` and `call_function`
+    pub fn enable_tracing<W: Write + 'rtm>(&mut self, output: W, format: TraceFormat) {
+        self.tracer = Some(Tracer::new(output, format));
+    }
+
+    /// Flushes the lines written by the tracer, if tracing was enabled
+    pub fn flush_trace(&mut self) -> std::io::Result<()> {
+        match self.tracer.as_mut() {
+            Some(tracer) => tracer.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the instructions of the program
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.instructions
+    }
+
+    /// Returns the index of the specified label
+    pub fn label_index(&self, name: &str) -> Option<usize> {
+        self.labels.get(name).map(|pos| *pos as usize)
+    }
+
+    /// Returns the call stack of the runtime
+    pub fn call_stack(&self) -> &CallStack {
+        &self.stack
+    }
+
+    /// Returns the label the instruction at the specified index was linked to
+    fn label_target(&self, pc: usize) -> i32 {
+        match self.links[pc] {
+            Link::Label(label_pos) => label_pos,
+            _ => unreachable!("jumps are linked before running"),
+        }
+    }
+
+    /// Pushes the frame of a defined function, taking its arguments from the argument stack,
+    /// and moves the program counter to its body. The receiver of a method call, given with
+    /// the register holding it, is bound to the `self` argument and written back to the
+    /// register on return. If an error is thrown, the program counter is moved to its handler.
+    fn enter_function(&mut self, name: &str, args_names: &[String], real_label: i32, receiver: Option<(usize, MiValue)>) -> Result<(), MiError> {
+        let mut args_hash = FxHashMap::default();
+        for arg_name in args_names {
+            match self.argument_stack.pop() {
+                Some(value) => {
+                    args_hash.insert(arg_name.clone(), value);
+                }
+                None => {
+                    self.program_counter = self.throw(
+                        "NotEnoughArguments",
+                        format!("Cannot satisfy the arguments size for the function `{}`: {}", name, args_names.len())
+                    )?;
+                    return Ok(());
+                }
+            }
+        }
+        let receiver = receiver.map(|(reg, value)| {
+            args_hash.insert("self".to_string(), value);
+            reg
+        });
+        let has_overflowed: Result<(), String> = self.stack.push_frame(StackFrame {
+            name: name.to_string(),
+            args: args_hash,
+            local_variables: FxHashMap::default(),
+            return_addr: Some(self.program_counter as usize),
+            error_handlers: Vec::new(),
+            caller_registers: None,
+            receiver,
+        });
+        if let Err(err) = has_overflowed {
+            self.program_counter = self.throw("StackOverflow", err)?;
+            return Ok(());
+        }
+        // the callee starts with a fresh register window, the caller's
+        // one is restored when the callee returns
+        let caller_registers = std::mem::replace(&mut self.registers, Registers::new());
+        self.stack.last_frame_mut().unwrap().caller_registers = Some(caller_registers);
+        self.program_counter = real_label;
+        Ok(())
+    }
+
+    /// Replaces the `self` argument of the current method with the updated instance if it
+    /// was the previous version of it, so that the method returns the changes made to it
+    fn update_receiver(&mut self, previous: *const Class, updated: &MiValue) {
+        let Some(frame) = self.stack.last_frame_mut().filter(|frame| frame.receiver.is_some()) else { return };
+        if matches!(frame.args.get("self"), Some(MiValue::Class(instance)) if Rc::as_ptr(instance) == previous) {
+            frame.args.insert("self".to_string(), updated.clone());
+        }
+    }
+
+    /// Calls the builtin stored at the specified index, taking its arguments from the
+    /// argument stack the same way defined functions do. Arguments and returned values
+    /// of another type than the declared one raise `InvalidType`.
+    fn call_builtin(&mut self, index: u32) -> Result<MiValue, MiError> {
+        let builtin = self.builtins.get(index).unwrap();
+        // the arguments are taken off the stack even when they are invalid, so that later calls don't read them
+        let count = builtin.arity().min(self.argument_stack.len());
+        let args: Vec<MiValue> = self.argument_stack.drain(self.argument_stack.len() - count..).rev().collect();
+        if args.len() < builtin.arity() {
+            return Err(MiError {
+                name: "NotEnoughArguments".to_string(),
+                message: format!("Cannot satisfy the arguments size for the builtin `{}`: {}", builtin.name, builtin.arity()),
+                backtrace: String::new(),
+            })
+        }
+        for (value, expected) in args.iter().zip(&builtin.arguments) {
+            if &value.variant() != expected {
+                return Err(MiError {
+                    name: "InvalidType".to_string(),
+                    message: format!(
+                        "The builtin `{}` expected an argument of type `{:?}`, found `{:?}`",
+                        builtin.name, expected, value.variant()
+                    ),
+                    backtrace: String::new(),
+                })
+            }
+        }
+        match (builtin.function)(&args) {
+            MiResult::Ok(value) if value.variant() != builtin.returns => Err(MiError {
+                name: "InvalidType".to_string(),
+                message: format!(
+                    "The builtin `{}` should return a value of type `{:?}`, it returned `{:?}`",
+                    builtin.name, builtin.returns, value.variant()
+                ),
+                backtrace: String::new(),
+            }),
+            MiResult::Ok(value) => Ok(value),
+            MiResult::Err(error) => Err(error),
+        }
+    }
+
+    /// Reads the value stored at the specified register, throwing `UnsetRegister` if it is not set.
+    /// Returns `None` when an error was thrown and the program counter moved to its handler.
+    fn read_register(&mut self, reg: usize) -> Result<Option<MiValue>, MiError> {
+        match self.registers.get(reg) {
+            Some(value) => Ok(Some(value.clone())),
+            None => {
+                self.program_counter = self.throw(
+                    "UnsetRegister",
+                    format!("The register `{reg}` has not been set yet.")
+                )?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Reads the value stored at the specified register, throwing `InvalidType` if it is
+    /// not of the expected type. Returns `None` when an error was thrown.
+    fn read_typed(&mut self, reg: usize, expected: MiType) -> Result<Option<MiValue>, MiError> {
+        let Some(value) = self.read_register(reg)? else { return Ok(None) };
+        if value.variant() != expected {
+            self.program_counter = self.throw(
+                "InvalidType",
+                format!("Expected a value of type `{:?}` at register `{reg}`, found `{:?}`", expected, value.variant())
+            )?;
+            return Ok(None);
+        }
+        Ok(Some(value))
+    }
+
+    /// Reads the int stored at the specified register. Returns `None` when an error was thrown.
+    fn read_int(&mut self, reg: usize) -> Result<Option<i64>, MiError> {
+        match self.read_typed(reg, MiType::Int)? {
+            Some(MiValue::Int(num)) => Ok(Some(num)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Reads the array stored at the specified register. Returns `None` when an error was thrown.
+    fn read_array(&mut self, reg: usize) -> Result<Option<Rc<Vec<MiValue>>>, MiError> {
+        match self.read_typed(reg, MiType::Array)? {
+            Some(MiValue::Array(array)) => Ok(Some(array)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Reads the map stored at the specified register. Returns `None` when an error was thrown.
+    fn read_map(&mut self, reg: usize) -> Result<Option<Rc<BTreeMap<MapKey, MiValue>>>, MiError> {
+        match self.read_typed(reg, MiType::Map)? {
+            Some(MiValue::Map(map)) => Ok(Some(map)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Reads the class instance stored at the specified register. Returns `None` when an error was thrown.
+    fn read_class(&mut self, reg: usize) -> Result<Option<Rc<Class>>, MiError> {
+        match self.read_typed(reg, MiType::Class)? {
+            Some(MiValue::Class(class)) => Ok(Some(class)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Borrows the array stored at the specified register to modify it in place, copying
+    /// it first only if it is shared with another register. Returns `None` when an error was thrown.
+    fn array_mut(&mut self, reg: usize) -> Result<Option<&mut Vec<MiValue>>, MiError> {
+        if self.read_typed(reg, MiType::Array)?.is_none() {
+            return Ok(None);
+        }
+        match self.registers.get_mut(reg) {
+            Some(MiValue::Array(array)) => Ok(Some(Rc::make_mut(array))),
+            _ => Ok(None),
+        }
+    }
+
+    /// Borrows the map stored at the specified register to modify it in place, copying
+    /// it first only if it is shared with another register. Returns `None` when an error was thrown.
+    fn map_mut(&mut self, reg: usize) -> Result<Option<&mut BTreeMap<MapKey, MiValue>>, MiError> {
+        if self.read_typed(reg, MiType::Map)?.is_none() {
+            return Ok(None);
+        }
+        match self.registers.get_mut(reg) {
+            Some(MiValue::Map(map)) => Ok(Some(Rc::make_mut(map))),
+            _ => Ok(None),
+        }
+    }
+
+    /// Applies a binary operation to the values stored at the operand registers and stores
+    /// the result in the destination register, throwing the error of the operation if any
+    fn binary_op(&mut self, op1: usize, op2: usize, dst: usize, operation: BinaryOperation) -> Result<(), MiError> {
+        let result = match (self.registers.get(op1), self.registers.get(op2)) {
+            (Some(op1), Some(op2)) => operation(op1, op2),
+            (None, _) => return self.throw_unset(op1),
+            (_, None) => return self.throw_unset(op2),
+        };
+        match result {
+            MiResult::Ok(value) => self.registers.set(dst, value),
+            MiResult::Err(error) => {
+                self.program_counter = self.throw(error.name, error.message)?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Applies a unary operation to the value stored at the source register and stores
+    /// the result in the destination register, throwing the error of the operation if any
+    fn unary_op(&mut self, src: usize, dst: usize, operation: UnaryOperation) -> Result<(), MiError> {
+        let result = match self.registers.get(src) {
+            Some(src) => operation(src),
+            None => return self.throw_unset(src),
+        };
+        match result {
+            MiResult::Ok(value) => self.registers.set(dst, value),
+            MiResult::Err(error) => {
+                self.program_counter = self.throw(error.name, error.message)?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Throws `UnsetRegister` for the specified register
+    fn throw_unset(&mut self, reg: usize) -> Result<(), MiError> {
+        self.program_counter = self.throw(
+            "UnsetRegister",
+            format!("The register `{reg}` has not been set yet.")
+        )?;
+        Ok(())
+    }
+
+    /// Reads the value stored at the specified register as a map key, throwing `InvalidType`
+    /// if it cannot be used as one. Returns `None` when an error was thrown.
+    fn read_key(&mut self, reg: usize) -> Result<Option<MapKey>, MiError> {
+        let Some(value) = self.read_register(reg)? else { return Ok(None) };
+        match MapKey::from_value(&value) {
+            Some(key) => Ok(Some(key)),
+            None => {
+                self.program_counter = self.throw(
+                    "InvalidType",
+                    format!("Map keys must be of type `Int` or `String`, found `{:?}`", value.variant())
+                )?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Checks that the index is valid for a collection of the specified length, throwing
+    /// `IndexOutOfBounds` otherwise. Returns `None` when an error was thrown.
+    fn check_index(&mut self, index: i64, len: usize) -> Result<Option<usize>, MiError> {
+        if index < 0 || index as usize >= len {
+            self.program_counter = self.throw(
+                "IndexOutOfBounds",
+                format!("The index `{index}` is out of bounds for a length of {len}")
+            )?;
+            return Ok(None);
+        }
+        Ok(Some(index as usize))
+    }
+
+    /// Unwinds the stack frames looking for an error handler.
+    ///
+    /// The frame owning the handler is kept, the handler itself is removed so that
+    /// errors thrown from the handling code propagate to the enclosing handlers.
+    /// Within `call_function`, the frames of the host are not unwound.
     pub fn unwind_stack(&mut self, error: MiError) -> Result<i32, MiError> {
-        while let Some(frame) = self.stack.pop_frame() {
-            if frame.handles_error {
-                return Ok(frame.error_handling_addr as i32)
+        let boundary = self.call_boundaries.last().copied().unwrap_or(0);
+        while self.stack.frames().len() > boundary {
+            let Some(frame) = self.stack.last_frame_mut() else { break };
+            if let Some(handler) = frame.error_handlers.pop() {
+                self.registers.set(handler.name_reg, error.name.into_value())?;
+                self.registers.set(handler.message_reg, error.message.into_value())?;
+                return Ok(handler.addr as i32)
+            }
+            if let Some(caller_registers) = self.stack.pop_frame().and_then(|frame| frame.caller_registers) {
+                self.registers = caller_registers;
             }
         }
         Err(error)
     }
 
+    /// Sets the limits checked while the program runs
+    pub fn set_limits(&mut self, limits: RuntimeLimits) {
+        self.stack.set_max_size(limits.max_call_depth);
+        self.limits = limits;
+    }
+
+    /// Returns the limits checked while the program runs
+    pub fn limits(&self) -> &RuntimeLimits {
+        &self.limits
+    }
+
+    /// Returns the number of instructions executed so far
+    pub fn executed_instructions(&self) -> u64 {
+        self.executed_instructions
+    }
+
+    /// Checks that the specified number of values can be pushed to the argument stack,
+    /// throwing `ArgumentStackOverflow` otherwise. Returns `false` when an error was thrown.
+    fn check_argument_stack(&mut self, count: usize) -> Result<bool, MiError> {
+        if let Some(max_argument_stack) = self.limits.max_argument_stack {
+            if self.argument_stack.len() + count > max_argument_stack {
+                self.program_counter = self.unwind_stack(self.argument_stack_overflow(max_argument_stack))?;
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn argument_stack_overflow(&self, max_argument_stack: usize) -> MiError {
+        self.new_error("ArgumentStackOverflow", format!("The argument stack exceeded the limit of {max_argument_stack} values"))
+    }
+
+    /// Counts the bytes taken by the values reachable from the registers, the stack frames
+    /// and the argument stack, raising a fatal `MemoryLimitExceeded` if they exceed the limit
+    fn check_memory(&mut self, max_value_bytes: usize) -> Result<(), MiError> {
+        let mut counter = ByteCounter::new();
+        self.registers.values().for_each(|value| counter.count(value));
+        self.argument_stack.iter().for_each(|value| counter.count(value));
+        for frame in self.stack.frames() {
+            frame.args.values().chain(frame.local_variables.values()).for_each(|value| counter.count(value));
+            if let Some(registers) = &frame.caller_registers {
+                registers.values().for_each(|value| counter.count(value));
+            }
+        }
+        self.value_bytes = counter.total();
+        if counter.total() > max_value_bytes {
+            return Err(self.new_error(
+                "MemoryLimitExceeded",
+                format!("The values of the program take {} bytes, exceeding the limit of {max_value_bytes}", counter.total())
+            ))
+        }
+        Ok(())
+    }
+
+    /// Creates an error holding the current backtrace, without throwing it
+    fn new_error<T: ToString>(&self, name: &str, message: T) -> MiError {
+        MiError {
+            name: name.to_string(),
+            message: message.to_string(),
+            backtrace: self.get_backtrace(),
+        }
+    }
+
     /// Gets the stack backtrace
     pub fn get_backtrace(&self) -> String {
-        self.stack.get_backtrace_string()
+        let pc = usize::try_from(self.program_counter).ok();
+        self.stack.get_backtrace_string(pc, self.debug_info.as_ref())
     }
 
     /// Throws an error
@@ -1500,4 +1489,479 @@ impl<'rtm> MirageRuntime<'rtm> {
         let res = self.unwind_stack(error);
         res
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::function::Function;
+
+    fn run(source: &str) -> Result<Option<MiValue>, MiError> {
+        let mut runtime = MirageRuntime::from_source(source, "test.masm").unwrap();
+        runtime.run()
+    }
+
+    #[test]
+    fn try_catches_errors_thrown_by_called_functions() {
+        let source = r#"
+definefnlabel inner 0 int
+    move r0 int 1
+    move r1 int 0
+    div r0 r1 r15
+    return
+endfunction
+definefnlabel outer 0 int
+    call inner
+    return
+endfunction
+move r5 string "kept"
+try handler r0 r1
+call outer
+endtry
+move r15 string "not caught"
+jumpunc end
+definelabel handler
+arraynew r15
+arraypush r15 r0
+arraypush r15 r5
+definelabel end
+"#;
+        // the handler runs in the window of Main, where the registers are left as they were
+        let caught = vec!["DivisionByZero".into_value(), "kept".into_value()];
+        assert_eq!(run(source), Ok(Some(caught.into_value())));
+    }
+
+    #[test]
+    fn errors_thrown_in_handlers_propagate_to_the_enclosing_handler() {
+        let source = r#"
+move r2 string "first"
+try outer r0 r1
+try inner r0 r1
+throwfrom r2 r2
+definelabel inner
+move r2 string "again"
+throwfrom r2 r2
+definelabel outer
+movebetween r1 r15
+"#;
+        assert_eq!(run(source), Ok(Some("again".into_value())));
+    }
+
+    #[test]
+    fn uncaught_errors_are_returned() {
+        let error = run("move r0 int 1\nmove r1 int 0\ndiv r0 r1 r2").unwrap_err();
+        assert_eq!(error.name, "DivisionByZero");
+    }
+
+    #[test]
+    fn jumps_continue_right_after_the_label() {
+        let source = r#"
+move r0 bool true
+jumpunc first
+move r15 string "skipped"
+definelabel first
+jumpc r0 second
+move r15 string "skipped"
+definelabel second
+move r15 string "reached"
+"#;
+        assert_eq!(run(source), Ok(Some("reached".into_value())));
+    }
+
+    #[test]
+    fn throwfrom_uses_the_values_of_the_registers() {
+        let source = r#"
+move r0 string "CustomError"
+move r1 int 42
+try handler r2 r3
+throwfrom r0 r1
+definelabel handler
+arraynew r15
+arraypush r15 r2
+arraypush r15 r3
+"#;
+        let caught = vec!["CustomError".into_value(), "42".into_value()];
+        assert_eq!(run(source), Ok(Some(caught.into_value())));
+    }
+
+    const HOST_CALLS: &str = r#"
+definefnlabel double 1 x int
+    moveargument "x" r0
+    add r0 r0 r15
+    return
+endfunction
+definefnlabel fail 0 int
+    move r0 int 1
+    move r1 int 0
+    div r0 r1 r15
+    return
+endfunction
+try handler r0 r1
+move r15 string "main"
+definelabel handler
+"#;
+
+    /// Starts the program and runs it up to the `definelabel handler`, with the handler installed
+    fn started() -> MirageRuntime<'static> {
+        let mut runtime = MirageRuntime::from_source(HOST_CALLS, "test.masm").unwrap();
+        runtime.start().unwrap();
+        let handler = runtime.label_index("handler").unwrap();
+        while runtime.next_index() != handler {
+            runtime.step().unwrap();
+        }
+        runtime
+    }
+
+    #[test]
+    fn call_function_leaves_the_registers_untouched() {
+        let mut runtime = started();
+        let registers = runtime.registers.clone();
+        assert_eq!(runtime.call_function("double", vec![21.into_value()]), Ok(Some(42.into_value())));
+        assert_eq!(runtime.registers, registers);
+        assert_eq!(runtime.call_stack().frames().len(), 1);
+    }
+
+    #[test]
+    fn call_function_returns_errors_instead_of_unwinding_the_host() {
+        let mut runtime = started();
+        let registers = runtime.registers.clone();
+        let error = runtime.call_function("fail", Vec::new()).unwrap_err();
+        assert_eq!(error.name, "DivisionByZero");
+        // the handler of Main is still installed and the program goes on where it stopped
+        assert_eq!(runtime.registers, registers);
+        assert_eq!(runtime.call_stack().frames().len(), 1);
+        assert!(runtime.call_stack().frames()[0].handles_error());
+        assert_eq!(runtime.execute(), Ok(Some("main".into_value())));
+    }
+
+    #[test]
+    fn returns_continue_right_after_the_call() {
+        let source = r#"
+definefnlabel one 0 int
+    move r15 int 1
+    return
+endfunction
+call one
+movebetween r15 r0
+add r0 r0 r15
+"#;
+        assert_eq!(run(source), Ok(Some(2.into_value())));
+    }
+
+    #[test]
+    fn fields_set_by_methods_are_kept_by_the_instance() {
+        let source = r#"
+defineclass Counter
+    field count int
+    definefnlabel increment 0 int
+        moveargument "self" r0
+        getfield r0 count r1
+        move r2 int 1
+        add r1 r2 r1
+        setfield r0 count r1
+        movebetween r1 r15
+        return
+    endfunction
+endclass
+new Counter r3
+move r4 int 0
+setfield r3 count r4
+callmethod r3 increment
+callmethod r3 increment
+getfield r3 count r5
+arraynew r6
+arraypush r6 r15
+arraypush r6 r5
+movebetween r6 r15
+"#;
+        let counts = vec![2.into_value(), 2.into_value()];
+        assert_eq!(run(source), Ok(Some(counts.into_value())));
+    }
+
+    #[test]
+    fn fields_set_by_nested_methods_are_kept_by_the_instance() {
+        let source = r#"
+defineclass Counter
+    field count int
+    definefnlabel inner 0 int
+        moveargument "self" r0
+        move r1 int 5
+        setfield r0 count r1
+        return
+    endfunction
+    definefnlabel outer 0 int
+        moveargument "self" r0
+        callmethod r0 inner
+        getfield r0 count r15
+        return
+    endfunction
+endclass
+new Counter r3
+move r4 int 0
+setfield r3 count r4
+callmethod r3 outer
+getfield r3 count r5
+arraynew r6
+arraypush r6 r15
+arraypush r6 r5
+movebetween r6 r15
+"#;
+        let counts = vec![5.into_value(), 5.into_value()];
+        assert_eq!(run(source), Ok(Some(counts.into_value())));
+    }
+
+    #[test]
+    fn step_links_the_program_before_running() {
+        let mut runtime = MirageRuntime::from_source("jumpunc end\nmove r15 int 1\ndefinelabel end", "test.masm").unwrap();
+        assert_eq!(runtime.step(), Ok(Step::Running));
+        assert_eq!(runtime.next_index(), 3);
+    }
+
+    #[test]
+    fn instructions_needing_a_frame_throw_without_one() {
+        for source in ["return", "move r0 int 1\nsetvariable r0 x", "movfromvariable x r0", "try end r0 r1\ndefinelabel end"] {
+            let mut runtime = MirageRuntime::from_source(source, "test.masm").unwrap();
+            let error = loop {
+                match runtime.step() {
+                    Ok(Step::Running) => continue,
+                    Ok(step) => panic!("`{source}` ended with {step:?}"),
+                    Err(error) => break error,
+                }
+            };
+            assert_eq!(error.name, "InvalidFrame", "`{source}` threw {error:?}");
+        }
+    }
+
+    fn run_limited(source: &str, limits: RuntimeLimits) -> Result<Option<MiValue>, MiError> {
+        let mut runtime = MirageRuntime::from_source(source, "test.masm").unwrap();
+        runtime.set_limits(limits);
+        runtime.run()
+    }
+
+    /// Runs the code with a handler that returns the name of the error it catches
+    fn run_caught(code: &str, limits: RuntimeLimits) -> Result<Option<MiValue>, MiError> {
+        let source = format!("try handler r0 r1\n{code}\nmove r15 string \"not thrown\"\njumpunc end\ndefinelabel handler\nmovebetween r0 r15\ndefinelabel end");
+        run_limited(&source, limits)
+    }
+
+    #[test]
+    fn stack_limits_throw_catchable_errors() {
+        let recursion = "definefnlabel f 0 int\ncall f\nreturn\nendfunction\ncall f";
+        let limits = RuntimeLimits { max_call_depth: 10, ..RuntimeLimits::default() };
+        assert_eq!(run_caught(recursion, limits), Ok(Some("StackOverflow".into_value())));
+
+        let arguments = "move r2 int 1\nmoveasargument r2\nmoveasargument r2\nmoveasargument r2";
+        let limits = RuntimeLimits { max_argument_stack: Some(2), ..RuntimeLimits::default() };
+        assert_eq!(run_caught(arguments, limits), Ok(Some("ArgumentStackOverflow".into_value())));
+        let limits = RuntimeLimits { max_argument_stack: Some(3), ..RuntimeLimits::default() };
+        assert_eq!(run_caught(arguments, limits), Ok(Some("not thrown".into_value())));
+    }
+
+    #[test]
+    fn a_zero_call_depth_cannot_start_the_program() {
+        let mut runtime = MirageRuntime::from_source("move r15 int 1", "test.masm").unwrap();
+        runtime.set_limits(RuntimeLimits { max_call_depth: 0, ..RuntimeLimits::default() });
+        assert_eq!(runtime.run().map_err(|error| error.name), Err("StackOverflow".to_string()));
+        runtime.set_limits(RuntimeLimits { max_call_depth: 1, ..RuntimeLimits::default() });
+        assert_eq!(runtime.run(), Ok(Some(1.into_value())));
+    }
+
+    #[test]
+    fn resource_limits_are_fatal() {
+        let endless = "definelabel loop\njumpunc loop";
+        let limits = RuntimeLimits { max_instructions: Some(100), ..RuntimeLimits::default() };
+        let error = run_caught(endless, limits).unwrap_err();
+        assert_eq!(error.name, "InstructionLimitExceeded");
+
+        let large = format!("move r2 string \"{}\"\nmovebetween r2 r3", "a".repeat(1000));
+        let limits = RuntimeLimits { max_value_bytes: Some(512), ..RuntimeLimits::default() };
+        let error = run_caught(&large, limits).unwrap_err();
+        assert_eq!(error.name, "MemoryLimitExceeded");
+        let limits = RuntimeLimits { max_value_bytes: Some(4096), ..RuntimeLimits::default() };
+        assert_eq!(run_caught(&large, limits), Ok(Some("not thrown".into_value())));
+    }
+
+    #[test]
+    fn builtin_arguments_are_consumed_when_invalid() {
+        let mut runtime = MirageRuntime::new(Vec::new());
+        let error = runtime.call_function("concat", vec![1.into_value(), "b".into_value()]).unwrap_err();
+        assert_eq!(error.name, "InvalidType");
+        assert!(runtime.argument_stack.is_empty());
+        assert_eq!(runtime.call_function("concat", vec!["a".into_value(), "b".into_value()]), Ok(Some("ab".into_value())));
+    }
+
+    #[test]
+    fn builtins_must_return_their_declared_type() {
+        let mut runtime = MirageRuntime::new(Vec::new());
+        runtime.register_function("answer", Vec::new(), MiType::Int, |_| MiResult::Ok("42".into_value()));
+        let error = runtime.call_function("answer", Vec::new()).unwrap_err();
+        assert_eq!(error.name, "InvalidType");
+        assert_eq!(error.message, "The builtin `answer` should return a value of type `Int`, it returned `String`");
+    }
+
+    /// A writer that fails on every write
+    struct Broken;
+
+    impl Write for Broken {
+        fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::other("broken pipe"))
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn write_errors_name_the_stream() {
+        for (instruction, stream) in [("stdoutwrite", "stdout"), ("stdoutwritedebugged", "stdout"), ("stderrwrite", "stderr")] {
+            let source = format!("move r0 int 1\n{instruction} r0");
+            let mut runtime = MirageRuntime::from_source(&source, "test.masm").unwrap();
+            runtime.set_stdout(Broken);
+            runtime.set_stderr(Broken);
+            let error = runtime.run().unwrap_err();
+            assert_eq!(error.message, format!("Error writing to {stream}: broken pipe"));
+        }
+    }
+
+    #[test]
+    fn array_indices_are_bounds_checked() {
+        let array = "move r2 array [int 10, int 20]\nmove r5 int 0\n";
+        let out_of_bounds = Ok(Some("IndexOutOfBounds".into_value()));
+        for index in ["2", "-1"] {
+            let get = format!("{array}move r3 int {index}\narrayget r2 r3 r4");
+            assert_eq!(run_caught(&get, RuntimeLimits::default()), out_of_bounds);
+            let set = format!("{array}move r3 int {index}\narrayset r2 r3 r5");
+            assert_eq!(run_caught(&set, RuntimeLimits::default()), out_of_bounds);
+        }
+        for (start, end) in [(0, 3), (2, 1), (-1, 1)] {
+            let slice = format!("{array}move r3 int {start}\nmove r4 int {end}\narrayslice r2 r3 r4 r6");
+            assert_eq!(run_caught(&slice, RuntimeLimits::default()), out_of_bounds);
+        }
+
+        let source = format!("{array}move r3 int 1\narrayset r2 r3 r5\nmove r4 int 2\narrayslice r2 r3 r4 r15");
+        assert_eq!(run(&source), Ok(Some(vec![0.into_value()].into_value())));
+        let error = run(&format!("{array}move r3 int 2\narrayget r2 r3 r4")).unwrap_err();
+        assert_eq!(error.message, "The index `2` is out of bounds for a length of 2");
+    }
+
+    #[test]
+    fn missing_map_keys_throw_a_catchable_key_error() {
+        let map = "mapnew r2\nmove r3 string \"present\"\nmove r4 int 1\nmapinsert r2 r3 r4\nmove r3 string \"missing\"\n";
+        let key_error = Ok(Some("KeyError".into_value()));
+        assert_eq!(run_caught(&format!("{map}mapget r2 r3 r5"), RuntimeLimits::default()), key_error);
+        assert_eq!(run_caught(&format!("{map}mapremove r2 r3 r5"), RuntimeLimits::default()), key_error);
+        // only ints and strings can be keys
+        let invalid_key = format!("{map}move r3 float 1.5\nmapget r2 r3 r5");
+        assert_eq!(run_caught(&invalid_key, RuntimeLimits::default()), Ok(Some("InvalidType".into_value())));
+
+        let source = format!("{map}mapcontains r2 r3 r5\nmove r3 string \"present\"\nmapget r2 r3 r6\narraynew r15\narraypush r15 r5\narraypush r15 r6");
+        assert_eq!(run(&source), Ok(Some(vec![false.into_value(), 1.into_value()].into_value())));
+        let error = run(&format!("{map}mapget r2 r3 r5")).unwrap_err();
+        assert_eq!(error.message, "The key `missing` is not present in the map");
+    }
+
+    #[test]
+    fn failed_casts_throw_a_catchable_conversion_error() {
+        let conversion_error = Ok(Some("ConversionError".into_value()));
+        for code in [
+            "move r2 string \"12a\"\ncast r2 int r3",
+            "move r2 string \"one\"\ncast r2 float r3",
+            "move r2 string \"yes\"\ncast r2 bool r3",
+            "move r2 array [int 1]\ncast r2 int r3",
+            // floats out of the range of ints and NaN have no int value
+            "move r2 string \"1e19\"\ncast r2 float r2\ncast r2 int r3",
+            "move r2 string \"-1e19\"\ncast r2 float r2\ncast r2 int r3",
+            "move r2 string \"NaN\"\ncast r2 float r2\ncast r2 int r3",
+            "move r2 string \"inf\"\ncast r2 float r2\ncast r2 int r3",
+        ] {
+            assert_eq!(run_caught(code, RuntimeLimits::default()), conversion_error, "{code}");
+        }
+
+        let error = run("move r2 string \" 12a \"\ncast r2 int r3").unwrap_err();
+        assert_eq!(error.name, "ConversionError");
+        assert_eq!(error.message, "Cannot parse `12a` as an int: invalid digit found in string");
+        let error = run("move r2 string \"NaN\"\ncast r2 float r2\ncast r2 int r3").unwrap_err();
+        assert_eq!(error.message, "The float `NaN` does not fit in an int");
+    }
+
+    #[test]
+    fn casts_convert_between_primitive_types() {
+        let source = r#"
+arraynew r15
+move r2 float -2.9
+cast r2 int r3
+arraypush r15 r3
+move r2 string " 42 "
+cast r2 int r3
+arraypush r15 r3
+move r2 bool true
+cast r2 float r3
+arraypush r15 r3
+move r2 int 0
+cast r2 bool r3
+arraypush r15 r3
+move r2 float 1.5
+cast r2 string r3
+arraypush r15 r3
+"#;
+        let values = vec![(-2).into_value(), 42.into_value(), 1.0.into_value(), MiValue::Bool(false), "1.5".to_string().into_value()];
+        assert_eq!(run(source), Ok(Some(values.into_value())));
+        assert_eq!(MiValue::Float(i64::MIN as f64).cast(&MiType::Int), Ok(MiValue::Int(i64::MIN)));
+        assert!(MiValue::Float(i64::MAX as f64).cast(&MiType::Int).is_err());
+    }
+
+    #[test]
+    fn typeof_names_every_type() {
+        let source = r#"
+defineclass Point
+    field x int
+endclass
+move r2 int 1
+move r3 float 1.5
+move r4 string "a"
+move r5 bool true
+new Point r6
+move r8 None
+move r9 array [int 1]
+mapnew r10
+arraynew r15
+typeof r2 r11
+arraypush r15 r11
+typeof r3 r11
+arraypush r15 r11
+typeof r4 r11
+arraypush r15 r11
+typeof r5 r11
+arraypush r15 r11
+typeof r6 r11
+arraypush r15 r11
+typeof r7 r11
+arraypush r15 r11
+typeof r8 r11
+arraypush r15 r11
+typeof r9 r11
+arraypush r15 r11
+typeof r10 r11
+arraypush r15 r11
+"#;
+        let mut runtime = MirageRuntime::from_source(source, "test.masm").unwrap();
+        // functions cannot be written as literals
+        runtime.registers.set(7, Function::Builtin(0).into_value()).unwrap();
+        let names = ["int", "float", "string", "bool", "class", "function", "None", "array", "map"]
+            .map(|name| name.to_string().into_value())
+            .to_vec();
+        assert_eq!(runtime.run(), Ok(Some(names.into_value())));
+    }
+
+    #[test]
+    fn uncaught_errors_point_to_the_source() {
+        let source = "definefnlabel fail 0 int\n    move r0 int 1\n    move r1 int 0\n    div r0 r1 r2\n    return\nendfunction\ncall fail";
+        let error = run(source).unwrap_err();
+        assert_eq!(error.name, "DivisionByZero");
+        let fail = error.backtrace.find("at fail").unwrap();
+        let main = error.backtrace.find("at Main").unwrap();
+        assert!(error.backtrace[fail..main].contains("- Location: test.masm:4:5"), "{}", error.backtrace);
+        assert!(error.backtrace[fail..main].contains("div r0 r1 r2"), "{}", error.backtrace);
+        assert!(error.backtrace[main..].contains("- Location: test.masm:7:1"), "{}", error.backtrace);
+    }
+}
@@ -1,5 +1,7 @@
 use fxhash::FxHashMap;
 
+use crate::debug_info::DebugInfo;
+use crate::registers::Registers;
 use crate::value::MiValue;
 
 #[derive(Clone, PartialEq, Debug)]
@@ -16,8 +18,13 @@ impl CallStack {
         }
     }
 
+    /// Sets the maximum number of frames the stack can hold
+    pub fn set_max_size(&mut self, max_size: usize) {
+        self.max_size = max_size;
+    }
+
     pub fn push_frame(&mut self, frame: StackFrame) -> Result<(), String> {
-        if self.frames.len() + 1 >= self.max_size {
+        if self.frames.len() >= self.max_size {
             return Err(format!("Call stack size exceeded the maximum limit of {}", self.max_size));
         }
 
@@ -33,11 +40,24 @@ impl CallStack {
         self.frames.last_mut()
     }
 
-    pub fn get_backtrace_string(&self) -> String {
+    pub fn last_frame(&self) -> Option<&StackFrame> {
+        self.frames.last()
+    }
+
+    /// Returns the frames of the stack, the innermost one being the last
+    pub fn frames(&self) -> &[StackFrame] {
+        &self.frames
+    }
+
+    /// Formats the frames of the stack, innermost first. `pc` is the index of the instruction
+    /// being executed; with debug info, each frame shows the source location it stopped at.
+    pub fn get_backtrace_string(&self, pc: Option<usize>, debug_info: Option<&DebugInfo>) -> String {
         let mut backtrace = String::new();
         let mut prev_frame: Option<&StackFrame> = None;
         let mut prev_frame_count = 1;
         let mut frame_count = 0;
+        // the outer frames stopped at the call that created the frame inside them
+        let mut position = pc;
 
         for frame in self.frames.iter().rev() {
             if frame_count >= 8 {
@@ -58,23 +78,32 @@ impl CallStack {
                 prev_frame_count = 1;
 
                 backtrace.push_str(&format!("at {}\n", frame.name));
+                if let (Some(debug_info), Some(position)) = (debug_info, position) {
+                    if let Some(location) = debug_info.format_location(position) {
+                        backtrace.push_str(&format!("\t- Location: {}\n", location));
+                    }
+                    if let Some(source_line) = debug_info.source_line(position) {
+                        backtrace.push_str(&format!("\t\t{}\n", source_line.trim()));
+                    }
+                }
                 backtrace.push_str("\t- Arguments:\n");
                 for (arg_name, arg_value) in &frame.args {
-                    backtrace.push_str(&format!("\t\t{}: {}\n", arg_name, arg_value.to_string()));
+                    backtrace.push_str(&format!("\t\t{}: {}\n", arg_name, arg_value));
                 }
                 backtrace.push_str("\t- Local Variables:\n");
                 for (var_name, var_value) in &frame.local_variables {
-                    backtrace.push_str(&format!("\t\t{}: {}\n", var_name, var_value.to_string()));
+                    backtrace.push_str(&format!("\t\t{}: {}\n", var_name, var_value));
                 }
                 if let Some(return_addr) = frame.return_addr {
                     backtrace.push_str(&format!("\t- Return Address: {}\n", return_addr));
                 }
-                if frame.handles_error {
-                    backtrace.push_str(&format!("\t- Error Handling Address: {}\n", frame.error_handling_addr));
+                if let Some(handler) = frame.error_handlers.last() {
+                    backtrace.push_str(&format!("\t- Error Handling Address: {}\n", handler.addr));
                 }
 
                 frame_count += 1;
             }
+            position = frame.return_addr;
         }
 
         if let Some(prev_frame) = prev_frame {
@@ -87,14 +116,30 @@ impl CallStack {
     }
 }
 
+/// An error handler installed by a `try` instruction
+#[derive(Clone, PartialEq, Debug)]
+pub struct ErrorHandler {
+    /// Position of the label execution resumes at when an error is caught
+    pub addr: usize,
+    /// Register that receives the name of the caught error
+    pub name_reg: usize,
+    /// Register that receives the message of the caught error
+    pub message_reg: usize,
+}
+
 #[derive(Clone, PartialEq, Debug)]
 pub struct StackFrame {
     pub name: String,
     pub args: FxHashMap<String, MiValue>,
     pub local_variables: FxHashMap<String, MiValue>,
     pub return_addr: Option<usize>,
-    pub handles_error: bool,
-    pub error_handling_addr: usize,
+    /// Installed error handlers, the innermost `try` being the last one
+    pub error_handlers: Vec<ErrorHandler>,
+    /// Register window of the caller, restored when this frame returns
+    pub caller_registers: Option<Registers>,
+    /// Register of the caller holding the instance a method was called on, which
+    /// receives the `self` argument when the method returns
+    pub receiver: Option<usize>,
 }
 
 impl StackFrame {
@@ -102,16 +147,20 @@ impl StackFrame {
         name: String,
         args: FxHashMap<String, MiValue>,
         return_addr: Option<usize>,
-        handles_error: bool,
-        error_handling_addr: usize,
     ) -> Self {
         Self {
             name,
             args,
             local_variables: FxHashMap::default(),
             return_addr,
-            handles_error,
-            error_handling_addr,
+            error_handlers: Vec::new(),
+            caller_registers: None,
+            receiver: None,
         }
     }
+
+    /// Returns whether an error thrown in this frame can be caught by it
+    pub fn handles_error(&self) -> bool {
+        !self.error_handlers.is_empty()
+    }
 }
@@ -0,0 +1,130 @@
+use std::io::{self, Write};
+
+use crate::instructions::Instruction;
+use crate::registers::{Registers, REGISTER_COUNT};
+use crate::value::{MiValue, ToStringDebugged};
+
+/// The format of the lines written by a `Tracer`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TraceFormat {
+    /// `pc frame instruction -> changes`, aligned for reading
+    Human,
+    /// One JSON object per line, with the `pc`, `frame`, `instruction` and `changes` keys
+    Json,
+}
+
+/// Writes a line for every executed instruction, with the registers it changed
+pub struct Tracer<'rtm> {
+    output: Box<dyn Write + 'rtm>,
+    format: TraceFormat,
+}
+
+impl<'rtm> Tracer<'rtm> {
+    pub fn new<W: Write + 'rtm>(output: W, format: TraceFormat) -> Self {
+        Self {
+            output: Box::new(output),
+            format,
+        }
+    }
+
+    /// Writes the line of the instruction at the specified index, executed in the specified frame
+    pub fn trace(&mut self, pc: usize, instruction: &Instruction, frame: &str, changes: &[(usize, MiValue)]) -> io::Result<()> {
+        match self.format {
+            TraceFormat::Human => {
+                write!(self.output, "{pc:>6} {frame:<16} {instruction:?}")?;
+                for (index, (reg, value)) in changes.iter().enumerate() {
+                    let separator = if index == 0 { " -> " } else { ", " };
+                    write!(self.output, "{separator}r{reg} = {}", value.to_string_debugged())?;
+                }
+                writeln!(self.output)
+            }
+            TraceFormat::Json => {
+                // written by hand so the keys and registers keep their order
+                let changes = changes
+                    .iter()
+                    .map(|(reg, value)| format!("\"r{reg}\":{}", json_string(&value.to_string_debugged())))
+                    .collect::<Vec<String>>()
+                    .join(",");
+                writeln!(
+                    self.output,
+                    "{{\"pc\":{pc},\"frame\":{},\"instruction\":{},\"changes\":{{{changes}}}}}",
+                    json_string(frame),
+                    json_string(&format!("{instruction:?}")),
+                )
+            }
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+/// Returns the registers set in `after` whose value differs from the one in `before`
+pub fn changed_registers(before: &Registers, after: &Registers) -> Vec<(usize, MiValue)> {
+    (0..REGISTER_COUNT)
+        .filter_map(|reg| match after.get(reg) {
+            Some(value) if before.get(reg) != Some(value) => Some((reg, value.clone())),
+            _ => None,
+        })
+        .collect()
+}
+
+fn json_string(string: &str) -> String {
+    serde_json::Value::String(string.to_string()).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::MirageRuntime;
+    use crate::value::IntoValue;
+
+    fn trace(source: &str, format: TraceFormat) -> Vec<String> {
+        let mut output = Vec::new();
+        {
+            let mut runtime = MirageRuntime::from_source(source, "test.masm").unwrap();
+            runtime.enable_tracing(&mut output, format);
+            runtime.run().unwrap();
+        }
+        String::from_utf8(output).unwrap().lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn human_lines_show_the_frame_and_changes() {
+        let lines = trace("move r0 int 1\nmove r0 int 1\nmove r1 string \"a\\\"b\"", TraceFormat::Human);
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("     0 Main             Move(0, Int(1))"), "{}", lines[0]);
+        assert!(lines[0].ends_with(" -> r0 = 1"), "{}", lines[0]);
+        // moving the same value again changes nothing
+        assert!(!lines[1].contains("->"), "{}", lines[1]);
+        assert!(lines[2].starts_with("     2 Main "), "{}", lines[2]);
+        assert!(lines[2].ends_with(" -> r1 = \"a\\\"b\""), "{}", lines[2]);
+    }
+
+    #[test]
+    fn json_lines_are_objects_with_ordered_keys() {
+        let lines = trace("definefnlabel f 0 int\nmove r15 int 3\nreturn\nendfunction\ncall f", TraceFormat::Json);
+        let values: Vec<serde_json::Value> = lines.iter().map(|line| serde_json::from_str(line).unwrap()).collect();
+        let frames: Vec<&str> = values.iter().map(|value| value["frame"].as_str().unwrap()).collect();
+        assert!(frames.contains(&"f"), "{lines:?}");
+        let set = values.iter().find(|value| value["frame"] == "f" && value["changes"]["r15"] == "3").unwrap();
+        assert!(set["pc"].is_u64());
+        assert!(set["instruction"].as_str().unwrap().starts_with("Move"));
+        for line in &lines {
+            assert!(line.starts_with("{\"pc\":") && line.contains(",\"frame\":") && line.contains(",\"instruction\":"), "{line}");
+        }
+    }
+
+    #[test]
+    fn changes_hold_the_registers_whose_value_differs() {
+        let mut before = Registers::new();
+        before.set(0, 1.into_value()).unwrap();
+        before.set(1, 2.into_value()).unwrap();
+        let mut after = before.clone();
+        after.set(1, 3.into_value()).unwrap();
+        after.set(4, MiValue::None).unwrap();
+        assert_eq!(changed_registers(&before, &after), vec![(1, 3.into_value()), (4, MiValue::None)]);
+        assert_eq!(changed_registers(&after, &after), vec![]);
+    }
+}
@@ -1,18 +1,39 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::rc::Rc;
+
 use crate::{class::Class, function::{Function, format_function}};
-use bincode::{serialize, deserialize};
 use serde_derive::{Serialize, Deserialize};
 
+/// Represents a value of the VM. Strings, collections, instances and functions
+/// are reference counted, so moving a value between registers never copies or
+/// re-encodes its contents; collections are copied on write instead.
 #[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
-pub struct MiValue {
-    pub bytes: Vec<u8>,
-    pub variant: MiType,
+pub enum MiValue {
+    Int(i64),
+    Float(f64),
+    Str(Rc<str>),
+    Bool(bool),
+    Class(Rc<Class>),
+    Function(Rc<Function>),
+    None,
+    Array(Rc<Vec<MiValue>>),
+    Map(Rc<BTreeMap<MapKey, MiValue>>),
 }
 
 impl MiValue {
-    pub fn new<T: Into<Vec<u8>>>(bytes: T, variant: MiType) -> MiValue {
-        Self {
-            bytes: bytes.into(),
-            variant,
+    /// Returns the type of the value
+    pub fn variant(&self) -> MiType {
+        match self {
+            MiValue::Int(_) => MiType::Int,
+            MiValue::Float(_) => MiType::Float,
+            MiValue::Str(_) => MiType::String,
+            MiValue::Bool(_) => MiType::Bool,
+            MiValue::Class(_) => MiType::Class,
+            MiValue::Function(_) => MiType::Function,
+            MiValue::None => MiType::None,
+            MiValue::Array(_) => MiType::Array,
+            MiValue::Map(_) => MiType::Map,
         }
     }
 }
@@ -26,6 +47,35 @@ pub enum MiType {
     Class,
     Function,
     None,
+    Array,
+    Map,
+}
+
+/// Represents the key of a map value, maps can only be indexed by ints and strings
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum MapKey {
+    Int(i64),
+    String(Rc<str>),
+}
+
+impl MapKey {
+    /// Converts a value into a map key, returning `None` if its type cannot be used as a key
+    pub fn from_value(value: &MiValue) -> Option<MapKey> {
+        match value {
+            MiValue::Int(num) => Some(MapKey::Int(*num)),
+            MiValue::Str(string) => Some(MapKey::String(string.clone())),
+            _ => None,
+        }
+    }
+}
+
+impl IntoValue for MapKey {
+    fn into_value(&self) -> MiValue {
+        match self {
+            MapKey::Int(num) => MiValue::Int(*num),
+            MapKey::String(string) => MiValue::Str(string.clone()),
+        }
+    }
 }
 
 impl MiType {
@@ -34,45 +84,123 @@ impl MiType {
             self == &MiType::Int
             || self == &MiType::Float
     }
+
+    /// Returns the name of the type as written in the assembly
+    pub fn name(&self) -> &'static str {
+        match self {
+            MiType::Int => "int",
+            MiType::Float => "float",
+            MiType::String => "string",
+            MiType::Bool => "bool",
+            MiType::Class => "class",
+            MiType::Function => "function",
+            MiType::None => "None",
+            MiType::Array => "array",
+            MiType::Map => "map",
+        }
+    }
+}
+
+impl MiValue {
+    /// Converts the value to the specified type, returning a message describing
+    /// why when the conversion is not possible
+    pub fn cast(&self, target: &MiType) -> Result<MiValue, String> {
+        if &self.variant() == target {
+            return Ok(self.clone());
+        }
+        match (self, target) {
+            (_, MiType::String) => Ok(self.to_string().into_value()),
+            (MiValue::Float(num), MiType::Int) => {
+                if num.is_finite() && *num >= i64::MIN as f64 && *num < i64::MAX as f64 {
+                    Ok(MiValue::Int(num.trunc() as i64))
+                } else {
+                    Err(format!("The float `{}` does not fit in an int", num))
+                }
+            }
+            (MiValue::Bool(b), MiType::Int) => Ok(MiValue::Int(*b as i64)),
+            (MiValue::Str(string), MiType::Int) => {
+                match string.trim().parse::<i64>() {
+                    Ok(num) => Ok(MiValue::Int(num)),
+                    Err(err) => Err(format!("Cannot parse `{}` as an int: {}", string.trim(), err)),
+                }
+            }
+            (MiValue::Int(num), MiType::Float) => Ok(MiValue::Float(*num as f64)),
+            (MiValue::Bool(b), MiType::Float) => Ok(MiValue::Float(*b as u8 as f64)),
+            (MiValue::Str(string), MiType::Float) => {
+                match string.trim().parse::<f64>() {
+                    Ok(num) => Ok(MiValue::Float(num)),
+                    Err(err) => Err(format!("Cannot parse `{}` as a float: {}", string.trim(), err)),
+                }
+            }
+            (MiValue::Int(num), MiType::Bool) => Ok(MiValue::Bool(*num != 0)),
+            (MiValue::Float(num), MiType::Bool) => Ok(MiValue::Bool(*num != 0.0)),
+            (MiValue::Str(string), MiType::Bool) => {
+                match string.trim() {
+                    "true" => Ok(MiValue::Bool(true)),
+                    "false" => Ok(MiValue::Bool(false)),
+                    other => Err(format!("Cannot parse `{}` as a bool", other)),
+                }
+            }
+            (value, target) => Err(format!("Cannot convert a value of type `{}` to `{}`", value.variant().name(), target.name())),
+        }
+    }
 }
 
 pub trait IntoValue {
     fn into_value(&self) -> MiValue;
 }
 
-impl IntoValue for i32 {
+impl IntoValue for i64 {
     fn into_value(&self) -> MiValue {
-        return MiValue::new(self.to_le_bytes(), MiType::Int)
+        MiValue::Int(*self)
     }
 }
 
 impl IntoValue for f64 {
     fn into_value(&self) -> MiValue {
-        return MiValue::new(self.to_le_bytes(), MiType::Float)
+        MiValue::Float(*self)
     }
 }
 
 impl IntoValue for String {
     fn into_value(&self) -> MiValue {
-        return MiValue::new(serialize(self).unwrap(), MiType::String)
+        MiValue::Str(Rc::from(self.as_str()))
+    }
+}
+
+impl IntoValue for str {
+    fn into_value(&self) -> MiValue {
+        MiValue::Str(Rc::from(self))
     }
 }
 
 impl IntoValue for bool {
     fn into_value(&self) -> MiValue {
-        return MiValue::new(if *self { [1] } else { [0] }, MiType::Bool)
+        MiValue::Bool(*self)
     }
 }
 
 impl IntoValue for Class {
     fn into_value(&self) -> MiValue {
-        MiValue::new(serialize(self).unwrap(), MiType::Class)
+        MiValue::Class(Rc::new(self.clone()))
     }
 }
 
 impl IntoValue for Function {
     fn into_value(&self) -> MiValue {
-        MiValue::new(serialize(self).unwrap(), MiType::Function)
+        MiValue::Function(Rc::new(self.clone()))
+    }
+}
+
+impl IntoValue for Vec<MiValue> {
+    fn into_value(&self) -> MiValue {
+        MiValue::Array(Rc::new(self.clone()))
+    }
+}
+
+impl IntoValue for BTreeMap<MapKey, MiValue> {
+    fn into_value(&self) -> MiValue {
+        MiValue::Map(Rc::new(self.clone()))
     }
 }
 
@@ -80,60 +208,38 @@ pub trait ToStringDebugged {
     fn to_string_debugged(&self) -> String;
 }
 
+fn format_function_value(function: &Function) -> String {
+    match function {
+        Function::Builtin(num) => format!("<builtin function at index={}>", num),
+        Function::Defined(structure) => format_function(structure),
+    }
+}
 
-
-impl ToString for MiValue {
-    fn to_string(&self) -> String {
-        match self.variant {
-            MiType::Bool => {
-                if self.bytes[0] == 1 {
-                    "true".to_string()
-                } else {
-                    "false".to_string()
-                }
-            }
-            MiType::String => {
-                deserialize::<String>(&self.bytes).unwrap()
-            }
-            MiType::None => {
-                "None".to_string()
+impl fmt::Display for MiValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MiValue::Bool(b) => write!(f, "{}", b),
+            MiValue::Str(string) => write!(f, "{}", string),
+            MiValue::None => write!(f, "None"),
+            MiValue::Int(num) => write!(f, "{}", num),
+            MiValue::Float(num) => write!(f, "{}", num),
+            MiValue::Function(function) => write!(f, "{}", format_function_value(function)),
+            MiValue::Class(class) => write!(f, "<instance of {}>", class.name),
+            MiValue::Array(array) => {
+                let elements = array
+                    .iter()
+                    .map(|value| value.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ");
+                write!(f, "[{}]", elements)
             }
-            MiType::Int => {
-                let num = i32::from_le_bytes(self.bytes.clone().try_into().unwrap());
-                format!("{}", num)
-            }
-            MiType::Float => {
-                let num = f64::from_le_bytes(self.bytes.clone().try_into().unwrap());
-                format!("{}", num)
-            }
-            MiType::Function => {
-                let fun = deserialize::<Function>(&self.bytes);
-                match fun {
-                    Ok(fun) => {
-                        match fun {
-                            Function::Builtin(num) => {
-                                return format!("<builtin function at index={}>", num);
-                            }
-                            Function::Defined(structure) => {
-                                format_function(&structure)
-                            }
-                        }
-                    }
-                    Err(err) => {
-                        panic!("Error deserializing function object: {err}")
-                    }
-                }
-            }
-            MiType::Class => {
-                let class = deserialize::<Class>(&self.bytes);
-                match class {
-                    Ok(class) => {
-                        format!("<class at {:?}>", &class as *const Class)
-                    }
-                    Err(err) => {
-                        panic!("Error deserializing function object: {err}")
-                    }
-                }
+            MiValue::Map(map) => {
+                let entries = map
+                    .iter()
+                    .map(|(key, value)| format!("{}: {}", key.into_value(), value))
+                    .collect::<Vec<String>>()
+                    .join(", ");
+                write!(f, "{{{}}}", entries)
             }
         }
     }
@@ -141,65 +247,26 @@ impl ToString for MiValue {
 
 impl ToStringDebugged for MiValue {
     fn to_string_debugged(&self) -> String {
-        match self.variant {
-            MiType::Bool => {
-                if self.bytes[0] == 1 {
-                    "true".to_string()
-                } else {
-                    "false".to_string()
-                }
-            }
-            MiType::String => {
-                let length: [u8; 4] = self.bytes[0..=4].try_into().unwrap();
-                let len = u32::from_le_bytes(length);
-                let mut string = String::new();
-                string.push('"');
-                for i in 0..len {
-                    string.push(self.bytes[(i + 4) as usize] as char);
-                }
-                string.push('"');
-                string
-            }
-            MiType::None => {
-                "None".to_string()
-            }
-            MiType::Int => {
-                let num = i32::from_le_bytes(self.bytes.clone().try_into().unwrap());
-                format!("{}", num)
-            }
-            MiType::Float => {
-                let num = f64::from_le_bytes(self.bytes.clone().try_into().unwrap());
-                format!("{}", num)
+        match self {
+            MiValue::Str(string) => format!("{:?}", string),
+            MiValue::Class(class) => class.format_debugged(),
+            MiValue::Array(array) => {
+                let elements = array
+                    .iter()
+                    .map(|value| value.to_string_debugged())
+                    .collect::<Vec<String>>()
+                    .join(", ");
+                format!("[{}]", elements)
             }
-            MiType::Function => {
-                let fun = deserialize::<Function>(&self.bytes);
-                match fun {
-                    Ok(fun) => {
-                        match fun {
-                            Function::Builtin(num) => {
-                                return format!("<builtin function at index={}>", num);
-                            }
-                            Function::Defined(structure) => {
-                                format_function(&structure)
-                            }
-                        }
-                    }
-                    Err(err) => {
-                        panic!("Error deserializing function object: {err}")
-                    }
-                }
-            }
-            MiType::Class => {
-                let class = deserialize::<Class>(&self.bytes);
-                match class {
-                    Ok(class) => {
-                        class.format_debugged()
-                    }
-                    Err(err) => {
-                        panic!("Error deserializing function object: {err}")
-                    }
-                }
+            MiValue::Map(map) => {
+                let entries = map
+                    .iter()
+                    .map(|(key, value)| format!("{}: {}", key.into_value().to_string_debugged(), value.to_string_debugged()))
+                    .collect::<Vec<String>>()
+                    .join(", ");
+                format!("{{{}}}", entries)
             }
+            _ => self.to_string(),
         }
     }
-}
\ No newline at end of file
+}
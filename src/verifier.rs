@@ -0,0 +1,340 @@
+use std::fmt;
+
+use fxhash::FxHashMap;
+
+use crate::instructions::Instruction;
+use crate::registers::REGISTER_COUNT;
+
+/// The reason an instruction was rejected by the verifier
+#[derive(Clone, PartialEq, Debug)]
+pub enum VerifyErrorKind {
+    /// A register operand is outside of the register window
+    InvalidRegister(usize),
+    /// A jump or `try` targets a label that is never defined
+    UndefinedLabel(String),
+    /// A label is defined more than once. Holds the index of the first definition
+    DuplicateLabel(String, usize),
+    /// A function is defined more than once. Holds the index of the first definition
+    DuplicateFunction(String, usize),
+    /// A function is defined inside of the body of another function
+    NestedFunction(String),
+    /// A function is never closed by an `EndFunction`
+    UnclosedFunction(String),
+    /// An `EndFunction` does not close any function
+    UnmatchedEndFunction,
+    /// A class is defined inside of another class or of a function
+    NestedClass(String),
+    /// A class is never closed by an `EndClass`
+    UnclosedClass(String),
+    /// An `EndClass` does not close any class
+    UnmatchedEndClass,
+    /// A field is declared outside of a class definition
+    FieldOutsideClass(String),
+    /// A jump or `try` targets a label on the other side of a function boundary
+    JumpAcrossFunction(String),
+    /// The end of the function body can be reached without returning
+    MissingReturn(String),
+}
+
+/// An error found by the verifier, pointing to the offending instruction
+#[derive(Clone, PartialEq, Debug)]
+pub struct VerifyError {
+    pub index: usize,
+    pub kind: VerifyErrorKind,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "instruction {}: ", self.index)?;
+        match &self.kind {
+            VerifyErrorKind::InvalidRegister(reg) => write!(f, "the register `{reg}` is not valid as is not between 0-{}", REGISTER_COUNT - 1),
+            VerifyErrorKind::UndefinedLabel(label) => write!(f, "the label `{label}` is not defined"),
+            VerifyErrorKind::DuplicateLabel(label, first) => write!(f, "the label `{label}` was already defined at instruction {first}"),
+            VerifyErrorKind::DuplicateFunction(name, first) => write!(f, "the function `{name}` was already defined at instruction {first}"),
+            VerifyErrorKind::NestedFunction(name) => write!(f, "the function `{name}` is defined inside of another function"),
+            VerifyErrorKind::UnclosedFunction(name) => write!(f, "the function `{name}` is never closed by `endfunction`"),
+            VerifyErrorKind::UnmatchedEndFunction => write!(f, "`endfunction` does not close any function"),
+            VerifyErrorKind::NestedClass(name) => write!(f, "the class `{name}` is defined inside of another definition"),
+            VerifyErrorKind::UnclosedClass(name) => write!(f, "the class `{name}` is never closed by `endclass`"),
+            VerifyErrorKind::UnmatchedEndClass => write!(f, "`endclass` does not close any class"),
+            VerifyErrorKind::FieldOutsideClass(name) => write!(f, "the field `{name}` is declared outside of a class"),
+            VerifyErrorKind::JumpAcrossFunction(label) => write!(f, "the label `{label}` is on the other side of a function boundary"),
+            VerifyErrorKind::MissingReturn(name) => write!(f, "the end of the function `{name}` can be reached without returning"),
+        }
+    }
+}
+
+/// Checks that the instructions form a well-formed program before it is run: registers
+/// are in bounds, labels are defined once and only targeted from their own function
+/// body, definitions are balanced and every path through a function body returns.
+/// Calls are not checked, as builtins can be registered by the host.
+pub fn verify(instructions: &[Instruction]) -> Result<(), Vec<VerifyError>> {
+    let mut errors = Vec::new();
+    let mut error = |index: usize, kind: VerifyErrorKind| errors.push(VerifyError { index, kind });
+
+    // the index of the function definition each instruction belongs to
+    let mut owners: Vec<Option<usize>> = Vec::with_capacity(instructions.len());
+    let mut labels: FxHashMap<&str, usize> = FxHashMap::default();
+    let mut functions: FxHashMap<String, usize> = FxHashMap::default();
+    let mut bodies: Vec<(usize, usize, &str)> = Vec::new();
+    let mut function: Option<(usize, &str)> = None;
+    let mut class: Option<(usize, &str)> = None;
+
+    for (index, instruction) in instructions.iter().enumerate() {
+        for reg in instruction.registers() {
+            if reg >= REGISTER_COUNT {
+                error(index, VerifyErrorKind::InvalidRegister(reg));
+            }
+        }
+        match instruction {
+            Instruction::DefineFnLabel(name, _, _) => {
+                if function.is_some() {
+                    error(index, VerifyErrorKind::NestedFunction(name.clone()));
+                } else {
+                    let qualified_name = match class {
+                        Some((_, class_name)) => format!("{class_name}.{name}"),
+                        None => name.clone(),
+                    };
+                    if let Some(first) = functions.get(&qualified_name) {
+                        error(index, VerifyErrorKind::DuplicateFunction(qualified_name, *first));
+                    } else {
+                        functions.insert(qualified_name, index);
+                    }
+                    function = Some((index, name));
+                }
+            }
+            Instruction::EndFunction => {
+                match function.take() {
+                    Some((start, name)) => bodies.push((start, index, name)),
+                    None => error(index, VerifyErrorKind::UnmatchedEndFunction),
+                }
+            }
+            Instruction::DefineClass(name) => {
+                if class.is_some() || function.is_some() {
+                    error(index, VerifyErrorKind::NestedClass(name.clone()));
+                } else {
+                    class = Some((index, name));
+                }
+            }
+            Instruction::EndClass => {
+                match class.take() {
+                    Some(_) => {
+                        if let Some((start, name)) = function.take() {
+                            error(start, VerifyErrorKind::UnclosedFunction(name.to_string()));
+                        }
+                    }
+                    None => error(index, VerifyErrorKind::UnmatchedEndClass),
+                }
+            }
+            Instruction::ClassField(name, _) if class.is_none() || function.is_some() => {
+                error(index, VerifyErrorKind::FieldOutsideClass(name.clone()));
+            }
+            Instruction::DefineLabel(label) => {
+                if let Some(first) = labels.get(label.as_str()) {
+                    error(index, VerifyErrorKind::DuplicateLabel(label.clone(), *first));
+                } else {
+                    labels.insert(label, index);
+                }
+            }
+            _ => {}
+        }
+        let owner = match instruction {
+            Instruction::EndFunction => bodies.last().map(|(start, _, _)| *start),
+            _ => function.map(|(start, _)| start),
+        };
+        owners.push(owner);
+    }
+    if let Some((start, name)) = function {
+        error(start, VerifyErrorKind::UnclosedFunction(name.to_string()));
+    }
+    if let Some((start, name)) = class {
+        error(start, VerifyErrorKind::UnclosedClass(name.to_string()));
+    }
+
+    // the resolved targets of the jumps, `None` when the target is invalid
+    let mut targets: Vec<Option<usize>> = vec![None; instructions.len()];
+    for (index, instruction) in instructions.iter().enumerate() {
+        let label = match instruction {
+            Instruction::JumpUnconditional(label)
+            | Instruction::JumpConditional(_, label)
+            | Instruction::Try(label, _, _) => label,
+            _ => continue,
+        };
+        match labels.get(label.as_str()) {
+            Some(&target) if owners[target] == owners[index] => targets[index] = Some(target),
+            Some(_) => error(index, VerifyErrorKind::JumpAcrossFunction(label.clone())),
+            None => error(index, VerifyErrorKind::UndefinedLabel(label.clone())),
+        }
+    }
+
+    for (start, end, name) in bodies {
+        if reaches_end(instructions, &targets, start, end) {
+            error(start, VerifyErrorKind::MissingReturn(name.to_string()));
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        errors.sort_by_key(|error| error.index);
+        Err(errors)
+    }
+}
+
+/// Returns whether the `EndFunction` at `end` can be reached from the start of the body
+/// of the function defined at `start` without going through a `Return`
+fn reaches_end(instructions: &[Instruction], targets: &[Option<usize>], start: usize, end: usize) -> bool {
+    let mut visited = vec![false; end - start];
+    let mut pending = vec![start + 1];
+    while let Some(index) = pending.pop() {
+        if index >= end {
+            return true;
+        }
+        if visited[index - start] {
+            continue;
+        }
+        visited[index - start] = true;
+        match &instructions[index] {
+            Instruction::Return | Instruction::ThrowFrom(..) => {}
+            Instruction::JumpUnconditional(_) => pending.extend(targets[index]),
+            Instruction::JumpConditional(..) | Instruction::Try(..) => {
+                pending.push(index + 1);
+                pending.extend(targets[index]);
+            }
+            _ => pending.push(index + 1),
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::value::{IntoValue, MiType};
+    use VerifyErrorKind::*;
+
+    fn errors(instructions: &[Instruction]) -> Vec<(usize, VerifyErrorKind)> {
+        match verify(instructions) {
+            Ok(()) => Vec::new(),
+            Err(errors) => errors.into_iter().map(|error| (error.index, error.kind)).collect(),
+        }
+    }
+
+    fn function(name: &str) -> Instruction {
+        Instruction::DefineFnLabel(name.to_string(), Vec::new(), MiType::Int)
+    }
+
+    fn label(name: &str) -> Instruction {
+        Instruction::DefineLabel(name.to_string())
+    }
+
+    fn jump(name: &str) -> Instruction {
+        Instruction::JumpUnconditional(name.to_string())
+    }
+
+    #[test]
+    fn accepts_well_formed_programs() {
+        let instructions = [
+            function("f"),
+            Instruction::Move(0, true.into_value()),
+            Instruction::JumpConditional(0, "done".to_string()),
+            Instruction::Move(15, 1.into_value()),
+            label("done"),
+            Instruction::Return,
+            Instruction::EndFunction,
+            Instruction::Call("f".to_string()),
+        ];
+        assert_eq!(errors(&instructions), Vec::new());
+    }
+
+    #[test]
+    fn rejects_invalid_registers_and_labels() {
+        let instructions = [
+            Instruction::Move(16, 1.into_value()),
+            jump("missing"),
+            label("twice"),
+            label("twice"),
+        ];
+        assert_eq!(errors(&instructions), vec![
+            (0, InvalidRegister(16)),
+            (1, UndefinedLabel("missing".to_string())),
+            (3, DuplicateLabel("twice".to_string(), 2)),
+        ]);
+    }
+
+    #[test]
+    fn rejects_unbalanced_definitions() {
+        let instructions = [
+            function("f"),
+            function("g"),
+            Instruction::Return,
+            Instruction::EndFunction,
+            Instruction::EndFunction,
+            Instruction::EndClass,
+            Instruction::ClassField("x".to_string(), MiType::Int),
+            Instruction::DefineClass("Point".to_string()),
+            Instruction::DefineClass("Inner".to_string()),
+            function("h"),
+        ];
+        assert_eq!(errors(&instructions), vec![
+            (1, NestedFunction("g".to_string())),
+            (4, UnmatchedEndFunction),
+            (5, UnmatchedEndClass),
+            (6, FieldOutsideClass("x".to_string())),
+            (7, UnclosedClass("Point".to_string())),
+            (8, NestedClass("Inner".to_string())),
+            (9, UnclosedFunction("h".to_string())),
+        ]);
+    }
+
+    #[test]
+    fn rejects_duplicate_functions_but_not_methods_of_other_classes() {
+        let method = |class: &str| [
+            Instruction::DefineClass(class.to_string()),
+            function("norm"),
+            Instruction::Return,
+            Instruction::EndFunction,
+            Instruction::EndClass,
+        ];
+        let mut instructions = Vec::new();
+        instructions.extend(method("A"));
+        instructions.extend(method("B"));
+        instructions.extend([function("f"), Instruction::Return, Instruction::EndFunction]);
+        instructions.extend([function("f"), Instruction::Return, Instruction::EndFunction]);
+        assert_eq!(errors(&instructions), vec![(13, DuplicateFunction("f".to_string(), 10))]);
+    }
+
+    #[test]
+    fn rejects_jumps_across_functions() {
+        let instructions = [
+            label("outside"),
+            function("f"),
+            jump("outside"),
+            label("inside"),
+            Instruction::Return,
+            Instruction::EndFunction,
+            jump("inside"),
+        ];
+        assert_eq!(errors(&instructions), vec![
+            (2, JumpAcrossFunction("outside".to_string())),
+            (6, JumpAcrossFunction("inside".to_string())),
+        ]);
+    }
+
+    #[test]
+    fn rejects_functions_that_can_end_without_returning() {
+        let instructions = [
+            function("f"),
+            Instruction::Move(0, true.into_value()),
+            Instruction::JumpConditional(0, "done".to_string()),
+            Instruction::Return,
+            label("done"),
+            Instruction::EndFunction,
+            // a throw ends the path just like a return
+            function("g"),
+            Instruction::ThrowFrom(0, 0),
+            Instruction::EndFunction,
+        ];
+        assert_eq!(errors(&instructions), vec![(0, MissingReturn("f".to_string()))]);
+    }
+}
@@ -0,0 +1,128 @@
+use std::time::SystemTime;
+
+use mirage::assembly::assemble;
+use mirage::assembly::disasm::{disassemble, disassemble_metadata};
+use mirage::meta::Metadata;
+
+/// Uses every instruction at least once, along with literals that need escaping
+const SOURCE: &str = r#"
+-- every instruction of the assembler
+definefnlabel add_all 2 a b int
+    moveargument "a" r0
+    moveargument "b" r1
+    add r0 r1 r15
+    return
+endfunction
+defineclass Point
+    field x int
+    field y float
+    definefnlabel norm 0 float
+        moveargument "self" r0
+        getfield r0 x r1
+        setfield r0 y r1
+        return
+    endfunction
+endclass
+move r0 int 1
+move r1 string "quote \" backslash \\ newline \n tab \t cr \r nul \0 unicode ä"
+move r2 bool true
+move r3 None
+move r4 array [int 1, string "two", array [bool false], None]
+move r5 array []
+move r6 int -5
+move r7 float -2.5e-3
+move r8 float 6.02E23
+move r9 array [int 0xFF,int -0b1010 , int 0o17, int 1_000_000, float 1.5]
+move r10 int -9223372036854775808
+movebetween r0 r6
+moveasargument r0
+sub r0 r1 r2
+mul r0 r1 r2
+div r0 r1 r2
+rem r0 r1 r2
+pow r0 r1 r2
+or r0 r1 r2
+xor r0 r1 r2
+and r0 r1 r2
+not r0 r1
+lt r0 r1 r2
+le r0 r1 r2
+gt r0 r1 r2
+ge r0 r1 r2
+eq r0 r1 r2
+ne r0 r1 r2
+setvariable r0 counter
+movfromvariable counter r1
+definelabel start
+jumpunc start
+jumpc r2 start
+call add_all
+stdoutwrite r0
+stdoutwritedebugged r0
+stdoutflush
+stderrwrite r0
+stderrwritedebugged r0
+stderrflush
+bufferedstdinread r0
+try handler r7 r8
+throwfrom r7 r8
+endtry
+definelabel handler
+arraynew r0
+arraypush r0 r1
+arraypop r0 r1
+arrayget r0 r1 r2
+arrayset r0 r1 r2
+arraylen r0 r1
+arrayslice r0 r1 r2 r3
+mapnew r0
+mapinsert r0 r1 r2
+mapget r0 r1 r2
+mapremove r0 r1 r2
+mapcontains r0 r1 r2
+mapkeys r0 r1
+maplen r0 r1
+new Point r0
+callmethod r0 norm
+cast r0 string r1
+typeof r0 r1
+"#;
+
+#[test]
+fn disassembly_reassembles_to_the_same_instructions() {
+    let instructions = assemble(SOURCE, "all.masm").unwrap();
+    let disassembled = disassemble(&instructions).unwrap();
+    let reassembled = assemble(&disassembled, "all.disasm.masm").unwrap();
+    assert_eq!(instructions, reassembled);
+    // the output is stable, so disassembling again gives the same text
+    assert_eq!(disassembled, disassemble(&reassembled).unwrap());
+}
+
+#[test]
+fn disassembled_binary_reassembles_to_an_identical_binary() {
+    let instructions = assemble(SOURCE, "all.masm").unwrap();
+    let metadata = Metadata {
+        package: "roundtrip".to_string(),
+        version: Some("1.0.0".to_string()),
+        timestamp: SystemTime::now(),
+        author: Some("mirage".to_string()),
+        total_instructions: instructions.len(),
+        instructions,
+        debug_info: None,
+        description: String::new(),
+        license: Some("MIT".to_string()),
+        compiled_version: mirage::MIRAGE_VERSION.to_string(),
+    };
+    let bytes = metadata.to_bytes().unwrap();
+
+    let disassembled = disassemble_metadata(&Metadata::from_bytes(&bytes).unwrap()).unwrap();
+    assert!(disassembled.contains("-- package: roundtrip"));
+    assert!(disassembled.contains("-- license: MIT"));
+    assert!(disassembled.contains(&format!("-- compiled version: {}", mirage::MIRAGE_VERSION)));
+
+    let rebuilt = Metadata {
+        instructions: assemble(&disassembled, "roundtrip.masm").unwrap(),
+        ..metadata
+    };
+    assert_eq!(bytes, rebuilt.to_bytes().unwrap());
+}